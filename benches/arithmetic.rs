@@ -0,0 +1,45 @@
+#[allow(deprecated)]
+use ssl::execute::execute;
+use ssl::{optimize::resolve_builtins, parser::parse, ExecuteOptions, FunctionDescriptor};
+
+use std::time::{Duration, Instant};
+
+const N: usize = 20000;
+const ITERATIONS: u32 = 5;
+const BATCHES: usize = 10;
+
+const SCRIPT: &str = r"
+    0 'sum' := 0 'i' :=
+    $0 i < while
+        3 i i * mod sum + 'sum' :=
+        i 1 + 'i' :=
+        $0 i <
+    end
+    sum
+";
+
+/// The best and median time of a run
+#[allow(deprecated)]
+fn time(code: &FunctionDescriptor) -> (Duration, Duration) {
+    let mut timings: Vec<_> = (0..BATCHES)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                execute(code, vec![(N as f64).into()]).expect("benchmark script runs");
+            }
+            start.elapsed() / ITERATIONS
+        })
+        .collect();
+    timings.sort();
+    (timings[0], timings[BATCHES / 2])
+}
+
+fn main() {
+    let code = parse(SCRIPT.chars()).expect("benchmark script parses");
+    let resolved = resolve_builtins(code.clone(), &ExecuteOptions::default());
+
+    for (name, code) in [("looked up", &code), ("resolved", &resolved)] {
+        let (best, median) = time(code);
+        println!("arithmetic ({N}, builtins {name}): best {best:?}/run, median {median:?}/run");
+    }
+}
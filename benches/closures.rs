@@ -0,0 +1,44 @@
+#[allow(deprecated)]
+use ssl::execute::execute;
+use ssl::parser::parse;
+
+use std::time::Instant;
+
+const DEPTH: usize = 500;
+const ITERATIONS: u32 = 50;
+const BATCHES: usize = 20;
+
+const SCRIPT: &str = r"
+    fn
+        $0 0 < if
+            1 'a' := 2 'b' := 3 'c' := 4 'd' := 5 'e' := 6 'h' := 7 'i' := 8 'j' :=
+            fn a b + c + d + e + h + i + j + $0 + end ^ 'f' :=
+            fn $0 f $0 * end ^ 'g' :=
+            $0 g 1 g + 2 g + 3 g + 'r' :=
+            1 $0 - countdown
+        end
+    end 'countdown' :=
+    $0 countdown
+";
+
+#[allow(deprecated)]
+fn main() {
+    let code = parse(SCRIPT.chars()).expect("benchmark script parses");
+
+    let mut timings: Vec<_> = (0..BATCHES)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                execute(&code, vec![(DEPTH as f64).into()]).expect("benchmark script runs");
+            }
+            start.elapsed() / ITERATIONS
+        })
+        .collect();
+    timings.sort();
+
+    println!(
+        "closures (depth {DEPTH}): best {:?}/run, median {:?}/run",
+        timings[0],
+        timings[BATCHES / 2]
+    );
+}
@@ -0,0 +1,42 @@
+#[allow(deprecated)]
+use ssl::execute::execute;
+use ssl::parser::parse;
+
+use std::time::Instant;
+
+const N: usize = 20;
+const ITERATIONS: u32 = 5;
+const BATCHES: usize = 10;
+
+const SCRIPT: &str = r"
+    fn
+        2 $0 < if
+            $0
+        else
+            1 $0 - fib 2 $0 - fib +
+        end
+    end 'fib' :=
+    $0 fib
+";
+
+#[allow(deprecated)]
+fn main() {
+    let code = parse(SCRIPT.chars()).expect("benchmark script parses");
+
+    let mut timings: Vec<_> = (0..BATCHES)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                execute(&code, vec![(N as f64).into()]).expect("benchmark script runs");
+            }
+            start.elapsed() / ITERATIONS
+        })
+        .collect();
+    timings.sort();
+
+    println!(
+        "fib ({N}): best {:?}/run, median {:?}/run",
+        timings[0],
+        timings[BATCHES / 2]
+    );
+}
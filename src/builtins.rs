@@ -1,94 +1,491 @@
 use crate::{
-    callable::*, execute::ExecuteError, machine_state::MachineState, pop_as, FlyString, Value,
+    callable::*,
+    execute::ExecuteError,
+    machine_state::{ArithmeticMode, MachineState},
+    pop_as,
+    value::lock,
+    FlyString, Value,
 };
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
+
+use once_cell::sync::Lazy;
+
+/// ( pops -- pushes ) of builtins that always take and leave the same number of values
+pub(crate) const STACK_EFFECTS: &[(&str, usize, usize)] = &[
+    ("+", 2, 1),
+    ("-", 2, 1),
+    ("*", 2, 1),
+    ("/", 2, 1),
+    ("<", 2, 1),
+    (">", 2, 1),
+    ("<=", 2, 1),
+    (">=", 2, 1),
+    ("=", 2, 1),
+    ("!=", 2, 1),
+    ("and", 2, 1),
+    ("or", 2, 1),
+    ("not", 1, 1),
+    ("is-nil", 1, 1),
+    ("dup", 1, 2),
+    ("drop", 1, 0),
+    ("swap", 2, 2),
+    ("over", 2, 3),
+    ("rot", 3, 3),
+    ("depth", 0, 1),
+    ("typeof", 1, 2),
+    ("to-string", 1, 1),
+    ("to-number", 1, 1),
+    ("to-bool", 1, 1),
+    (":=", 2, 0),
+    (":=global", 2, 0),
+    ("!", 2, 0),
+    ("^", 1, 1),
+    ("^&", 1, 1),
+    ("compose", 2, 1),
+    ("curry", 1, 1),
+    ("push", 2, 1),
+    ("get", 2, 1),
+    ("set", 3, 1),
+    ("len", 1, 1),
+    ("mod", 2, 1),
+    ("pow", 2, 1),
+    ("min", 2, 1),
+    ("max", 2, 1),
+    ("sqrt", 1, 1),
+    ("floor", 1, 1),
+    ("ceil", 1, 1),
+    ("abs", 1, 1),
+    ("to-int", 1, 1),
+    ("to-float", 1, 1),
+    ("band", 2, 1),
+    ("bor", 2, 1),
+    ("bxor", 2, 1),
+    ("bnot", 1, 1),
+    ("shl", 2, 1),
+    ("shr", 2, 1),
+    ("rand", 0, 1),
+    ("rand-int", 2, 1),
+    ("collect-garbage", 0, 1),
+    ("perform", 2, 1),
+];
+
+/// A numeric operand: ints stay ints, anything involving a float is computed in floating point
+#[derive(Clone, Copy)]
+enum Num {
+    Int(i64),
+    Float(f64),
+}
+
+impl Num {
+    fn as_float(self) -> f64 {
+        match self {
+            Num::Int(x) => x as f64,
+            Num::Float(x) => x,
+        }
+    }
+}
+
+impl From<Num> for Value {
+    fn from(value: Num) -> Self {
+        match value {
+            Num::Int(x) => Value::Int(x),
+            Num::Float(x) => Value::Number(x),
+        }
+    }
+}
+
+fn to_num(value: &Value) -> Result<Num, ExecuteError> {
+    match value {
+        Value::Int(x) => Ok(Num::Int(*x)),
+        Value::Number(x) => Ok(Num::Float(*x)),
+        _ => Err(ExecuteError::TypeMismatch {
+            expected: "Number".into(),
+            actual: value.type_name(),
+        }),
+    }
+}
+
+fn pop_num(state: &mut MachineState) -> Result<Num, ExecuteError> {
+    to_num(&state.pop()?)
+}
+
+pub(crate) fn to_text(value: &Value) -> Result<&str, ExecuteError> {
+    value.as_str().ok_or_else(|| ExecuteError::TypeMismatch {
+        expected: "String".into(),
+        actual: value.type_name(),
+    })
+}
+
+/// Pop a string naming something, interning it if it was built at runtime. Names are few, text is not
+fn pop_name(state: &mut MachineState) -> Result<FlyString, ExecuteError> {
+    match state.pop()? {
+        Value::String(name) => Ok(name),
+        value => Ok(to_text(&value)?.into()),
+    }
+}
+
+/// Reject a NaN result under checked arithmetic
+fn float_result(state: &MachineState, x: f64) -> Result<Num, ExecuteError> {
+    if x.is_nan() && state.arithmetic() == ArithmeticMode::Checked {
+        return Err(ExecuteError::NanResult);
+    }
+    Ok(Num::Float(x))
+}
+
+/// Reject a zero divisor under checked arithmetic
+fn check_divisor(state: &MachineState, divisor: f64) -> Result<(), ExecuteError> {
+    if divisor == 0.0 && state.arithmetic() == ArithmeticMode::Checked {
+        return Err(ExecuteError::DivisionByZero);
+    }
+    Ok(())
+}
+
+/// Convert a count or index to usize, rejecting negative and fractional values instead of truncating them
+fn to_usize(value: Num) -> Option<usize> {
+    match value {
+        Num::Int(x) => usize::try_from(x).ok(),
+        Num::Float(x) if x >= 0.0 && x.fract() == 0.0 && x <= (1u64 << 53) as f64 => {
+            Some(x as usize)
+        }
+        Num::Float(_) => None,
+    }
+}
 
 macro_rules! numeric_biop_impl {
-    ($name:ident, $op:tt, $output:ident) => {
+    ($name:ident, $op:tt, $checked:ident) => {
+        fn $name(state: &mut MachineState) -> Result<(), ExecuteError> {
+            let result = match (pop_num(state)?, pop_num(state)?) {
+                (Num::Int(a), Num::Int(b)) => {
+                    Num::Int(a.$checked(b).ok_or(ExecuteError::IntegerOverflow)?)
+                }
+                (a, b) => float_result(state, a.as_float() $op b.as_float())?,
+            };
+            state.push(result.into());
+            Ok(())
+        }
+    };
+}
+
+/// Numbers compare by value, strings lexicographically, as the `PartialOrd` of [`Value`] does
+macro_rules! comparison_impl {
+    ($name:ident, $op:tt) => {
         fn $name(state: &mut MachineState) -> Result<(), ExecuteError> {
-            use Value as V;
-            let a = pop_as!(state, Number);
-            let b = pop_as!(state, Number);
-            state.push(V::$output(a $op b));
+            let a = state.pop()?;
+            let b = state.pop()?;
+            if a.as_str().is_none() || b.as_str().is_none() {
+                to_num(&a)?;
+                to_num(&b)?;
+            }
+            state.push(Value::Bool(a $op b));
             Ok(())
         }
     };
 }
 
-numeric_biop_impl!(add, +, Number);
-numeric_biop_impl!(sub, -, Number);
-numeric_biop_impl!(mul, *, Number);
-numeric_biop_impl!(div, /, Number);
+mod bytes;
+mod channel;
+mod coroutine;
+#[cfg(feature = "host")]
+mod fs;
+mod io;
+mod list;
+mod math;
+#[cfg(feature = "host")]
+mod process;
+mod string;
+#[cfg(feature = "host")]
+mod system;
+mod thread;
 
-numeric_biop_impl!(lt, <, Bool);
+numeric_biop_impl!(add, +, checked_add);
+numeric_biop_impl!(sub, -, checked_sub);
+numeric_biop_impl!(mul, *, checked_mul);
 
-fn print_function(f: &FunctionDescriptor) {
-    if f.captured_names.is_empty() {
-        print!("<function");
-        return;
-    }
-    print!("<closure: ");
-    for (i, (name, _)) in f.captured_names.iter().enumerate() {
-        if i != 0 {
-            print!(", ");
+fn div(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = pop_num(state)?.as_float();
+    let b = pop_num(state)?.as_float();
+    check_divisor(state, b)?;
+    let result = float_result(state, a / b)?;
+    state.push(result.into());
+    Ok(())
+}
+
+comparison_impl!(lt, <);
+comparison_impl!(gt, >);
+comparison_impl!(le, <=);
+comparison_impl!(ge, >=);
+
+macro_rules! logic_biop_impl {
+    ($name:ident, $op:tt) => {
+        fn $name(state: &mut MachineState) -> Result<(), ExecuteError> {
+            let a = pop_as!(state, Bool);
+            let b = pop_as!(state, Bool);
+            state.push(Value::Bool(a $op b));
+            Ok(())
         }
-        print!("{name}");
-    }
+    };
 }
 
-fn print_callable(f: &Callable) {
-    match &f.kind {
-        CallableKind::Builtin(_) => print!("<builtin"),
-        CallableKind::Function(f) => print_function(&f),
-    }
-    if f.bound_arguments.is_empty() {
-        println!(">");
-        return;
+logic_biop_impl!(and, &&);
+logic_biop_impl!(or, ||);
+
+fn not(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = pop_as!(state, Bool);
+    state.push(Value::Bool(!a));
+    Ok(())
+}
+
+fn equal(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = state.pop()?;
+    let b = state.pop()?;
+    state.push(Value::Bool(a == b));
+    Ok(())
+}
+
+fn not_equal(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = state.pop()?;
+    let b = state.pop()?;
+    state.push(Value::Bool(a != b));
+    Ok(())
+}
+
+fn is_nil(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    state.push(Value::Bool(matches!(value, Value::Nil)));
+    Ok(())
+}
+
+/// ( a -- a a )
+fn dup(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = state.pop()?;
+    state.push(a.clone());
+    state.push(a);
+    Ok(())
+}
+
+/// ( a -- )
+fn drop(state: &mut MachineState) -> Result<(), ExecuteError> {
+    state.pop()?;
+    Ok(())
+}
+
+/// ( a b -- b a )
+fn swap(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let b = state.pop()?;
+    let a = state.pop()?;
+    state.push(b);
+    state.push(a);
+    Ok(())
+}
+
+/// ( a b -- a b a )
+fn over(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let b = state.pop()?;
+    let a = state.pop()?;
+    state.push(a.clone());
+    state.push(b);
+    state.push(a);
+    Ok(())
+}
+
+/// ( a b c -- b c a )
+fn rot(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let c = state.pop()?;
+    let b = state.pop()?;
+    let a = state.pop()?;
+    state.push(b);
+    state.push(c);
+    state.push(a);
+    Ok(())
+}
+
+fn depth(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let depth = i64::try_from(state.stack_len()).map_err(|_| ExecuteError::IntegerOverflow)?;
+    state.push(Value::Int(depth));
+    Ok(())
+}
+
+fn clear_stack(state: &mut MachineState) -> Result<(), ExecuteError> {
+    state.clear_stack();
+    Ok(())
+}
+
+fn to_string(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    state.push_new(value.to_string().into());
+    Ok(())
+}
+
+/// An int if the text is one, else a float. Surrounding whitespace is ignored
+fn parse_number(text: &str) -> Result<Value, ExecuteError> {
+    let text = text.trim();
+    match text.parse::<i64>() {
+        Ok(x) => Ok(Value::Int(x)),
+        Err(_) => text
+            .parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| ExecuteError::NotANumber(text.into())),
     }
+}
 
-    print!(", bound arguments: ");
-    for (i, val) in f.bound_arguments.iter().enumerate() {
-        if i != 0 {
-            print!(", ");
+fn parse_number_builtin(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let text = state.pop()?;
+    state.push(parse_number(to_text(&text)?)?);
+    Ok(())
+}
+
+/// Numbers stay as they are, strings are parsed and bools become 1 or 0
+fn to_number(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = match state.pop()? {
+        value @ (Value::Int(_) | Value::Number(_)) => value,
+        value @ (Value::String(_) | Value::OwnedString(_)) => parse_number(to_text(&value)?)?,
+        Value::Bool(b) => Value::Int(b.into()),
+        value => {
+            return Err(ExecuteError::TypeMismatch {
+                expected: "Number, String or Bool".into(),
+                actual: value.type_name(),
+            })
         }
-        print!("${i}: {val:?}");
+    };
+    state.push(value);
+    Ok(())
+}
+
+/// `false`, `nil`, zero, NaN, the empty string and the empty list are false; everything else is true
+fn to_bool(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let truthy = match state.pop()? {
+        Value::Nil => false,
+        Value::Bool(b) => b,
+        Value::Int(x) => x != 0,
+        Value::Number(x) => x != 0.0 && !x.is_nan(),
+        Value::String(s) => !s.as_str().is_empty(),
+        Value::OwnedString(s) => !s.is_empty(),
+        Value::List(list) => !list.is_empty(),
+        Value::Bytes(bytes) => !bytes.is_empty(),
+        Value::Function(_) | Value::Error(_) | Value::Shared(_) | Value::Channel(_) => true,
+    };
+    state.push(Value::Bool(truthy));
+    Ok(())
+}
+
+/// Raise an error with the given message, or re-raise a caught one
+fn throw(state: &mut MachineState) -> Result<(), ExecuteError> {
+    match state.pop()? {
+        Value::String(message) => Err(ExecuteError::Thrown(message.as_str().into())),
+        Value::OwnedString(message) | Value::Error(message) => Err(ExecuteError::Thrown(message)),
+        value => Err(ExecuteError::TypeMismatch {
+            expected: "String".into(),
+            actual: value.type_name(),
+        }),
     }
-    println!(">");
 }
 
-fn print(state: &mut MachineState) -> Result<(), ExecuteError> {
-    use Value as V;
-    match state.pop() {
-        Ok(V::Bool(b)) => println!("{b}"),
-        Ok(V::Number(x)) => println!("{x}"),
-        Ok(V::String(s)) => println!("{s}"),
-        Ok(V::Function(ref f)) => print_callable(f),
-        Err(_) => println!("<empty>"),
+/// `condition assert` or `condition message assert`
+fn assert(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let message = match state.pop()? {
+        Value::Bool(condition) => {
+            state.push(Value::Bool(condition));
+            None
+        }
+        message => Some(to_text(&message)?.into()),
+    };
+    match (pop_as!(state, Bool), message) {
+        (true, _) => Ok(()),
+        (false, None) => Err(ExecuteError::AssertionFailed),
+        (false, Some(message)) => Err(ExecuteError::AssertionFailedWith(message)),
     }
+}
+
+/// End the program with an exit code for the host. `try` can't stop it
+fn exit(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let code = pop_as!(state, Int);
+    let code = i32::try_from(code).map_err(|_| ExecuteError::IntegerOverflow)?;
+    Err(ExecuteError::Exit(code))
+}
+
+fn error_message(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let message = pop_as!(state, Error);
+    state.push_new(Value::OwnedString(message));
     Ok(())
 }
 
+/// Assigning to a variable shared with `^&` updates it for everyone sharing it
 fn assign(state: &mut MachineState) -> Result<(), ExecuteError> {
-    let name = pop_as!(state, String);
+    let name = pop_name(state)?;
+    let value = state.pop()?;
+
+    match state.look_up(&name) {
+        Some(Value::Shared(cell)) => *lock(cell) = value,
+        _ => state.current_scope_mut().set(name, value),
+    }
+
+    Ok(())
+}
+
+/// Like `:=`, but visible everywhere once defined, even when run inside a function
+fn assign_global(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let name = pop_name(state)?;
     let value = state.pop()?;
 
-    state.current_scope_mut().set(name, value);
+    match state.global_scope().get(&name) {
+        Some(Value::Shared(cell)) => *lock(cell) = value,
+        _ => state.global_scope_mut().set(name, value),
+    }
 
     Ok(())
 }
 
+/// ( value effect -- result ) Suspend the script and hand `value` to the handler of `effect`: the builtin of that
+/// name the host registered, e.g. with [`crate::ExecuteOptions::with_builtin`]. The script resumes with what the
+/// handler leaves on top of the stack. Functions the script defined can't handle effects
+fn perform(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let effect = pop_name(state)?;
+    let handler = match state.global_scope().get(&effect).map(Value::current) {
+        Some(Value::Function(
+            handler @ Callable {
+                kind: CallableKind::Builtin(_),
+                ..
+            },
+        )) => handler,
+        _ => return Err(ExecuteError::UnhandledEffect(effect)),
+    };
+    handler.execute(state)
+}
+
 fn assert_type(state: &mut MachineState) -> Result<(), ExecuteError> {
-    let type_name = pop_as!(state, String);
+    let type_name = pop_name(state)?;
     let value = state.pop()?;
 
     if type_name != value.type_name() {
-        return Err(ExecuteError::InvalidType(value.type_name(), type_name));
+        return Err(ExecuteError::InvalidType {
+            expected: type_name,
+            actual: value.type_name(),
+        });
     }
     Ok(())
 }
 
+/// ( a -- a type ) Unlike `!`, keeps the value and never fails on it
+fn type_of(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let type_name = state
+        .peek_result()
+        .ok_or(ExecuteError::EmptyStack)?
+        .type_name();
+    state.push(type_name.into());
+    Ok(())
+}
+
 fn make_closure(state: &mut MachineState) -> Result<(), ExecuteError> {
+    closure(state, false)
+}
+
+/// Like `^`, but the closure shares the captured variables with the scope it was made in
+fn make_shared_closure(state: &mut MachineState) -> Result<(), ExecuteError> {
+    closure(state, true)
+}
+
+fn closure(state: &mut MachineState, shared: bool) -> Result<(), ExecuteError> {
     let Callable {
         kind,
         bound_arguments,
@@ -97,18 +494,28 @@ fn make_closure(state: &mut MachineState) -> Result<(), ExecuteError> {
     let f = match &kind {
         CallableKind::Function(f) => f,
         CallableKind::Builtin(_) => {
-            return Err(ExecuteError::InvalidType("builtin", "function".into()))
+            return Err(ExecuteError::InvalidType {
+                expected: "function".into(),
+                actual: "builtin",
+            })
         }
     };
 
-    let captured_names = state.current_scope().names().clone();
-    let closure = FunctionDescriptor {
-        captured_names,
-        num_args: f.num_args,
-        operations: f.operations.clone(),
+    let captured_names = if shared {
+        let captured_names = state.current_scope_mut().share(&f.referenced_names);
+        for value in captured_names.values() {
+            if let Value::Shared(cell) = value {
+                state.track_cell(cell);
+            }
+        }
+        captured_names
+    } else {
+        state.current_scope().capture(&f.referenced_names)
     };
+    state.record_captures(captured_names.len());
+    let closure = f.with_captures(captured_names);
 
-    state.push(Value::Function(Callable {
+    state.push_new(Value::Function(Callable {
         kind: CallableKind::Function(closure.into()),
         bound_arguments,
     }));
@@ -116,9 +523,20 @@ fn make_closure(state: &mut MachineState) -> Result<(), ExecuteError> {
     Ok(())
 }
 
+/// ( -- freed ) Free variables shared with `^&` that only cycles keep alive, see [`MachineState::collect_garbage`]
+fn collect_garbage(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let freed = state.collect_garbage();
+    state.push(Value::Int(freed as i64));
+    Ok(())
+}
+
 fn bind(state: &mut MachineState) -> Result<(), ExecuteError> {
     let f = pop_as!(state, Function);
-    let num_to_bind = pop_as!(state, Number) as usize;
+    let count = pop_num(state)?;
+    let num_to_bind = to_usize(count).ok_or_else(|| ExecuteError::TypeMismatch {
+        expected: "non-negative integer".into(),
+        actual: Value::from(count).type_name(),
+    })?;
 
     if let CallableKind::Function(ref f) = f.kind {
         if f.num_args < num_to_bind {
@@ -130,7 +548,7 @@ fn bind(state: &mut MachineState) -> Result<(), ExecuteError> {
         .map(|_| state.pop())
         .collect::<Result<_, _>>()?;
 
-    state.push(Value::Function(Callable {
+    state.push_new(Value::Function(Callable {
         bound_arguments,
         ..f
     }));
@@ -138,17 +556,217 @@ fn bind(state: &mut MachineState) -> Result<(), ExecuteError> {
     Ok(())
 }
 
+/// `f g compose` makes a function that runs `g`, then `f` on what `g` left
+fn compose(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let g = pop_as!(state, Function);
+    let f = pop_as!(state, Function);
+    state.push_new(Value::builtin(move |state| {
+        g.execute(state)?;
+        f.execute(state)
+    }));
+    Ok(())
+}
+
+/// Make a function that takes the arguments of `f` one call at a time, binding each like `bind` would,
+/// and runs `f` once the last one is given
+fn curry(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    state.push_new(curried(f)?);
+    Ok(())
+}
+
+fn curried(f: Callable) -> Result<Value, ExecuteError> {
+    let remaining = match &f.kind {
+        CallableKind::Function(descriptor) if !descriptor.variadic => descriptor
+            .num_args
+            .checked_sub(f.bound_arguments.len())
+            .ok_or(ExecuteError::ArityMismatch {
+                expected: descriptor.num_args,
+                got: f.bound_arguments.len(),
+            })?,
+        kind => {
+            return Err(ExecuteError::TypeMismatch {
+                expected: "function with a fixed number of arguments".into(),
+                actual: match kind {
+                    CallableKind::Function(_) => "variadic function",
+                    CallableKind::Builtin(_) => "builtin",
+                },
+            })
+        }
+    };
+    if remaining == 0 {
+        return Ok(Value::Function(f));
+    }
+    Ok(Value::builtin(move |state| {
+        let arg = state.pop()?;
+        let bound = Callable {
+            bound_arguments: f.bound_arguments.iter().cloned().chain([arg]).collect(),
+            ..f.clone()
+        };
+        if remaining == 1 {
+            bound.execute(state)
+        } else {
+            state.push_new(curried(bound)?);
+            Ok(())
+        }
+    }))
+}
+
+/// Run the function on top of the stack, as naming it would
+fn call(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    f.execute(state)
+}
+
+/// `args f apply` runs `f` with the items of the list `args` as its arguments. A variadic function gets
+/// the items past its numbered arguments as `$*`, any other function fails on more items than it takes
+fn apply(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let args = pop_as!(state, List);
+    let got = args.len() + f.bound_arguments.len();
+    if let CallableKind::Function(descriptor) = &f.kind {
+        if !descriptor.variadic && got > descriptor.num_args {
+            return Err(ExecuteError::ArityMismatch {
+                expected: descriptor.num_args,
+                got,
+            });
+        }
+    }
+    args.iter().cloned().for_each(|arg| state.push(arg));
+    if let CallableKind::Function(descriptor) = &f.kind {
+        if descriptor.variadic {
+            let extra = got.saturating_sub(descriptor.num_args);
+            state.push(Value::Int(extra as i64));
+        }
+    }
+    f.execute(state)
+}
+
+/// The core builtins, made once so every machine gets the same functions and replacements can be told apart
+static BUILTINS: Lazy<HashMap<FlyString, Value>> = Lazy::new(make_builtins);
+
 pub fn get_builtins() -> HashMap<FlyString, Value> {
-    HashMap::from([
+    BUILTINS.clone()
+}
+
+/// Whether `f` is the core builtin called `name`, rather than something a host or program put in its place
+pub(crate) fn is_core_builtin(name: &FlyString, f: &Callable) -> bool {
+    matches!(BUILTINS.get(name), Some(Value::Function(core)) if core == f)
+}
+
+fn make_builtins() -> HashMap<FlyString, Value> {
+    let mut builtins = HashMap::from([
         ("+".into(), Value::builtin(add)),
         ("-".into(), Value::builtin(sub)),
         ("*".into(), Value::builtin(mul)),
         ("/".into(), Value::builtin(div)),
         ("<".into(), Value::builtin(lt)),
-        (".".into(), Value::builtin(print)),
+        (">".into(), Value::builtin(gt)),
+        ("<=".into(), Value::builtin(le)),
+        (">=".into(), Value::builtin(ge)),
+        ("=".into(), Value::builtin(equal)),
+        ("!=".into(), Value::builtin(not_equal)),
+        ("is-nil".into(), Value::builtin(is_nil)),
+        ("dup".into(), Value::builtin(dup)),
+        ("drop".into(), Value::builtin(drop)),
+        ("swap".into(), Value::builtin(swap)),
+        ("over".into(), Value::builtin(over)),
+        ("rot".into(), Value::builtin(rot)),
+        ("depth".into(), Value::builtin(depth)),
+        ("clear-stack".into(), Value::builtin(clear_stack)),
+        ("and".into(), Value::builtin(and)),
+        ("or".into(), Value::builtin(or)),
+        ("not".into(), Value::builtin(not)),
+        ("to-string".into(), Value::builtin(to_string)),
+        ("to-number".into(), Value::builtin(to_number)),
+        ("to-bool".into(), Value::builtin(to_bool)),
+        ("parse-number".into(), Value::builtin(parse_number_builtin)),
         (":=".into(), Value::builtin(assign)),
+        (":=global".into(), Value::builtin(assign_global)),
         ("!".into(), Value::builtin(assert_type)),
+        ("typeof".into(), Value::builtin(type_of)),
         ("^".into(), Value::builtin(make_closure)),
+        ("^&".into(), Value::builtin(make_shared_closure)),
+        ("collect-garbage".into(), Value::builtin(collect_garbage)),
         ("bind".into(), Value::builtin(bind)),
-    ])
+        ("call".into(), Value::builtin(call)),
+        ("apply".into(), Value::builtin(apply)),
+        ("compose".into(), Value::builtin(compose)),
+        ("curry".into(), Value::builtin(curry)),
+        ("throw".into(), Value::builtin(throw)),
+        ("error-message".into(), Value::builtin(error_message)),
+        ("assert".into(), Value::builtin(assert)),
+        ("exit".into(), Value::builtin(exit)),
+        ("perform".into(), Value::builtin(perform)),
+    ]);
+    builtins.extend(bytes::get_builtins());
+    builtins.extend(coroutine::get_builtins());
+    builtins.extend(list::get_builtins());
+    builtins.extend(math::get_builtins());
+    builtins.extend(string::get_builtins());
+    check_arities(&mut builtins);
+    builtins
+}
+
+/// Make the builtins with a fixed stack effect fail with [`ExecuteError::ArityMismatch`] when called on too
+/// few values, rather than with whatever error their first missing operand causes
+fn check_arities(builtins: &mut HashMap<FlyString, Value>) {
+    for &(name, pops, _) in STACK_EFFECTS {
+        let name = FlyString::from(name);
+        let Some(Value::Function(Callable {
+            kind: CallableKind::Builtin(f),
+            ..
+        })) = builtins.get(&name)
+        else {
+            continue;
+        };
+        if pops > 0 {
+            let f = Arc::clone(f);
+            builtins.insert(name, Value::builtin_with_arity(pops, move |state| f(state)));
+        }
+    }
+}
+
+/// Builtins that print to the program's output and read its input
+pub fn get_stdio_builtins() -> [(FlyString, Value); 3] {
+    io::get_builtins()
+}
+
+/// Builtins that start threads and pass values between them
+pub fn get_thread_builtins() -> Vec<(FlyString, Value)> {
+    [channel::get_builtins().as_slice(), &thread::get_builtins()].concat()
+}
+
+/// Every builtin that reaches the host beyond the program's input and output
+#[cfg(feature = "host")]
+pub fn get_host_builtins() -> Vec<(FlyString, Value)> {
+    [
+        get_fs_builtins().as_slice(),
+        &get_system_builtins(),
+        &get_process_builtins(),
+    ]
+    .concat()
+}
+
+#[cfg(not(feature = "host"))]
+pub fn get_host_builtins() -> Vec<(FlyString, Value)> {
+    vec![]
+}
+
+/// Builtins that touch the file system. Only registered when the embedder opts in
+#[cfg(feature = "host")]
+pub fn get_fs_builtins() -> [(FlyString, Value); 4] {
+    fs::get_builtins()
+}
+
+/// Builtins that read the host process's environment. Only registered when the embedder opts in
+#[cfg(feature = "host")]
+pub fn get_system_builtins() -> [(FlyString, Value); 1] {
+    system::get_builtins()
+}
+
+/// Builtins that start other programs. Only registered when the embedder opts in
+#[cfg(feature = "host")]
+pub fn get_process_builtins() -> [(FlyString, Value); 1] {
+    process::get_builtins()
 }
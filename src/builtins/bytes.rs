@@ -0,0 +1,113 @@
+use super::{pop_num, to_text, to_usize, Num};
+use crate::{
+    execute::ExecuteError, machine_state::MachineState, pop_as, value::to_hex, FlyString, Value,
+};
+
+/// A list of ints from 0 to 255 as bytes
+fn bytes(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let list = pop_as!(state, List);
+    let bytes = list
+        .iter()
+        .map(|value| {
+            match value {
+                Value::Int(x) => u8::try_from(*x).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| ExecuteError::TypeMismatch {
+                expected: "int from 0 to 255".into(),
+                actual: value.type_name(),
+            })
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    state.push_new(bytes.into());
+    Ok(())
+}
+
+fn bytes_list(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let bytes = pop_as!(state, Bytes);
+    let list: Vec<Value> = bytes.iter().map(|byte| Value::Int(*byte as i64)).collect();
+    state.push_new(list.into());
+    Ok(())
+}
+
+fn bytes_len(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let bytes = pop_as!(state, Bytes);
+    state.push(Value::Int(bytes.len() as i64));
+    Ok(())
+}
+
+/// `bytes start end bytes-slice` copies the bytes from `start` up to but excluding `end`
+fn bytes_slice(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let end = pop_num(state)?;
+    let start = pop_num(state)?;
+    let bytes = pop_as!(state, Bytes);
+    let out_of_range = |index: Num| ExecuteError::IndexOutOfRange(index.as_float(), bytes.len());
+    let end_index = to_usize(end)
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(|| out_of_range(end))?;
+    let start_index = to_usize(start)
+        .filter(|start| *start <= end_index)
+        .ok_or_else(|| out_of_range(start))?;
+    state.push_new(bytes[start_index..end_index].to_vec().into());
+    Ok(())
+}
+
+/// `a b bytes-concat` is the bytes of `a` followed by those of `b`
+fn bytes_concat(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let b = pop_as!(state, Bytes);
+    let a = pop_as!(state, Bytes);
+    state.push_new([&a[..], &b[..]].concat().into());
+    Ok(())
+}
+
+/// The UTF-8 encoding of a string
+fn to_bytes(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    state.push_new(to_text(&value)?.as_bytes().to_vec().into());
+    Ok(())
+}
+
+/// Decode UTF-8, failing on anything that isn't valid
+fn bytes_to_string(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let bytes = pop_as!(state, Bytes);
+    let s = std::str::from_utf8(&bytes).map_err(|_| ExecuteError::InvalidUtf8)?;
+    state.push_new(s.to_string().into());
+    Ok(())
+}
+
+fn bytes_to_hex(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let bytes = pop_as!(state, Bytes);
+    state.push_new(to_hex(&bytes).into());
+    Ok(())
+}
+
+/// Two hex digits per byte, in either case
+fn hex_to_bytes(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    let hex = to_text(&value)?;
+    let invalid = || ExecuteError::InvalidHex(hex.to_string());
+    if hex.len() % 2 != 0 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+    state.push_new(bytes.into());
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 9] {
+    [
+        ("bytes".into(), Value::builtin(bytes)),
+        ("bytes-list".into(), Value::builtin(bytes_list)),
+        ("bytes-len".into(), Value::builtin(bytes_len)),
+        ("bytes-slice".into(), Value::builtin(bytes_slice)),
+        ("bytes-concat".into(), Value::builtin(bytes_concat)),
+        ("to-bytes".into(), Value::builtin(to_bytes)),
+        ("bytes-to-string".into(), Value::builtin(bytes_to_string)),
+        ("bytes-to-hex".into(), Value::builtin(bytes_to_hex)),
+        ("hex-to-bytes".into(), Value::builtin(hex_to_bytes)),
+    ]
+}
@@ -0,0 +1,44 @@
+use crate::{
+    channel::Channel, execute::ExecuteError, machine_state::MachineState, pop_as, FlyString, Value,
+};
+
+use std::time::Duration;
+
+/// How often a waiting `recv` checks whether the program was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn chan(state: &mut MachineState) -> Result<(), ExecuteError> {
+    state.push_new(Value::Channel(Channel::new()));
+    Ok(())
+}
+
+/// `value channel send`
+fn send(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let channel = pop_as!(state, Channel);
+    let value = state.pop()?;
+    channel.send(value);
+    Ok(())
+}
+
+/// Take the oldest value from `channel`, waiting until something is sent if it is empty, or the program is
+/// cancelled
+pub(super) fn receive(state: &MachineState, channel: &Channel) -> Result<Value, ExecuteError> {
+    channel
+        .recv_unless(CANCEL_POLL_INTERVAL, || state.is_cancelled())
+        .ok_or(ExecuteError::Cancelled)
+}
+
+fn recv(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let channel = pop_as!(state, Channel);
+    let value = receive(state, &channel)?;
+    state.push(value);
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 3] {
+    [
+        ("chan".into(), Value::builtin(chan)),
+        ("send".into(), Value::builtin(send)),
+        ("recv".into(), Value::builtin(recv)),
+    ]
+}
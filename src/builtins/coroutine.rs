@@ -0,0 +1,45 @@
+use crate::{
+    coroutine::Coroutine, execute::ExecuteError, machine_state::MachineState, pop_as, vm::Request,
+    FlyString, Value,
+};
+
+use std::sync::Mutex;
+
+/// Pause the coroutine running this, handing the value on top of the stack to whoever resumed it
+fn yield_value(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    state.push(value);
+    state.request_yield()
+}
+
+/// ( f -- ) Call `f` with the continuation of this call: a function that, called with a value, abandons what the
+/// program is doing and carries on after `call/cc` with that value on the stack. Returning from `f` normally
+/// carries on there too. Only works in code a coroutine runs directly, like `yield`
+fn call_with_continuation(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    state.request(Request::CallWithContinuation(f))
+}
+
+/// Turn a function without arguments into one that resumes it as a coroutine on every call, pushing what
+/// it yields next, or nil once it has returned. It sees the global definitions as they are now
+fn make_coroutine(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let coroutine = Mutex::new(Coroutine::start(state.fork(), f)?);
+    state.push_new(Value::builtin(move |state| {
+        let mut coroutine = coroutine
+            .try_lock()
+            .map_err(|_| ExecuteError::CoroutineRunning)?;
+        let value = coroutine.resume()?;
+        state.push(value.unwrap_or(Value::Nil));
+        Ok(())
+    }));
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 3] {
+    [
+        ("yield".into(), Value::builtin(yield_value)),
+        ("call/cc".into(), Value::builtin(call_with_continuation)),
+        ("coroutine".into(), Value::builtin(make_coroutine)),
+    ]
+}
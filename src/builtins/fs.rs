@@ -0,0 +1,45 @@
+use super::to_text;
+use crate::{execute::ExecuteError, machine_state::MachineState, FlyString, Value};
+
+use std::io::Write;
+
+fn file_read(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let path = state.pop()?;
+    let contents = std::fs::read_to_string(to_text(&path)?)?;
+    state.push_new(contents.into());
+    Ok(())
+}
+
+fn file_write(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let contents = state.pop()?;
+    let path = state.pop()?;
+    std::fs::write(to_text(&path)?, to_text(&contents)?)?;
+    Ok(())
+}
+
+fn file_append(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let contents = state.pop()?;
+    let path = state.pop()?;
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(to_text(&path)?)?
+        .write_all(to_text(&contents)?.as_bytes())?;
+    Ok(())
+}
+
+fn file_exists(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let path = state.pop()?;
+    let exists = std::path::Path::new(to_text(&path)?).exists();
+    state.push(Value::Bool(exists));
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 4] {
+    [
+        ("file-read".into(), Value::builtin(file_read)),
+        ("file-write".into(), Value::builtin(file_write)),
+        ("file-append".into(), Value::builtin(file_append)),
+        ("file-exists".into(), Value::builtin(file_exists)),
+    ]
+}
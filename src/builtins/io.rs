@@ -0,0 +1,35 @@
+use super::parse_number;
+use crate::{execute::ExecuteError, machine_state::MachineState, FlyString, Value};
+
+fn print(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let line = match state.pop() {
+        Ok(value) => format!("{value}\n"),
+        Err(_) => "<empty>\n".into(),
+    };
+    state.output().write(&line)?;
+    Ok(())
+}
+
+fn read_line(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let line = state.input().read_line()?;
+    state.push_new(line.map_or(Value::Nil, Value::from));
+    Ok(())
+}
+
+/// Pushes nil at end of input; a line that isn't a number is an error
+fn read_number(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let Some(line) = state.input().read_line()? else {
+        state.push(Value::Nil);
+        return Ok(());
+    };
+    state.push(parse_number(&line)?);
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 3] {
+    [
+        (".".into(), Value::builtin(print)),
+        ("read-line".into(), Value::builtin(read_line)),
+        ("read-number".into(), Value::builtin(read_number)),
+    ]
+}
@@ -0,0 +1,193 @@
+use super::{pop_num, to_usize, Num};
+use crate::{
+    callable::Callable, execute::ExecuteError, machine_state::MachineState, pop_as, FlyString,
+    Value,
+};
+
+use std::sync::Arc;
+
+fn to_index(index: Num, len: usize) -> Result<usize, ExecuteError> {
+    to_usize(index)
+        .filter(|i| *i < len)
+        .ok_or(ExecuteError::IndexOutOfRange(index.as_float(), len))
+}
+
+fn push(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    let mut list = pop_as!(state, List);
+    Arc::make_mut(&mut list).push(value);
+    state.push_new(Value::List(list));
+    Ok(())
+}
+
+fn get(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let index = pop_num(state)?;
+    let list = pop_as!(state, List);
+    let value = list[to_index(index, list.len())?].clone();
+    state.push(value);
+    Ok(())
+}
+
+fn set(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    let index = pop_num(state)?;
+    let mut list = pop_as!(state, List);
+    let index = to_index(index, list.len())?;
+    Arc::make_mut(&mut list)[index] = value;
+    state.push_new(Value::List(list));
+    Ok(())
+}
+
+fn len(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let list = pop_as!(state, List);
+    state.push(Value::Int(list.len() as i64));
+    Ok(())
+}
+
+fn head(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let list = pop_as!(state, List);
+    let value = list
+        .first()
+        .ok_or(ExecuteError::IndexOutOfRange(0.0, 0))?
+        .clone();
+    state.push(value);
+    Ok(())
+}
+
+fn tail(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let list = pop_as!(state, List);
+    if list.is_empty() {
+        return Err(ExecuteError::IndexOutOfRange(0.0, 0));
+    }
+    state.push_new(list[1..].to_vec().into());
+    Ok(())
+}
+
+/// The numbers from `start` up to but excluding `end`, `step` apart. Ints stay ints; with a float anywhere
+/// every element is computed from `start` rather than by adding up steps, so rounding errors don't accumulate
+fn make_range(start: Num, end: Num, step: Num) -> Result<Value, ExecuteError> {
+    let list = match (start, end, step) {
+        (_, _, Num::Int(0)) => return Err(ExecuteError::InvalidRange),
+        (Num::Int(start), Num::Int(end), Num::Int(step)) => {
+            let mut list = vec![];
+            let mut x = start;
+            while (step > 0 && x < end) || (step < 0 && x > end) {
+                list.push(Value::Int(x));
+                match x.checked_add(step) {
+                    Some(next) => x = next,
+                    None => break,
+                }
+            }
+            list
+        }
+        (start, end, step) => {
+            let (start, end, step) = (start.as_float(), end.as_float(), step.as_float());
+            if step == 0.0 || ![start, end, step].iter().all(|x| x.is_finite()) {
+                return Err(ExecuteError::InvalidRange);
+            }
+            let count = ((end - start) / step).ceil().max(0.0);
+            (0..count as u64)
+                .map(|i| Value::Number(start + i as f64 * step))
+                .collect()
+        }
+    };
+    Ok(list.into())
+}
+
+fn range(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let end = pop_num(state)?;
+    let start = pop_num(state)?;
+    state.push_new(make_range(start, end, Num::Int(1))?);
+    Ok(())
+}
+
+fn range_step(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let step = pop_num(state)?;
+    let end = pop_num(state)?;
+    let start = pop_num(state)?;
+    state.push_new(make_range(start, end, step)?);
+    Ok(())
+}
+
+/// Call a function with each element of a list in turn, leaving whatever the calls push
+fn for_each(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let list = pop_as!(state, List);
+    for value in list.iter() {
+        state.push(value.clone());
+        f.execute(state)?;
+    }
+    Ok(())
+}
+
+/// Call `f` on `value` and take the one result it leaves
+fn apply(state: &mut MachineState, f: &Callable, value: &Value) -> Result<Value, ExecuteError> {
+    state.push(value.clone());
+    f.execute(state)?;
+    state.pop()
+}
+
+/// The list of what a function returns for each element
+fn map(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let list = pop_as!(state, List);
+    let mapped = list
+        .iter()
+        .map(|value| apply(state, &f, value))
+        .collect::<Result<Vec<_>, _>>()?;
+    state.push_new(mapped.into());
+    Ok(())
+}
+
+/// The elements a function returns true for
+fn filter(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let list = pop_as!(state, List);
+    let mut kept = vec![];
+    for value in list.iter() {
+        let keep = match apply(state, &f, value)? {
+            Value::Bool(keep) => keep,
+            value => {
+                return Err(ExecuteError::TypeMismatch {
+                    expected: "Bool".into(),
+                    actual: value.type_name(),
+                })
+            }
+        };
+        if keep {
+            kept.push(value.clone());
+        }
+    }
+    state.push_new(kept.into());
+    Ok(())
+}
+
+/// Fold a list into a starting value: the function is called with the value so far as `$0` and the element as `$1`
+fn reduce(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let mut acc = state.pop()?;
+    let list = pop_as!(state, List);
+    for value in list.iter() {
+        state.push(acc);
+        acc = apply(state, &f, value)?;
+    }
+    state.push(acc);
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 12] {
+    [
+        ("push".into(), Value::builtin(push)),
+        ("get".into(), Value::builtin(get)),
+        ("set".into(), Value::builtin(set)),
+        ("len".into(), Value::builtin(len)),
+        ("head".into(), Value::builtin(head)),
+        ("tail".into(), Value::builtin(tail)),
+        ("for-each".into(), Value::builtin(for_each)),
+        ("range".into(), Value::builtin(range)),
+        ("range-step".into(), Value::builtin(range_step)),
+        ("map".into(), Value::builtin(map)),
+        ("filter".into(), Value::builtin(filter)),
+        ("reduce".into(), Value::builtin(reduce)),
+    ]
+}
@@ -0,0 +1,193 @@
+use super::{check_divisor, float_result, pop_num, Num};
+use crate::{execute::ExecuteError, machine_state::MachineState, pop_as, FlyString, Value};
+
+fn rem(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let result = match (pop_num(state)?, pop_num(state)?) {
+        (Num::Int(_), Num::Int(0)) => return Err(ExecuteError::DivisionByZero),
+        (Num::Int(a), Num::Int(b)) => {
+            Num::Int(a.checked_rem(b).ok_or(ExecuteError::IntegerOverflow)?)
+        }
+        (a, b) => {
+            check_divisor(state, b.as_float())?;
+            float_result(state, a.as_float() % b.as_float())?
+        }
+    };
+    state.push(result.into());
+    Ok(())
+}
+
+fn pow(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let result = match (pop_num(state)?, pop_num(state)?) {
+        (Num::Int(a), Num::Int(b)) if b >= 0 => {
+            let exponent = u32::try_from(b).map_err(|_| ExecuteError::IntegerOverflow)?;
+            Num::Int(
+                a.checked_pow(exponent)
+                    .ok_or(ExecuteError::IntegerOverflow)?,
+            )
+        }
+        (a, b) => float_result(state, a.as_float().powf(b.as_float()))?,
+    };
+    state.push(result.into());
+    Ok(())
+}
+
+macro_rules! numeric_select_impl {
+    ($name:ident, $method:ident) => {
+        fn $name(state: &mut MachineState) -> Result<(), ExecuteError> {
+            let result = match (pop_num(state)?, pop_num(state)?) {
+                (Num::Int(a), Num::Int(b)) => Num::Int(a.$method(b)),
+                (a, b) => Num::Float(a.as_float().$method(b.as_float())),
+            };
+            state.push(result.into());
+            Ok(())
+        }
+    };
+}
+
+numeric_select_impl!(min, min);
+numeric_select_impl!(max, max);
+
+/// Rounding an int is a no-op, so only floats are rounded
+macro_rules! rounding_impl {
+    ($name:ident, $method:ident) => {
+        fn $name(state: &mut MachineState) -> Result<(), ExecuteError> {
+            let result = match pop_num(state)? {
+                Num::Int(a) => Num::Int(a),
+                Num::Float(a) => Num::Float(a.$method()),
+            };
+            state.push(result.into());
+            Ok(())
+        }
+    };
+}
+
+rounding_impl!(floor, floor);
+rounding_impl!(ceil, ceil);
+
+fn abs(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let result = match pop_num(state)? {
+        Num::Int(a) => Num::Int(a.checked_abs().ok_or(ExecuteError::IntegerOverflow)?),
+        Num::Float(a) => Num::Float(a.abs()),
+    };
+    state.push(result.into());
+    Ok(())
+}
+
+fn sqrt(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = pop_num(state)?.as_float();
+    let result = float_result(state, a.sqrt())?;
+    state.push(result.into());
+    Ok(())
+}
+
+/// Truncates floats towards zero, failing for values without an i64 counterpart
+fn to_int(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let result = match pop_num(state)? {
+        Num::Int(a) => a,
+        Num::Float(a) if a.is_finite() && a.abs() < i64::MAX as f64 => a as i64,
+        Num::Float(_) => return Err(ExecuteError::IntegerOverflow),
+    };
+    state.push(Value::Int(result));
+    Ok(())
+}
+
+fn to_float(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = pop_num(state)?.as_float();
+    state.push(Value::Number(a));
+    Ok(())
+}
+
+/// The bits of a number as a two's complement int. Floats must be whole numbers in range, so nothing is
+/// silently truncated
+fn pop_bits(state: &mut MachineState) -> Result<i64, ExecuteError> {
+    match pop_num(state)? {
+        Num::Int(a) => Ok(a),
+        Num::Float(a) if a.fract() == 0.0 && a.abs() < i64::MAX as f64 => Ok(a as i64),
+        Num::Float(_) => Err(ExecuteError::TypeMismatch {
+            expected: "integral number".into(),
+            actual: "number",
+        }),
+    }
+}
+
+macro_rules! bitwise_impl {
+    ($name:ident, $op:tt) => {
+        fn $name(state: &mut MachineState) -> Result<(), ExecuteError> {
+            let result = pop_bits(state)? $op pop_bits(state)?;
+            state.push(Value::Int(result));
+            Ok(())
+        }
+    };
+}
+
+bitwise_impl!(band, &);
+bitwise_impl!(bor, |);
+bitwise_impl!(bxor, ^);
+
+fn bnot(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let a = pop_bits(state)?;
+    state.push(Value::Int(!a));
+    Ok(())
+}
+
+/// ( amount x -- x shifted ) Bits shifted out are dropped. Shifting by a negative amount or by 64 or more fails
+macro_rules! shift_impl {
+    ($name:ident, $method:ident) => {
+        fn $name(state: &mut MachineState) -> Result<(), ExecuteError> {
+            let x = pop_bits(state)?;
+            let amount = pop_bits(state)?;
+            let result = u32::try_from(amount)
+                .ok()
+                .and_then(|amount| x.$method(amount))
+                .ok_or(ExecuteError::IntegerOverflow)?;
+            state.push(Value::Int(result));
+            Ok(())
+        }
+    };
+}
+
+shift_impl!(shl, checked_shl);
+// Shifting right keeps the sign, so negative numbers stay negative
+shift_impl!(shr, checked_shr);
+
+/// A float in `[0, 1)`
+fn rand(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let x = state.rng().next_f64();
+    state.push(Value::Number(x));
+    Ok(())
+}
+
+/// ( low high -- x ) An int in `[low, high)`
+fn rand_int(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let high = pop_as!(state, Int);
+    let low = pop_as!(state, Int);
+    if high <= low {
+        return Err(ExecuteError::InvalidRange);
+    }
+    let offset = state.rng().below(high.abs_diff(low));
+    state.push(Value::Int(low.wrapping_add_unsigned(offset)));
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 18] {
+    [
+        ("mod".into(), Value::builtin(rem)),
+        ("pow".into(), Value::builtin(pow)),
+        ("min".into(), Value::builtin(min)),
+        ("max".into(), Value::builtin(max)),
+        ("sqrt".into(), Value::builtin(sqrt)),
+        ("floor".into(), Value::builtin(floor)),
+        ("ceil".into(), Value::builtin(ceil)),
+        ("abs".into(), Value::builtin(abs)),
+        ("to-int".into(), Value::builtin(to_int)),
+        ("to-float".into(), Value::builtin(to_float)),
+        ("rand".into(), Value::builtin(rand)),
+        ("rand-int".into(), Value::builtin(rand_int)),
+        ("band".into(), Value::builtin(band)),
+        ("bor".into(), Value::builtin(bor)),
+        ("bxor".into(), Value::builtin(bxor)),
+        ("bnot".into(), Value::builtin(bnot)),
+        ("shl".into(), Value::builtin(shl)),
+        ("shr".into(), Value::builtin(shr)),
+    ]
+}
@@ -0,0 +1,29 @@
+use super::to_text;
+use crate::{execute::ExecuteError, machine_state::MachineState, pop_as, FlyString, Value};
+
+use std::process::Command;
+
+/// ( command -- stdout status ) Runs a list of a program and its arguments, without a shell.
+/// The status is nil if the process was killed by a signal; stderr is discarded
+fn exec(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let command = pop_as!(state, List);
+    let mut words = command.iter().map(to_text);
+    let program = words
+        .next()
+        .ok_or(ExecuteError::IndexOutOfRange(0.0, 0))??;
+    let output = Command::new(program)
+        .args(words.collect::<Result<Vec<_>, _>>()?)
+        .output()?;
+    state.push_new(String::from_utf8_lossy(&output.stdout).into_owned().into());
+    state.push_new(
+        output
+            .status
+            .code()
+            .map_or(Value::Nil, |code| Value::Int(code.into())),
+    );
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 1] {
+    [("exec".into(), Value::builtin(exec))]
+}
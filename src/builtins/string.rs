@@ -0,0 +1,59 @@
+use super::{pop_num, to_text, to_usize};
+use crate::{execute::ExecuteError, machine_state::MachineState, pop_as, FlyString, Value};
+
+/// `string index char-at` pushes the character at `index` as a string of its own. Strings are indexed by
+/// Unicode scalar value rather than byte, so every index names a whole character whatever its length in UTF-8
+fn char_at(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let index = pop_num(state)?;
+    let value = state.pop()?;
+    let s = to_text(&value)?;
+    let c = to_usize(index)
+        .and_then(|i| s.chars().nth(i))
+        .ok_or_else(|| ExecuteError::IndexOutOfRange(index.as_float(), s.chars().count()))?;
+    state.push_new(c.to_string().into());
+    Ok(())
+}
+
+fn chars(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    let chars: Vec<Value> = to_text(&value)?
+        .chars()
+        .map(|c| c.to_string().into())
+        .collect();
+    state.push_new(chars.into());
+    Ok(())
+}
+
+/// The code point of a single-character string
+fn ord(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let value = state.pop()?;
+    let mut chars = to_text(&value)?.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return Err(ExecuteError::TypeMismatch {
+            expected: "single character".into(),
+            actual: value.type_name(),
+        });
+    };
+    state.push(Value::Int(c as i64));
+    Ok(())
+}
+
+/// The single-character string with the given code point
+fn chr(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let code = pop_as!(state, Int);
+    let c = u32::try_from(code)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or(ExecuteError::InvalidCodePoint(code))?;
+    state.push_new(c.to_string().into());
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 4] {
+    [
+        ("char-at".into(), Value::builtin(char_at)),
+        ("chars".into(), Value::builtin(chars)),
+        ("ord".into(), Value::builtin(ord)),
+        ("chr".into(), Value::builtin(chr)),
+    ]
+}
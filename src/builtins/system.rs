@@ -0,0 +1,14 @@
+use super::to_text;
+use crate::{execute::ExecuteError, machine_state::MachineState, FlyString, Value};
+
+/// Pushes nil for variables that are unset or not valid unicode
+fn env(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let name = state.pop()?;
+    let value = std::env::var(to_text(&name)?).ok();
+    state.push_new(value.map_or(Value::Nil, Value::from));
+    Ok(())
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 1] {
+    [("env".into(), Value::builtin(env))]
+}
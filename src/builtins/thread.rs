@@ -0,0 +1,80 @@
+use super::channel::receive;
+use crate::{
+    channel::Channel, execute::ExecuteError, machine_state::MachineState, pop_as, FlyString, Value,
+};
+
+use std::sync::{atomic::AtomicBool, Arc};
+
+/// The most native stack a spawned thread gets, whatever [`crate::Limits::thread_stack_size`] asks for
+const MAX_THREAD_STACK: usize = 1 << 30;
+
+/// Run a function without arguments on a new thread and machine, which starts with a copy of the global
+/// definitions. Pushes a channel that gets the value the function leaves on top of its stack, nil if it
+/// leaves none, or the error it fails with, and stands for the thread in `join` and `cancel`. Threads still
+/// running when the machine that spawned them goes away are cancelled. Fails on targets without threads
+fn spawn(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut thread_state = state.fork();
+    thread_state.add_cancel_token(Arc::clone(&cancel));
+    let stack_size = thread_state
+        .limits()
+        .thread_stack_size
+        .min(MAX_THREAD_STACK);
+    let result = Channel::for_task(cancel);
+    let sender = result.clone();
+    let thread = std::thread::Builder::new()
+        .name("ssl-spawn".into())
+        .stack_size(stack_size)
+        .spawn(move || {
+            let interner = thread_state.interner().clone();
+            let value = match interner.enter(|| f.execute(&mut thread_state)) {
+                Ok(()) => thread_state.pop_result().unwrap_or(Value::Nil),
+                Err(e) => Value::Error(e.kind().to_string().into()),
+            };
+            sender.send(value);
+        })?;
+    state.tasks_mut().add(result.clone(), thread);
+    state.push_new(Value::Channel(result));
+    Ok(())
+}
+
+/// `task join` waits for a thread started by `spawn` to end and pushes its result, like `recv` on its channel
+fn join(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let task = pop_as!(state, Channel);
+    let value = receive(state, &task)?;
+    state.tasks_mut().join(&task);
+    state.push(value);
+    Ok(())
+}
+
+/// `task cancel` stops a thread started by `spawn` at its next operation, or while it waits on a channel.
+/// Its result is the `Cancelled` error
+fn cancel(state: &mut MachineState) -> Result<(), ExecuteError> {
+    pop_as!(state, Channel).cancel_task();
+    Ok(())
+}
+
+/// `f task-group` runs `f`, then waits for the threads it spawned and didn't join. If `f` fails, they are
+/// cancelled instead and the error is passed on once they ended
+fn task_group(state: &mut MachineState) -> Result<(), ExecuteError> {
+    let f = pop_as!(state, Function);
+    let outer = std::mem::take(state.tasks_mut());
+    let result = f.execute(state);
+    let mut group = std::mem::replace(state.tasks_mut(), outer);
+    match result {
+        Ok(()) => group.join_all(),
+        // Dropping cancels them, without waiting forever for any stuck in a host call
+        Err(_) => drop(group),
+    }
+    result
+}
+
+pub fn get_builtins() -> [(FlyString, Value); 4] {
+    [
+        ("spawn".into(), Value::builtin(spawn)),
+        ("join".into(), Value::builtin(join)),
+        ("cancel".into(), Value::builtin(cancel)),
+        ("task-group".into(), Value::builtin(task_group)),
+    ]
+}
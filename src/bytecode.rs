@@ -0,0 +1,211 @@
+use crate::{
+    callable::Callable,
+    operation::{Operation, OperationKind},
+    FlyString, Value,
+};
+
+use std::{fmt, sync::Arc};
+
+use once_cell::sync::OnceCell;
+
+/// What an instruction does. Jumps are relative to the instruction doing them
+#[derive(Debug, Clone)]
+pub(crate) enum InstructionKind {
+    Push(Value),
+    PushConst(usize),
+    PushId(FlyString),
+    PushRaw(FlyString),
+    PushArg(usize),
+    Call(Callable),
+    /// Pop a condition and open a scope, jumping forward if the condition is false
+    Branch(usize),
+    /// Pop a condition, jumping forward if it is false
+    JumpUnless(usize),
+    Jump(isize),
+    /// Pop a value and open a scope, jumping forward by the offset of the first label equal to it, or the last offset
+    Case(Vec<(Value, usize)>, usize),
+    EnterScope,
+    ExitScope,
+    /// Pop a count and open a scope for a loop running that many times
+    BeginRepeat,
+    /// Start another iteration of the innermost `repeat`, or jump forward once it ran often enough
+    Repeat(usize),
+    /// Open a scope collecting everything pushed from now on into a list
+    BeginList,
+    EndList,
+    /// Open a scope whose errors are handled by the code at the offset
+    BeginTry(usize),
+    /// Leave the `try` body, jumping forward over its handler
+    EndTry(usize),
+    Recurse,
+    Return,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Instruction {
+    pub(crate) kind: InstructionKind,
+    /// The index of the operation the instruction was compiled from in [`Code::operations`]
+    pub(crate) op: usize,
+    /// Whether the operation starts with this instruction, so it is counted and shown to hooks here
+    pub(crate) begins: bool,
+    /// Whether nothing in the function runs after this instruction
+    pub(crate) tail: bool,
+}
+
+/// A function body flattened into a list of instructions, so running it needs no recursion into blocks
+#[derive(Debug, Default)]
+pub(crate) struct Code {
+    pub(crate) instructions: Vec<Instruction>,
+    /// Every operation of the body, nested ones included, for hooks and error locations
+    pub(crate) operations: Vec<Operation>,
+    /// How many try bodies the block being compiled is nested in. Nothing in them is a tail call,
+    /// even right before a return, since the handler has to stay installed while it runs
+    try_depth: usize,
+}
+
+/// The [`Code`] of a function, compiled on its first call. Clones share it,
+/// so it has to be reset whenever the operations it was compiled from change
+#[derive(Clone, Default)]
+pub(crate) struct CodeCache(OnceCell<Arc<Code>>);
+
+impl CodeCache {
+    pub(crate) fn get_or_compile(&self, operations: &[Operation]) -> &Code {
+        self.0.get_or_init(|| Arc::new(compile(operations)))
+    }
+}
+
+impl fmt::Debug for CodeCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CodeCache")
+    }
+}
+
+/// Flatten `operations` into the body of a function
+pub(crate) fn compile(operations: &[Operation]) -> Code {
+    let mut code = Code::default();
+    code.block(operations, true);
+    code
+}
+
+impl Code {
+    /// `tail` is set if the function ends right after the block
+    fn block(&mut self, operations: &[Operation], tail: bool) {
+        for (i, op) in operations.iter().enumerate() {
+            let tail = match operations.get(i + 1) {
+                None => tail,
+                Some(next) => self.try_depth == 0 && matches!(next.kind, OperationKind::Return),
+            };
+            self.operation(op, tail);
+        }
+    }
+
+    fn operation(&mut self, op: &Operation, tail: bool) {
+        use InstructionKind as I;
+        use OperationKind as O;
+
+        let index = self.operations.len();
+        self.operations.push(op.clone());
+        let start = self.instructions.len();
+        let emit = |code: &mut Self, kind: InstructionKind| code.emit(kind, index, tail);
+
+        match &op.kind {
+            O::Push(v) => emit(self, I::Push(v.clone())),
+            O::PushConst(i) => emit(self, I::PushConst(*i)),
+            O::PushId(id) => emit(self, I::PushId(id.clone())),
+            O::PushRaw(id) => emit(self, I::PushRaw(id.clone())),
+            O::PushArg(i) => emit(self, I::PushArg(*i)),
+            O::Call(_, f) => emit(self, I::Call(f.clone())),
+            O::If(if_body, else_body) => {
+                emit(self, I::Branch(0));
+                self.block(if_body, tail);
+                emit(self, I::Jump(0));
+                let jump = self.instructions.len() - 1;
+                self.patch(start);
+                self.block(else_body, tail);
+                self.patch(jump);
+                emit(self, I::ExitScope);
+            }
+            O::While(body) => {
+                emit(self, I::EnterScope);
+                let head = self.instructions.len();
+                emit(self, I::JumpUnless(0));
+                self.block(body, false);
+                let back = head as isize - self.instructions.len() as isize;
+                emit(self, I::Jump(back));
+                self.patch(head);
+                emit(self, I::ExitScope);
+            }
+            O::Repeat(body) => {
+                emit(self, I::BeginRepeat);
+                let head = self.instructions.len();
+                emit(self, I::Repeat(0));
+                self.block(body, false);
+                let back = head as isize - self.instructions.len() as isize;
+                emit(self, I::Jump(back));
+                self.patch(head);
+                emit(self, I::ExitScope);
+            }
+            O::List(body) => {
+                emit(self, I::BeginList);
+                self.block(body, false);
+                emit(self, I::EndList);
+            }
+            O::Try(body, handler) => {
+                emit(self, I::BeginTry(0));
+                self.try_depth += 1;
+                self.block(body, false);
+                self.try_depth -= 1;
+                let end_try = self.instructions.len();
+                emit(self, I::EndTry(0));
+                self.patch(start);
+                self.block(handler, tail);
+                emit(self, I::ExitScope);
+                self.patch(end_try);
+            }
+            O::Case(branches, default) => {
+                emit(self, I::Case(vec![], 0));
+                let mut offsets = Vec::with_capacity(branches.len());
+                let mut jumps = Vec::with_capacity(branches.len());
+                for (label, body) in branches {
+                    offsets.push((label.clone(), self.instructions.len() - start));
+                    self.block(body, tail);
+                    jumps.push(self.instructions.len());
+                    emit(self, I::Jump(0));
+                }
+                let default_offset = self.instructions.len() - start;
+                self.block(default, tail);
+                jumps.into_iter().for_each(|jump| self.patch(jump));
+                emit(self, I::ExitScope);
+                self.instructions[start].kind = I::Case(offsets, default_offset);
+            }
+            O::Recurse => emit(self, I::Recurse),
+            O::Return => emit(self, I::Return),
+        }
+        self.instructions[start].begins = true;
+    }
+
+    fn emit(&mut self, kind: InstructionKind, op: usize, tail: bool) {
+        self.instructions.push(Instruction {
+            kind,
+            op,
+            begins: false,
+            tail,
+        });
+    }
+
+    /// Point the forward jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        use InstructionKind as I;
+
+        let distance = self.instructions.len() - at;
+        match &mut self.instructions[at].kind {
+            I::Branch(offset)
+            | I::JumpUnless(offset)
+            | I::Repeat(offset)
+            | I::BeginTry(offset)
+            | I::EndTry(offset) => *offset = distance,
+            I::Jump(offset) => *offset = distance as isize,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+}
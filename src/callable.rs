@@ -1,47 +1,237 @@
 use crate::{
+    bytecode::{Code, CodeCache},
     execute::{execute_function, ExecuteError},
     machine_state::MachineState,
-    operation::Operation,
+    operation::{Operation, OperationKind},
+    span::Span,
     value::Value,
     FlyString,
 };
 
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionDescriptor {
-    pub(crate) operations: Vec<Operation>,
-    pub(crate) captured_names: HashMap<FlyString, Value>,
+    /// Ahead of the operations, so it is serialized before the functions nested in them that share it
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::pool"))]
+    pub(crate) constants: Arc<[Value]>,
+    pub(crate) operations: Arc<[Operation]>,
+    pub(crate) captured_names: Arc<HashMap<FlyString, Value>>,
+    pub(crate) referenced_names: Arc<[FlyString]>,
     pub(crate) num_args: usize,
+    /// Whether the function takes a count of extra arguments, which `$*` reads as a list
+    pub(crate) variadic: bool,
+    /// Where the `fn` of the function is, or the default span for a whole program
+    pub(crate) span: Span,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) code: CodeCache,
+    /// Set on programs loaded from signed bytes, see [`Self::signature`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) signature: Option<Arc<Signature>>,
 }
 
-pub type BuiltinFuntion = fn(&mut MachineState) -> Result<(), ExecuteError>;
+/// The signature stored with a program, and the encoding of the program it was made over
+#[derive(Debug)]
+pub struct Signature {
+    pub(crate) signed: Box<[u8]>,
+    pub(crate) signature: Box<[u8]>,
+}
 
-#[derive(Debug, Clone)]
+impl Signature {
+    /// The encoded program, as it was handed to the signing function
+    pub fn signed_bytes(&self) -> &[u8] {
+        &self.signed
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.signature
+    }
+}
+
+impl FunctionDescriptor {
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// The literals `OperationKind::PushConst` indexes into, shared by every function of a program
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    /// The values a closure captured when it was created
+    pub fn captured_names(&self) -> impl Iterator<Item = (&FlyString, &Value)> {
+        self.captured_names.iter()
+    }
+
+    /// Every identifier the function, including nested functions, may look up
+    pub fn referenced_names(&self) -> &[FlyString] {
+        &self.referenced_names
+    }
+
+    /// The number of `$n` arguments. Variadic functions take their rest list after these
+    pub fn num_args(&self) -> usize {
+        self.num_args
+    }
+
+    pub fn is_variadic(&self) -> bool {
+        self.variadic
+    }
+
+    /// Where the function is defined, which [`crate::ExecutionReport::functions`] is keyed by
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The signature the program was stored with, if it was loaded from bytes made by `to_signed_bytes`.
+    /// Verifiers registered with [`crate::ExecuteOptions::with_verifier`] check it against
+    /// [`Signature::signed_bytes`]. Changing the program, e.g. by optimizing it, drops the signature
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_deref()
+    }
+
+    /// The operations flattened into instructions, compiled once and shared by every closure made from the function
+    pub(crate) fn code(&self) -> &Code {
+        self.code.get_or_compile(&self.operations)
+    }
+
+    /// A closure of this function, sharing its compiled code
+    pub(crate) fn with_captures(&self, captured_names: Arc<HashMap<FlyString, Value>>) -> Self {
+        self.code();
+        Self {
+            captured_names,
+            ..self.clone()
+        }
+    }
+}
+
+/// Every identifier `operations` (including nested function bodies) may look up, which is all a closure needs to capture
+pub(crate) fn referenced_names(operations: &[Operation]) -> Arc<[FlyString]> {
+    fn collect(operations: &[Operation], names: &mut HashSet<FlyString>) {
+        use OperationKind as O;
+
+        for op in operations {
+            match &op.kind {
+                O::PushId(id) | O::PushRaw(id) => {
+                    names.insert(id.clone());
+                }
+                O::Push(Value::Function(Callable {
+                    kind: CallableKind::Function(f),
+                    ..
+                })) => names.extend(f.referenced_names.iter().cloned()),
+                _ => op.blocks().for_each(|block| collect(block, names)),
+            }
+        }
+    }
+
+    let mut names = HashSet::new();
+    collect(operations, &mut names);
+    names.into_iter().collect()
+}
+
+/// A native function. Being a closure, it can carry state of the embedding application
+pub type BuiltinFuntion = Arc<dyn Fn(&mut MachineState) -> Result<(), ExecuteError> + Send + Sync>;
+
+#[derive(Clone)]
 pub enum CallableKind {
-    Function(Rc<FunctionDescriptor>),
+    Function(Arc<FunctionDescriptor>),
     Builtin(BuiltinFuntion),
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Callable {
     pub(crate) kind: CallableKind,
-    pub(crate) bound_arguments: Vec<Value>,
+    /// Shared so looking the function up, which clones it, doesn't copy its arguments
+    pub(crate) bound_arguments: Arc<[Value]>,
 }
 
 impl Callable {
     pub fn execute(&self, state: &mut MachineState) -> Result<(), ExecuteError> {
         match &self.kind {
             CallableKind::Builtin(f) => {
-                self.bound_arguments
-                    .iter()
-                    .rev()
-                    .cloned()
-                    .for_each(|arg| state.push(arg));
-                f(state)
+                state.forbid_yield();
+                state.record_builtin(f);
+                self.call_builtin(state, f)
+            }
+            CallableKind::Function(f) => execute_function(state, self, f),
+        }
+    }
+
+    /// Run a builtin after pushing its bound arguments
+    pub(crate) fn call_builtin(
+        &self,
+        state: &mut MachineState,
+        f: &BuiltinFuntion,
+    ) -> Result<(), ExecuteError> {
+        self.bound_arguments
+            .iter()
+            .rev()
+            .cloned()
+            .for_each(|arg| state.push(arg));
+        f(state)
+    }
+}
+
+/// Functions compare by identity: the same body (or builtin) with equal bound arguments
+impl PartialEq for CallableKind {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Function(a), Self::Function(b)) => Arc::ptr_eq(a, b),
+            (Self::Builtin(a), Self::Builtin(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for CallableKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Function(function) => f.debug_tuple("Function").field(function).finish(),
+            Self::Builtin(builtin) => write!(f, "Builtin({:p})", Arc::as_ptr(builtin)),
+        }
+    }
+}
+
+impl fmt::Display for Callable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            CallableKind::Builtin(_) => f.write_str("<builtin")?,
+            CallableKind::Function(function) if function.captured_names.is_empty() => {
+                f.write_str("<function")?
+            }
+            CallableKind::Function(function) => {
+                let mut names: Vec<_> = function.captured_names.keys().collect();
+                names.sort_unstable_by_key(|name| name.as_str());
+                f.write_str("<closure: ")?;
+                for (i, name) in names.into_iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{name}")?;
+                }
+            }
+        }
+        if !self.bound_arguments.is_empty() {
+            f.write_str(", bound arguments: ")?;
+            for (i, value) in self.bound_arguments.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "${i}: {value}")?;
             }
-            CallableKind::Function(f) => execute_function(state, f, &self.bound_arguments),
         }
+        f.write_str(">")
+    }
+}
+
+impl PartialEq for Callable {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.bound_arguments == other.bound_arguments
     }
 }
 
@@ -49,7 +239,7 @@ impl From<BuiltinFuntion> for Callable {
     fn from(value: BuiltinFuntion) -> Self {
         Self {
             kind: CallableKind::Builtin(value),
-            bound_arguments: vec![],
+            bound_arguments: Arc::default(),
         }
     }
 }
@@ -58,7 +248,7 @@ impl From<FunctionDescriptor> for Callable {
     fn from(value: FunctionDescriptor) -> Self {
         Self {
             kind: CallableKind::Function(value.into()),
-            bound_arguments: vec![],
+            bound_arguments: Arc::default(),
         }
     }
 }
@@ -0,0 +1,103 @@
+use crate::Value;
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+/// A queue of values shared by all its clones, for passing values between programs running on different threads.
+/// Sending never waits, receiving waits for a value to arrive
+#[derive(Clone, Default)]
+pub struct Channel(pub(crate) Arc<Queue>);
+
+#[derive(Default)]
+pub(crate) struct Queue {
+    values: Mutex<VecDeque<Value>>,
+    sent: Condvar,
+    /// The cancel token of the thread whose result the channel gets, if `spawn` made it
+    task: Option<Arc<AtomicBool>>,
+}
+
+impl Channel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A channel for the result of a thread that stops once `cancel` is set
+    pub(crate) fn for_task(cancel: Arc<AtomicBool>) -> Self {
+        Self(Arc::new(Queue {
+            task: Some(cancel),
+            ..Default::default()
+        }))
+    }
+
+    /// Ask the thread whose result this channel gets to stop. Does nothing for channels `spawn` didn't make
+    pub(crate) fn cancel_task(&self) {
+        if let Some(cancel) = &self.0.task {
+            cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn send(&self, value: Value) {
+        self.values().push_back(value);
+        self.0.sent.notify_one();
+    }
+
+    /// Take the oldest value, waiting until there is one
+    pub fn recv(&self) -> Value {
+        let mut values = self.values();
+        loop {
+            if let Some(value) = values.pop_front() {
+                return value;
+            }
+            values = self.0.sent.wait(values).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Like [`Self::recv`], but gives up with `None` once `cancelled` returns true, which is checked every
+    /// `interval` while waiting
+    pub fn recv_unless(&self, interval: Duration, cancelled: impl Fn() -> bool) -> Option<Value> {
+        let mut values = self.values();
+        loop {
+            if let Some(value) = values.pop_front() {
+                return Some(value);
+            }
+            if cancelled() {
+                return None;
+            }
+            let (guard, _) = self
+                .0
+                .sent
+                .wait_timeout(values, interval)
+                .unwrap_or_else(|e| e.into_inner());
+            values = guard;
+        }
+    }
+
+    /// Take the oldest value if there is one
+    pub fn try_recv(&self) -> Option<Value> {
+        self.values().pop_front()
+    }
+
+    pub(crate) fn values(&self) -> std::sync::MutexGuard<'_, VecDeque<Value>> {
+        self.0.values.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Channels compare by identity
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Channel")
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{
+    builtins::STACK_EFFECTS,
+    callable::{Callable, CallableKind, FunctionDescriptor},
+    operation::{Operation, OperationKind},
+    FlyString, Span, Value,
+};
+
+use std::{collections::HashSet, fmt};
+
+use thiserror::Error;
+
+/// A likely mistake found by [`check`]. The program may still run fine, e.g. when a function it calls leaves
+/// more values than it looks like
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub span: Span,
+    pub kind: WarningKind,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    #[error("{name} takes {needs} values, but the stack only holds {available} here")]
+    Underflow {
+        name: FlyString,
+        needs: usize,
+        available: usize,
+    },
+    #[error("the branches leave different numbers of values: {0:+} and {1:+}")]
+    UnbalancedBranches(i64, i64),
+    #[error(
+        "each iteration changes the stack by {0:+}, the body should only push the next condition"
+    )]
+    UnbalancedLoop(i64),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at {})", self.kind, self.span)
+    }
+}
+
+/// Follow the stack depth through `program` to find operations that would run out of values and blocks
+/// whose branches or iterations leave the stack unbalanced. Builtins are assumed to be the standard ones;
+/// after calling anything else the depth is unknown until the stack is cleared
+pub fn check(program: &FunctionDescriptor) -> Vec<Warning> {
+    let mut checker = Checker::default();
+    checker.block(program.operations(), program, Depth::Known(0), true);
+    checker.warnings.sort_by_key(|warning| warning.span.offset);
+    checker.warnings
+}
+
+/// How many values are on the stack, relative to the start of the function being checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Depth {
+    Known(i64),
+    Unknown,
+    /// Control never gets past the operation, e.g. after `ret`
+    Diverged,
+}
+
+impl Depth {
+    fn add(self, n: i64) -> Self {
+        match self {
+            Depth::Known(depth) => Depth::Known(depth + n),
+            depth => depth,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    warnings: Vec<Warning>,
+    /// Names last assigned a literal other than a function, which push one value when named
+    values: HashSet<FlyString>,
+}
+
+impl Checker {
+    /// `bottomed` says whether the stack is known to be empty when the block's function starts. Only the
+    /// program itself is, functions also see what their callers left
+    fn block(
+        &mut self,
+        ops: &[Operation],
+        f: &FunctionDescriptor,
+        mut depth: Depth,
+        bottomed: bool,
+    ) -> Depth {
+        for (i, op) in ops.iter().enumerate() {
+            if depth == Depth::Diverged {
+                break;
+            }
+            if let OperationKind::PushId(name) | OperationKind::Call(name, _) = &op.kind {
+                if *name == ":=" {
+                    self.track_assignment(&ops[..i], f);
+                }
+            }
+            depth = self.operation(op, f, depth, bottomed);
+        }
+        depth
+    }
+
+    fn operation(
+        &mut self,
+        op: &Operation,
+        f: &FunctionDescriptor,
+        depth: Depth,
+        bottomed: bool,
+    ) -> Depth {
+        use OperationKind as O;
+
+        match &op.kind {
+            O::Push(value) => {
+                if let Value::Function(Callable {
+                    kind: CallableKind::Function(inner),
+                    ..
+                }) = value
+                {
+                    self.block(inner.operations(), inner, Depth::Known(0), false);
+                }
+                depth.add(1)
+            }
+            O::PushConst(_) | O::PushRaw(_) | O::PushArg(_) => depth.add(1),
+            O::PushId(name) | O::Call(name, _) => self.call(name, op.span, depth, bottomed),
+            O::If(then, otherwise) => {
+                let depth = self.pop(1, "if", op.span, depth, bottomed);
+                let branches = [
+                    self.block(then, f, depth, bottomed),
+                    self.block(otherwise, f, depth, bottomed),
+                ];
+                self.merge(&branches, depth, op.span)
+            }
+            O::Case(branches, default) => {
+                let depth = self.pop(1, "case", op.span, depth, bottomed);
+                let mut ends: Vec<_> = branches
+                    .iter()
+                    .map(|(_, body)| self.block(body, f, depth, bottomed))
+                    .collect();
+                ends.push(self.block(default, f, depth, bottomed));
+                self.merge(&ends, depth, op.span)
+            }
+            O::Try(body, handler) => {
+                // A failed body leaves nothing behind but the error
+                let branches = [
+                    self.block(body, f, depth, bottomed),
+                    self.block(handler, f, depth.add(1), bottomed),
+                ];
+                self.merge(&branches, depth, op.span)
+            }
+            O::While(body) => {
+                let depth = self.pop(1, "while", op.span, depth, bottomed);
+                match (depth, self.block(body, f, depth, bottomed)) {
+                    (Depth::Known(before), Depth::Known(after)) if after != before + 1 => {
+                        self.warn(op.span, WarningKind::UnbalancedLoop(after - before - 1));
+                        Depth::Unknown
+                    }
+                    (_, Depth::Unknown) => Depth::Unknown,
+                    _ => depth,
+                }
+            }
+            O::Repeat(body) => {
+                // Leaving values on every repetition is fine, but then how many depends on the count
+                let depth = self.pop(1, "repeat", op.span, depth, bottomed);
+                match self.block(body, f, depth, bottomed) {
+                    after if after == depth => depth,
+                    _ => Depth::Unknown,
+                }
+            }
+            O::List(body) => {
+                self.block(body, f, depth, bottomed);
+                depth.add(1)
+            }
+            O::Recurse => Depth::Unknown,
+            O::Return => Depth::Diverged,
+        }
+    }
+
+    fn call(&mut self, name: &FlyString, span: Span, depth: Depth, bottomed: bool) -> Depth {
+        if self.values.contains(name) {
+            return depth.add(1);
+        }
+        match name.as_str() {
+            // Prints `<empty>` rather than failing on an empty stack
+            "." => match depth {
+                Depth::Known(depth) if bottomed => Depth::Known((depth - 1).max(0)),
+                depth => depth.add(-1),
+            },
+            "clear-stack" => Depth::Known(0),
+            "throw" | "exit" => {
+                self.pop(1, name.as_str(), span, depth, bottomed);
+                Depth::Diverged
+            }
+            name => match STACK_EFFECTS.iter().find(|(builtin, ..)| *builtin == name) {
+                Some(&(_, pops, pushes)) => self
+                    .pop(pops, name, span, depth, bottomed)
+                    .add(pushes as i64),
+                None => Depth::Unknown,
+            },
+        }
+    }
+
+    /// Take `n` values for the operation `name`, warning if the stack can't have that many
+    fn pop(&mut self, n: usize, name: &str, span: Span, depth: Depth, bottomed: bool) -> Depth {
+        match depth {
+            Depth::Known(available) if bottomed && available < n as i64 => {
+                self.warn(
+                    span,
+                    WarningKind::Underflow {
+                        name: name.into(),
+                        needs: n,
+                        available: available.max(0) as usize,
+                    },
+                );
+                Depth::Known(0)
+            }
+            depth => depth.add(-(n as i64)),
+        }
+    }
+
+    /// The depth after one of several blocks that started at `start` ran, warning if they disagree
+    fn merge(&mut self, ends: &[Depth], start: Depth, span: Span) -> Depth {
+        let mut known = ends.iter().filter_map(|depth| match depth {
+            Depth::Known(depth) => Some(*depth),
+            _ => None,
+        });
+        let first = known.next();
+        if let Some(first) = first {
+            if let Some(other) = known.find(|depth| *depth != first) {
+                if let Depth::Known(start) = start {
+                    self.warn(
+                        span,
+                        WarningKind::UnbalancedBranches(first - start, other - start),
+                    );
+                }
+                return Depth::Unknown;
+            }
+        }
+        if ends.contains(&Depth::Unknown) {
+            Depth::Unknown
+        } else {
+            first.map_or(Depth::Diverged, Depth::Known)
+        }
+    }
+
+    /// Remember whether `value 'name' :=`, whose `:=` follows `before`, gives the name a plain value
+    fn track_assignment(&mut self, before: &[Operation], f: &FunctionDescriptor) {
+        let [.., value, name] = before else {
+            return;
+        };
+        let OperationKind::PushConst(index) = name.kind else {
+            return;
+        };
+        let Some(Value::String(name)) = f.constants().get(index) else {
+            return;
+        };
+        let is_plain = match &value.kind {
+            OperationKind::PushConst(_) | OperationKind::List(_) => true,
+            OperationKind::Push(value) => !matches!(value, Value::Function(_)),
+            _ => false,
+        };
+        if is_plain {
+            self.values.insert(name.clone());
+        } else {
+            self.values.remove(name);
+        }
+    }
+
+    fn warn(&mut self, span: Span, kind: WarningKind) {
+        self.warnings.push(Warning { span, kind });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn kinds(source: &str) -> Vec<WarningKind> {
+        let program = parse(source.chars()).unwrap();
+        check(&program).into_iter().map(|w| w.kind).collect()
+    }
+
+    #[test]
+    fn finds_underflows() {
+        let warnings = check(&parse("1 2 + 'x' :=\n1 +".chars()).unwrap());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].span.line, warnings[0].span.column), (2, 3));
+        assert!(matches!(
+            &warnings[0].kind,
+            WarningKind::Underflow { name, needs: 2, available: 1 } if name.as_str() == "+"
+        ));
+    }
+
+    #[test]
+    fn finds_unbalanced_blocks() {
+        assert_eq!(
+            kinds("0 1 < if 2 3 else 4 end"),
+            [WarningKind::UnbalancedBranches(2, 1)]
+        );
+        assert_eq!(
+            kinds("0 1 < while 1 0 1 < end"),
+            [WarningKind::UnbalancedLoop(1)]
+        );
+    }
+
+    #[test]
+    fn trusts_functions_and_balanced_code() {
+        assert!(kinds("1 2 + 3 * 0 < if 1 else 2 end").is_empty());
+        assert!(kinds("fn + end 'f' := 1 f").is_empty());
+    }
+}
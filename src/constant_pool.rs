@@ -0,0 +1,69 @@
+use crate::{
+    callable::CallableKind,
+    operation::{Operation, OperationKind},
+    FlyString, Value,
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    Number(u64),
+    String(FlyString),
+}
+
+/// Deduplicates the literals of one program so every function parsed from it can share a single table
+#[derive(Default)]
+pub(crate) struct ConstantPool {
+    values: Vec<Value>,
+    indices: HashMap<Key, usize>,
+}
+
+impl ConstantPool {
+    pub fn add(&mut self, value: Value) -> usize {
+        let key = match &value {
+            Value::Int(x) => Key::Int(*x),
+            Value::Number(x) => Key::Number(x.to_bits()),
+            Value::String(s) => Key::String(s.clone()),
+            _ => unreachable!("only literals are pooled"),
+        };
+        *self.indices.entry(key).or_insert_with(|| {
+            self.values.push(value);
+            self.values.len() - 1
+        })
+    }
+
+    pub fn get(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+
+    pub fn finish(self) -> Arc<[Value]> {
+        self.values.into()
+    }
+}
+
+/// Hand the finished pool to every function nested in `operations`
+pub(crate) fn share_constants(operations: &mut [Operation], constants: &Arc<[Value]>) {
+    use OperationKind as O;
+
+    for op in operations {
+        match &mut op.kind {
+            O::Push(Value::Function(f)) => {
+                let CallableKind::Function(f) = &mut f.kind else {
+                    continue;
+                };
+                let f = Arc::get_mut(f).expect("Freshly parsed functions are not shared");
+                f.constants = Arc::clone(constants);
+                share_constants(
+                    Arc::get_mut(&mut f.operations)
+                        .expect("Freshly parsed functions are not shared"),
+                    constants,
+                );
+            }
+            _ => op
+                .blocks_mut()
+                .for_each(|block| share_constants(block, constants)),
+        }
+    }
+}
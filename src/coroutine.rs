@@ -0,0 +1,60 @@
+use crate::{
+    callable::Callable,
+    execute::{ExecuteError, ExecuteOptions},
+    machine_state::MachineState,
+    vm::{Stop, Vm},
+    Value,
+};
+
+/// A function that runs a piece at a time. Every [`Coroutine::resume`] runs it until it calls `yield`,
+/// which hands the value on top of its stack back to the caller. Scripts make one with the `coroutine` builtin
+#[derive(Debug)]
+pub struct Coroutine {
+    vm: Vm,
+    finished: bool,
+}
+
+impl Coroutine {
+    /// Prepare a call of `f` with `args` on a fresh machine configured by `options`.
+    /// Nothing runs until the first [`Self::resume`]
+    pub fn new(
+        f: Callable,
+        args: Vec<Value>,
+        options: &ExecuteOptions,
+    ) -> Result<Self, ExecuteError> {
+        let mut state = options.new_state();
+        args.into_iter().for_each(|arg| state.push(arg));
+        Self::start(state, f)
+    }
+
+    /// Call `f` on `state`, taking its arguments from the stack
+    pub(crate) fn start(state: MachineState, f: Callable) -> Result<Self, ExecuteError> {
+        Ok(Self {
+            vm: Vm::calling(state, f)?,
+            finished: false,
+        })
+    }
+
+    /// Run until the next `yield`, returning the value yielded, or `None` once the function has returned.
+    /// A coroutine that returned or failed stays finished
+    pub fn resume(&mut self) -> Result<Option<Value>, ExecuteError> {
+        if self.finished {
+            return Ok(None);
+        }
+        match self.vm.run() {
+            Ok(Stop::Yielded) => Ok(Some(self.vm.state_mut().pop()?)),
+            Ok(_) => {
+                self.finished = true;
+                Ok(None)
+            }
+            Err(e) => {
+                self.finished = true;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{execute::ExecuteError, parser::ParseError, span::Span, InterpreterError};
+
+use std::fmt::Write;
+
+/// An error that can point at the source it happened in
+pub trait Diagnostic {
+    /// A stable name for the kind of error, see [`ExecuteError::code`]
+    fn code(&self) -> &'static str;
+
+    /// What went wrong, without where
+    fn message(&self) -> String;
+
+    fn span(&self) -> Option<Span>;
+
+    /// Advice on fixing the error, for mistakes where the message alone rarely helps
+    fn hint(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Where the functions the error happened in were called from, innermost first
+    fn call_sites(&self) -> &[Span] {
+        &[]
+    }
+}
+
+impl Diagnostic for ParseError {
+    fn code(&self) -> &'static str {
+        "parse"
+    }
+
+    fn message(&self) -> String {
+        self.kind().to_string()
+    }
+
+    fn span(&self) -> Option<Span> {
+        ParseError::span(self)
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        match self.kind() {
+            ParseError::InvalidString => Some("strings end with the quote they started with"),
+            ParseError::InvalidRawPush => {
+                Some("write `$name` to push a name's value without calling it")
+            }
+            ParseError::MissingThen => {
+                Some("conditions after elif end with `then`: `elif cond then ... end`")
+            }
+            ParseError::InvalidCaseLabel => {
+                Some("labels look like `of 1 then`, with one literal each")
+            }
+            ParseError::UnclosedBlock(_) => {
+                Some("fn, if, case, try, while and repeat blocks each end with their own `end`")
+            }
+            ParseError::UnmatchedBracket => Some("every [ needs a ] after it"),
+            ParseError::TypeMismatch { .. } => {
+                Some("builtins take their operands top first, so check their order")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic for ExecuteError {
+    fn code(&self) -> &'static str {
+        ExecuteError::code(self)
+    }
+
+    fn message(&self) -> String {
+        self.kind().to_string()
+    }
+
+    fn span(&self) -> Option<Span> {
+        ExecuteError::span(self)
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        match self.kind() {
+            ExecuteError::UnboundIdentifier(_) => {
+                Some("names are assigned with `value 'name' :=` before they are used")
+            }
+            ExecuteError::EmptyStack => {
+                Some("an operation needed more values than were on the stack")
+            }
+            ExecuteError::ArityMismatch { .. } => {
+                Some("a function took more arguments than were bound to it and on the stack")
+            }
+            ExecuteError::UnboundArgument(_) => {
+                Some("the function uses more arguments than it was called with")
+            }
+            ExecuteError::TypeMismatch { .. } => {
+                Some("builtins take their operands top first, so check their order")
+            }
+            ExecuteError::RecurseOutsideFunction => Some("recurse only works inside `fn ... end`"),
+            ExecuteError::YieldOutsideCoroutine => {
+                Some("wrap the function with `coroutine` to be able to yield from it")
+            }
+            ExecuteError::ContinuationOutsideCoroutine => Some(
+                "continuations only work in code a `coroutine` runs, outside of `map` and the like",
+            ),
+            _ => None,
+        }
+    }
+
+    fn call_sites(&self) -> &[Span] {
+        self.trace().map_or(&[], |trace| trace.call_sites())
+    }
+}
+
+impl Diagnostic for InterpreterError {
+    fn code(&self) -> &'static str {
+        InterpreterError::code(self)
+    }
+
+    fn message(&self) -> String {
+        self.inner().message()
+    }
+
+    fn span(&self) -> Option<Span> {
+        self.inner().span()
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        self.inner().hint()
+    }
+
+    fn call_sites(&self) -> &[Span] {
+        self.inner().call_sites()
+    }
+}
+
+impl InterpreterError {
+    fn inner(&self) -> &dyn Diagnostic {
+        match self {
+            Self::Parse(e) => e,
+            Self::Execute(e) => e,
+        }
+    }
+}
+
+/// How many call sites are listed before the rest are summed up
+const SHOWN_CALLS: usize = 10;
+
+/// Describe `error` like a compiler would: the message, then the line of `source` it happened at with the
+/// offending token underlined, a hint if there is one and the calls it happened in. `name` says where
+/// the source came from, like a file path. Errors pointing past the end of `source`, e.g. because they happened
+/// in an imported module, are shown without the line
+pub fn render(name: &str, source: &str, error: &(impl Diagnostic + ?Sized)) -> String {
+    let mut out = format!("error[{}]: {}\n", error.code(), error.message());
+    let line = error
+        .span()
+        .and_then(|span| Some((span, source.lines().nth(span.line.checked_sub(1)?)?)));
+
+    match line {
+        Some((span, text)) => {
+            let number = span.line.to_string();
+            let gutter = " ".repeat(number.len());
+            let _ = writeln!(out, "{gutter}--> {name}:{}:{}", span.line, span.column);
+            let _ = writeln!(out, "{gutter} |");
+            let _ = writeln!(out, "{number} | {text}");
+            let _ = writeln!(out, "{gutter} | {}", underline(text, span));
+        }
+        None => {
+            let _ = writeln!(out, " --> {name}");
+        }
+    }
+    if let Some(hint) = error.hint() {
+        let _ = writeln!(out, "  = hint: {hint}");
+    }
+
+    let calls = error.call_sites();
+    for span in calls.iter().take(SHOWN_CALLS) {
+        let _ = match span.line {
+            0 => writeln!(out, "  = called from the host"),
+            _ => writeln!(out, "  = called at {name}:{}:{}", span.line, span.column),
+        };
+    }
+    if calls.len() > SHOWN_CALLS {
+        let _ = writeln!(out, "  = ... and {} more calls", calls.len() - SHOWN_CALLS);
+    }
+    out
+}
+
+/// Carets under the token at `span` in its line, keeping tabs so they line up
+fn underline(line: &str, span: Span) -> String {
+    let start = span.column.saturating_sub(1);
+    let offset = line
+        .char_indices()
+        .nth(start)
+        .map_or(line.len(), |(i, _)| i);
+    let end = (offset + span.len).min(line.len());
+    let token = line
+        .get(offset..end)
+        .map_or(0, |t| t.chars().count())
+        .max(1);
+    let indent: String = line
+        .chars()
+        .take(start)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!("{indent}{}", "^".repeat(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse, vm::Vm};
+
+    #[test]
+    fn underlines_the_token_on_its_line() {
+        let source = "1 2 +\n  'abc\n";
+        let error = parse(source.chars()).unwrap_err();
+        assert_eq!(
+            render("test.ssl", source, &error),
+            "error[parse]: Unclosed string literal\n \
+             --> test.ssl:2:3\n  |\n2 |   'abc\n  |   ^^^^\n  \
+             = hint: strings end with the quote they started with\n"
+        );
+    }
+
+    #[test]
+    fn lists_the_calls_an_error_happened_in() {
+        let source = "fn 'a' 1 + end 'f' :=\nf";
+        let error = Vm::new(parse(source.chars()).unwrap(), vec![])
+            .run()
+            .unwrap_err();
+        let rendered = render("test.ssl", source, &error);
+        assert!(rendered.starts_with("error[type-mismatch]: "));
+        assert!(rendered.contains("1 | fn 'a' 1 + end 'f' :=\n  |          ^\n"));
+        assert!(rendered.ends_with("  = called at test.ssl:2:1\n"));
+    }
+
+    #[test]
+    fn leaves_out_lines_past_the_end_of_the_source() {
+        let error = parse("'abc".chars()).unwrap_err();
+        let rendered = render("module", "", &error);
+        assert!(rendered.contains(" --> module\n"));
+        assert!(!rendered.contains('^'));
+    }
+}
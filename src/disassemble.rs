@@ -0,0 +1,131 @@
+use crate::{
+    callable::{Callable, CallableKind, FunctionDescriptor},
+    format::quote,
+    operation::{Operation, OperationKind},
+    Value,
+};
+
+use std::fmt::Write;
+
+const INDENT: &str = "    ";
+
+impl FunctionDescriptor {
+    /// A listing of every operation with its index and source location. Nested blocks and
+    /// function literals are listed below the operation they belong to, indented one level
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        function(&mut out, self, 0);
+        out
+    }
+}
+
+fn function(out: &mut String, f: &FunctionDescriptor, depth: usize) {
+    let indent = INDENT.repeat(depth);
+    let _ = write!(out, "{indent}function, {} args", f.num_args);
+    if f.variadic {
+        let _ = write!(out, " and the rest as ${}", f.num_args);
+    }
+    if !f.captured_names.is_empty() {
+        let mut names: Vec<_> = f.captured_names.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        let _ = write!(out, ", captures {}", names.join(" "));
+    }
+    out.push('\n');
+    block(out, &f.operations, &f.constants, depth);
+}
+
+fn block(out: &mut String, operations: &[Operation], constants: &[Value], depth: usize) {
+    let indent = INDENT.repeat(depth);
+    for (i, op) in operations.iter().enumerate() {
+        let location = format!("{}:{}", op.span.line, op.span.column);
+        let _ = writeln!(
+            out,
+            "{indent}{i:>4}  {location:<8} {}",
+            describe(op, constants)
+        );
+        nested(out, op, constants, depth + 1);
+    }
+}
+
+fn describe(op: &Operation, constants: &[Value]) -> String {
+    use OperationKind as O;
+
+    match &op.kind {
+        O::Push(Value::Function(_)) => "push function".into(),
+        O::Push(v) => format!("push {}", literal(v)),
+        O::PushConst(index) => format!("push-const {index}  ; {}", literal(&constants[*index])),
+        O::PushId(id) => format!("push-id {id}"),
+        O::PushRaw(id) => format!("push-raw {id}"),
+        O::PushArg(index) => format!("push-arg {index}"),
+        O::Call(name, _) => format!("call builtin {name}"),
+        O::If(..) => "if".into(),
+        O::While(_) => "while".into(),
+        O::Repeat(_) => "repeat".into(),
+        O::List(_) => "list".into(),
+        O::Try(..) => "try".into(),
+        O::Case(..) => "case".into(),
+        O::Recurse => "recurse".into(),
+        O::Return => "ret".into(),
+    }
+}
+
+fn literal(value: &Value) -> String {
+    match value.as_str() {
+        Some(s) => quote(s),
+        None => value.to_string(),
+    }
+}
+
+/// List what `op` contains, labelling blocks that need telling apart
+fn nested(out: &mut String, op: &Operation, constants: &[Value], depth: usize) {
+    use OperationKind as O;
+
+    let labels: Vec<String> = match &op.kind {
+        O::Push(Value::Function(Callable {
+            kind: CallableKind::Function(f),
+            ..
+        })) => return function(out, f, depth),
+        O::If(..) => vec!["then".into(), "else".into()],
+        O::Try(..) => vec!["try".into(), "catch".into()],
+        O::Case(branches, _) => branches
+            .iter()
+            .map(|(label, _)| format!("of {}", literal(label)))
+            .chain(["else".into()])
+            .collect(),
+        _ => vec![String::new()],
+    };
+    let indent = INDENT.repeat(depth);
+    for (label, body) in labels.iter().zip(op.blocks()) {
+        if !label.is_empty() {
+            let _ = writeln!(out, "{indent}{label}:");
+        }
+        block(out, body, constants, depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+
+    #[test]
+    fn lists_operations_with_their_locations() {
+        let listing = parse("1 'x' :=\nx".chars()).unwrap().disassemble();
+        let lines: Vec<_> = listing.lines().collect();
+        assert_eq!(lines[0], "function, 0 args");
+        assert!(lines[1].trim_start().starts_with("0  1:1"));
+        assert!(lines[1].ends_with("; 1"));
+        assert!(lines[4].trim_start().starts_with("3  2:1"));
+        assert!(lines[4].ends_with("push-id x"));
+    }
+
+    #[test]
+    fn nests_functions_and_labels_blocks() {
+        let listing = parse("fn $0 end 'f' := 1 if 'a' else 'b' end".chars())
+            .unwrap()
+            .disassemble();
+        assert!(listing.contains("\n    function, 1 args\n"));
+        assert!(listing.contains("push-arg 0"));
+        assert!(listing.contains("\n    then:\n"));
+        assert!(listing.contains("\n    else:\n"));
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{execute::ExecuteError, machine_state::MachineState, FlyString, Value};
+
+use std::collections::HashMap;
+
+/// A set of builtins published on its own, like bindings to a regex or HTTP library, which embedders plug
+/// into the interpreter with [`crate::ExecuteOptions::with_builtin_module`]
+pub trait BuiltinModule {
+    /// Identifies the module, e.g. in [`crate::ExecuteOptions::builtin_modules`]
+    fn name(&self) -> &str;
+
+    fn register(&self, env: &mut Environment<'_>);
+}
+
+/// The global names a [`BuiltinModule`] registers its builtins under
+pub struct Environment<'a> {
+    builtins: &'a mut HashMap<FlyString, Value>,
+}
+
+impl<'a> Environment<'a> {
+    pub(crate) fn new(builtins: &'a mut HashMap<FlyString, Value>) -> Self {
+        Self { builtins }
+    }
+
+    /// Register a builtin, replacing any existing one with the same name
+    pub fn builtin(
+        &mut self,
+        name: impl Into<FlyString>,
+        f: impl Fn(&mut MachineState) -> Result<(), ExecuteError> + Send + Sync + 'static,
+    ) {
+        self.builtins.insert(name.into(), Value::builtin(f));
+    }
+
+    /// Define a global name bound to a value, like a constant the module provides
+    pub fn define(&mut self, name: impl Into<FlyString>, value: Value) {
+        self.builtins.insert(name.into(), value);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.builtins.contains_key(&FlyString::from(name))
+    }
+}
@@ -1,105 +1,506 @@
+#[cfg(feature = "host")]
+use crate::builtins::{get_fs_builtins, get_process_builtins, get_system_builtins};
 use crate::{
-    callable::*, machine_state::MachineState, operation::Operation, scope::Scope, FlyString, Value,
+    builtins::{get_builtins, get_host_builtins, get_stdio_builtins, get_thread_builtins},
+    bytecode::{Code, Instruction, InstructionKind},
+    callable::*,
+    environment::{BuiltinModule, Environment},
+    io::Output,
+    machine_state::{ArithmeticMode, Limit, Limits, MachineState, OperationHook},
+    module::{import_builtin, ModuleError, ModuleResolver},
+    operation::Operation,
+    prelude::PRELUDE,
+    scope::Scope,
+    span::Span,
+    FlyString, Interner, Value,
 };
 
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{atomic::AtomicBool, Arc},
+};
 
 use thiserror::Error;
 
 #[macro_export]
 macro_rules! pop_as {
     ($state:ident,$type:ident) => {{
-        let Value::$type(v) = $state.pop()? else {
-            return Err(ExecuteError::TypeMismatch(stringify!($type).into()));
-        };
-        v
+        match $state.pop()? {
+            Value::$type(v) => v,
+            value => {
+                return Err(ExecuteError::TypeMismatch {
+                    expected: stringify!($type).into(),
+                    actual: value.type_name(),
+                })
+            }
+        }
     }};
 }
 
+/// Why a program failed. Hosts can tell failures apart by [`Self::code`] and the fields of [`Self::kind`]
+/// rather than by message, which may be reworded
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum ExecuteError {
-    #[error("Type mismatch: Expected {0}")]
-    TypeMismatch(FlyString),
+    #[error("Type mismatch: Expected {expected}, got {actual}")]
+    TypeMismatch {
+        expected: FlyString,
+        actual: &'static str,
+    },
     #[error("Unbound identifier {0}")]
     UnboundIdentifier(FlyString),
     #[error("Tried to pop from empty stack")]
     EmptyStack,
     #[error("Unbound argument number {0}")]
     UnboundArgument(usize),
-    #[error("Type assertion failed: expected {0}, got {1}")]
-    InvalidType(&'static str, FlyString),
+    #[error("Type assertion failed: expected {expected}, got {actual}")]
+    InvalidType {
+        expected: FlyString,
+        actual: &'static str,
+    },
     #[error("Tried to bind too many arguments")]
     TooManyBoundArgs,
+    /// A call needed more arguments than were bound and on the stack together
+    #[error("Arity mismatch: Expected {expected} arguments, got {got}")]
+    ArityMismatch { expected: usize, got: usize },
+    #[error("Index {0} out of range for list of length {1}")]
+    IndexOutOfRange(f64, usize),
+    #[error("Integer overflow")]
+    IntegerOverflow,
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Arithmetic result is not a number")]
+    NanResult,
+    #[error("Ranges need finite bounds and a non-zero step")]
+    InvalidRange,
+    #[error("Not a number: {0:?}")]
+    NotANumber(String),
+    #[error("{0} is not a Unicode scalar value")]
+    InvalidCodePoint(i64),
+    #[error("Bytes are not valid UTF-8")]
+    InvalidUtf8,
+    #[error("Invalid hex string {0:?}")]
+    InvalidHex(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Exceeded the {0} limit of {1}")]
+    LimitExceeded(Limit, usize),
+    #[error(transparent)]
+    Module(#[from] ModuleError),
+    #[error("{0}")]
+    Thrown(Arc<str>),
+    #[error("Ran out of fuel")]
+    OutOfFuel,
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("recurse outside of a function")]
+    RecurseOutsideFunction,
+    #[error("yield outside of a coroutine, or in a function called by a builtin")]
+    YieldOutsideCoroutine,
+    #[error(
+        "call/cc or a continuation outside of a coroutine, or in a function called by a builtin"
+    )]
+    ContinuationOutsideCoroutine,
+    #[error("No handler for effect {0}")]
+    UnhandledEffect(FlyString),
+    #[error("Coroutine resumed while it is running")]
+    CoroutineRunning,
+    #[error("Assertion failed")]
+    AssertionFailed,
+    #[error("Assertion failed: {0}")]
+    AssertionFailedWith(FlyString),
+    /// Raised by `exit` to end the program, which hosts should treat as finishing with the code
+    #[error("Exited with code {0}")]
+    Exit(i32),
+    /// The verifier of the options refused to run the program, see [`ExecuteOptions::with_verifier`]
+    #[error("Program rejected: {0}")]
+    Rejected(String),
+    #[error("{1} (at {0})")]
+    At(Span, Box<ExecuteError>),
+    #[error("{1}{0}")]
+    Traced(CallTrace, Box<ExecuteError>),
+}
+
+impl ExecuteError {
+    /// Attach the location of the failing operation, unless a more precise one is already known
+    pub(crate) fn at(self, span: Span) -> Self {
+        match self {
+            Self::At(..) => self,
+            Self::Traced(trace, e) => Self::Traced(trace, Box::new(e.at(span))),
+            e => Self::At(span, Box::new(e)),
+        }
+    }
+
+    /// Attach the calls in progress, unless the error already left a function that attached them
+    pub(crate) fn traced(self, state: &MachineState) -> Self {
+        match self {
+            Self::Traced(..) => self,
+            e => Self::Traced(CallTrace(state.call_sites().rev().collect()), Box::new(e)),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::At(span, _) => Some(*span),
+            Self::Traced(_, e) => e.span(),
+            _ => None,
+        }
+    }
+
+    /// The calls the error happened in, if it happened inside a function
+    pub fn trace(&self) -> Option<&CallTrace> {
+        match self {
+            Self::Traced(trace, _) => Some(trace),
+            _ => None,
+        }
+    }
+
+    /// Whether `try` may handle the error. Running out of fuel, cancellation and `exit` always stop the program
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self.kind(),
+            Self::OutOfFuel | Self::Cancelled | Self::Exit(_) | Self::Rejected(_)
+        )
+    }
+
+    /// The code the program asked to end with, if it stopped by calling `exit`
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.kind() {
+            Self::Exit(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Names the kind of failure, which unlike the message stays the same across versions.
+    /// Location and trace don't change it
+    pub fn code(&self) -> &'static str {
+        match self.kind() {
+            Self::TypeMismatch { .. } => "type-mismatch",
+            Self::UnboundIdentifier(_) => "unbound-identifier",
+            Self::EmptyStack => "empty-stack",
+            Self::UnboundArgument(_) => "unbound-argument",
+            Self::InvalidType { .. } => "invalid-type",
+            Self::TooManyBoundArgs => "too-many-bound-args",
+            Self::ArityMismatch { .. } => "arity-mismatch",
+            Self::IndexOutOfRange(..) => "index-out-of-range",
+            Self::IntegerOverflow => "integer-overflow",
+            Self::DivisionByZero => "division-by-zero",
+            Self::NanResult => "nan-result",
+            Self::InvalidRange => "invalid-range",
+            Self::NotANumber(_) => "not-a-number",
+            Self::InvalidCodePoint(_) => "invalid-code-point",
+            Self::InvalidUtf8 => "invalid-utf8",
+            Self::InvalidHex(_) => "invalid-hex",
+            Self::Io(_) => "io",
+            Self::LimitExceeded(..) => "limit-exceeded",
+            Self::Module(_) => "module",
+            Self::Thrown(_) => "thrown",
+            Self::OutOfFuel => "out-of-fuel",
+            Self::Cancelled => "cancelled",
+            Self::RecurseOutsideFunction => "recurse-outside-function",
+            Self::YieldOutsideCoroutine => "yield-outside-coroutine",
+            Self::ContinuationOutsideCoroutine => "continuation-outside-coroutine",
+            Self::UnhandledEffect(_) => "unhandled-effect",
+            Self::CoroutineRunning => "coroutine-running",
+            Self::AssertionFailed | Self::AssertionFailedWith(_) => "assertion-failed",
+            Self::Exit(_) => "exit",
+            Self::Rejected(_) => "rejected",
+            Self::At(..) | Self::Traced(..) => unreachable!("kind() strips locations and traces"),
+        }
+    }
+
+    /// The error without its location and trace
+    pub fn kind(&self) -> &ExecuteError {
+        match self {
+            Self::At(_, e) | Self::Traced(_, e) => e.kind(),
+            e => e,
+        }
+    }
 }
 
-fn push_or_execute(state: &mut MachineState, v: Value) -> Result<(), ExecuteError> {
+/// Where each function an error happened in was called from, innermost first.
+/// Tail calls replace their caller, so they don't show up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallTrace(Vec<Span>);
+
+impl CallTrace {
+    /// How many calls are shown before the rest are summed up, so runaway recursion stays readable
+    const SHOWN: usize = 10;
+
+    pub fn call_sites(&self) -> &[Span] {
+        &self.0
+    }
+}
+
+impl fmt::Display for CallTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for span in self.0.iter().take(Self::SHOWN) {
+            match span.line {
+                0 => f.write_str("\n    called from the host")?,
+                _ => write!(f, "\n    called at {span}")?,
+            }
+        }
+        if self.0.len() > Self::SHOWN {
+            write!(f, "\n    ... and {} more calls", self.0.len() - Self::SHOWN)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn resolve(state: &MachineState, id: &FlyString) -> Result<Value, ExecuteError> {
+    state
+        .look_up(id)
+        .or_else(|| state.global_scope().get(id))
+        .map(Value::current)
+        .ok_or_else(|| ExecuteError::UnboundIdentifier(id.clone()))
+}
+
+/// Look up `id` and call it if it is a function, or push it otherwise.
+/// In tail position, SSL functions are handed back to the caller instead of being called
+fn push_or_execute(
+    state: &mut MachineState,
+    id: &FlyString,
+    tail: bool,
+) -> Result<Control, ExecuteError> {
     use Value as V;
-    match v {
-        V::Function(f) => f.execute(state)?,
-        _ => state.push(v),
+    match resolve(state, id)? {
+        V::Function(f) => {
+            if tail && matches!(f.kind, CallableKind::Function(_)) {
+                return Ok(Control::Exit(Some(f)));
+            }
+            f.execute(state)?
+        }
+        v => state.push(v),
     }
-    Ok(())
+    Ok(Control::Next)
+}
+
+/// What the code being executed belongs to
+#[derive(Clone, Copy)]
+struct Frame<'a> {
+    constants: &'a [Value],
+    /// The function being run, so `recurse` can call it again. `None` for top-level code
+    function: Option<&'a Callable>,
+}
+
+/// Where execution continues after an instruction
+enum Control {
+    Next,
+    /// Jump by an offset from the instruction
+    Jump(isize),
+    /// Leave the code, handing back the function to run in its place if it ended in a tail call
+    Exit(Option<Callable>),
 }
 
-fn execute_function_code(
+/// A `try` whose body is running
+struct Handler {
+    /// The instruction its handler starts at
+    start: usize,
+    scope_depth: usize,
+    stack_len: usize,
+    lists: usize,
+    repeats: usize,
+}
+
+/// What running a piece of code keeps track of besides the machine
+#[derive(Default)]
+struct Blocks {
+    /// The stack length at the start of every unfinished list literal
+    lists: Vec<usize>,
+    /// The iterations left of every unfinished `repeat`
+    repeats: Vec<i64>,
+    handlers: Vec<Handler>,
+}
+
+/// Run one instruction at `pc`
+fn execute_instruction(
     state: &mut MachineState,
-    operations: &Vec<Operation>,
-) -> Result<bool, ExecuteError> {
-    use Operation as O;
-
-    let mut i = 0;
-    while let Some(op) = operations.get(i) {
-        //println!("{op:?}");
-        match op {
-            O::Push(v) => state.push(v.clone()),
-            O::PushId(id) => {
-                if let Some(v) = state.look_up(&id) {
-                    push_or_execute(state, v)?;
-                } else if let Some(v) = state.global_scope().get(&id) {
-                    push_or_execute(state, v)?;
-                } else {
-                    return Err(ExecuteError::UnboundIdentifier(id.clone()));
-                }
+    instruction: &Instruction,
+    pc: usize,
+    frame: Frame,
+    blocks: &mut Blocks,
+) -> Result<Control, ExecuteError> {
+    use InstructionKind as I;
+
+    match &instruction.kind {
+        I::Push(v) => state.push(v.clone()),
+        I::PushConst(index) => state.push(frame.constants[*index].clone()),
+        I::PushId(id) => return push_or_execute(state, id, instruction.tail),
+        I::PushRaw(id) => state.push(resolve(state, id)?),
+        I::PushArg(index) => state.push(state.get_arg(*index)?.clone()),
+        I::Call(f) => f.execute(state)?,
+        I::Branch(offset) => {
+            let condition = pop_as!(state, Bool);
+            state.push_scope(Scope::conditional());
+            if !condition {
+                return Ok(Control::Jump(*offset as isize));
             }
-            O::PushRaw(id) => {
-                if let Some(v) = state.look_up(&id) {
-                    state.push(v);
-                } else if let Some(v) = state.global_scope().get(&id) {
-                    state.push(v);
-                } else {
-                    return Err(ExecuteError::UnboundIdentifier(id.clone()));
-                }
+        }
+        I::JumpUnless(offset) => {
+            if !pop_as!(state, Bool) {
+                return Ok(Control::Jump(*offset as isize));
             }
-            O::PushArg(index) => state.push(state.get_arg(*index)?),
-            O::If(if_body, else_body) => {
-                let condition = pop_as!(state, Bool);
-                if condition {
-                    state.push_scope(Scope::conditional());
-                    let do_return = execute_function_code(state, &if_body)?;
-                    state.pop_scope();
-                    if do_return {
-                        return Ok(true);
-                    }
-                } else {
-                    assert!(else_body.len() == 0);
-                }
+        }
+        I::Jump(offset) => return Ok(Control::Jump(*offset)),
+        I::Case(branches, default) => {
+            let subject = state.pop()?;
+            let offset = branches
+                .iter()
+                .find(|(label, _)| *label == subject)
+                .map_or(*default, |(_, offset)| *offset);
+            state.push_scope(Scope::conditional());
+            return Ok(Control::Jump(offset as isize));
+        }
+        I::EnterScope => state.push_scope(Scope::conditional()),
+        I::BeginRepeat => begin_repeat(state, blocks)?,
+        I::Repeat(offset) => return Ok(blocks.repeat(*offset)),
+        I::ExitScope => {
+            state.pop_scope();
+        }
+        I::BeginList => {
+            blocks.lists.push(state.stack_len());
+            state.push_scope(Scope::conditional());
+        }
+        I::EndList => {
+            state.pop_scope();
+            let depth = blocks.lists.pop().expect("Lists are balanced");
+            let items = state.split_stack(depth);
+            state.push_new(items.into());
+        }
+        I::BeginTry(offset) => {
+            blocks.handlers.push(Handler {
+                start: pc + offset,
+                scope_depth: state.scope_depth(),
+                stack_len: state.stack_len(),
+                lists: blocks.lists.len(),
+                repeats: blocks.repeats.len(),
+            });
+            state.push_scope(Scope::conditional());
+        }
+        I::EndTry(offset) => {
+            blocks.handlers.pop();
+            state.pop_scope();
+            return Ok(Control::Jump(*offset as isize));
+        }
+        I::Recurse => {
+            let function = frame.function.ok_or(ExecuteError::RecurseOutsideFunction)?;
+            if instruction.tail {
+                return Ok(Control::Exit(Some(function.clone())));
             }
-            O::Return => return Ok(true),
+            function.execute(state)?
         }
-        i += 1;
+        I::Return => return Ok(Control::Exit(None)),
     }
+    Ok(Control::Next)
+}
 
-    Ok(false)
+fn begin_repeat(state: &mut MachineState, blocks: &mut Blocks) -> Result<(), ExecuteError> {
+    let count = pop_as!(state, Int);
+    blocks.repeats.push(count);
+    state.push_scope(Scope::conditional());
+    Ok(())
 }
 
-pub(crate) fn execute_function(
+impl Blocks {
+    /// Count down an iteration of the innermost `repeat`, or leave it by `offset` if none are left
+    fn repeat(&mut self, offset: usize) -> Control {
+        let left = self.repeats.last_mut().expect("Repeats are balanced");
+        if *left <= 0 {
+            self.repeats.pop();
+            return Control::Jump(offset as isize);
+        }
+        *left -= 1;
+        Control::Next
+    }
+}
+
+/// Continue in the handler of the innermost `try` around the failure, undoing what its body did.
+/// Returns the instruction to continue at, or the error if nothing handles it
+fn catch(
+    state: &mut MachineState,
+    error: ExecuteError,
+    blocks: &mut Blocks,
+) -> Result<usize, ExecuteError> {
+    if !error.is_catchable() {
+        return Err(error);
+    }
+    let Some(handler) = blocks.handlers.pop() else {
+        return Err(error);
+    };
+    state.restore(handler.scope_depth, handler.stack_len);
+    blocks.lists.truncate(handler.lists);
+    blocks.repeats.truncate(handler.repeats);
+    state.push_new(Value::Error(error.kind().to_string().into()));
+    state.push_scope(Scope::conditional());
+    Ok(handler.start)
+}
+
+/// Run compiled code, returning the function to run next if it ended in a tail call.
+/// Scopes opened by its blocks are closed however it ends, except by an error
+fn execute_code(
+    state: &mut MachineState,
+    code: &Code,
+    frame: Frame,
+) -> Result<Option<Callable>, ExecuteError> {
+    let scope_depth = state.scope_depth();
+    let mut blocks = Blocks::default();
+    let mut pc = 0;
+    while let Some(instruction) = code.instructions.get(pc) {
+        let op = &code.operations[instruction.op];
+        let result = if instruction.begins {
+            state
+                .begin_operation(op)
+                .and_then(|()| state.check_stack_size())
+                .and_then(|()| execute_instruction(state, instruction, pc, frame, &mut blocks))
+        } else {
+            execute_instruction(state, instruction, pc, frame, &mut blocks)
+        };
+        pc = match result {
+            Ok(Control::Next) => pc + 1,
+            Ok(Control::Jump(offset)) => pc.wrapping_add_signed(offset),
+            Ok(Control::Exit(next)) => {
+                state.truncate_scopes(scope_depth);
+                return Ok(next);
+            }
+            Err(e) => catch(state, e.at(op.span), &mut blocks)?,
+        };
+    }
+    Ok(None)
+}
+
+/// Gather the arguments after the numbered ones of a variadic function into the list `$*` reads
+pub(crate) fn pack_rest(f: &FunctionDescriptor, mut args: Vec<Value>) -> Vec<Value> {
+    if f.variadic && args.len() >= f.num_args {
+        let rest = args.split_off(f.num_args);
+        args.push(rest.into());
+    }
+    args
+}
+
+/// Take the arguments of `f` off the stack and open its scope.
+/// Variadic functions first take the number of extra arguments from the top of the stack
+pub(crate) fn enter_function(
     state: &mut MachineState,
+    callable: &Callable,
     f: &FunctionDescriptor,
-    bound_args: &Vec<Value>,
 ) -> Result<(), ExecuteError> {
+    let bound_args = &callable.bound_arguments;
     let mut args = VecDeque::default();
 
-    let args_to_pop = f.num_args - bound_args.len();
+    let mut expected = f.num_args;
+    if f.variadic {
+        expected +=
+            usize::try_from(pop_as!(state, Int)).map_err(|_| ExecuteError::TypeMismatch {
+                expected: "non-negative integer".into(),
+                actual: "int",
+            })?;
+    }
+    let got = bound_args.len() + state.stack_len();
+    let args_to_pop = expected
+        .checked_sub(bound_args.len())
+        .filter(|_| got >= expected)
+        .ok_or(ExecuteError::ArityMismatch { expected, got })?;
     for _ in 0..args_to_pop {
         args.push_front(state.pop()?);
     }
@@ -110,18 +511,459 @@ pub(crate) fn execute_function(
         .cloned()
         .for_each(|x| args.push_front(x));
 
-    state.push_scope(Scope::function(args.into(), f.captured_names.clone()));
-    execute_function_code(state, &f.operations)?;
+    state.check_scope_depth()?;
+    let args = pack_rest(f, args.into());
+    state.push_scope(Scope::function(args, Arc::clone(&f.captured_names)));
+    Ok(())
+}
+
+/// Run one call of `f` in a fresh scope, returning the function to run next if it ended in a tail call
+fn call_once(
+    state: &mut MachineState,
+    callable: &Callable,
+    f: &FunctionDescriptor,
+) -> Result<Option<Callable>, ExecuteError> {
+    enter_function(state, callable, f)?;
+    let frame = Frame {
+        constants: &f.constants,
+        function: Some(callable),
+    };
+    state.enter_body(f.span);
+    let next = execute_code(state, f.code(), frame);
+    state.leave_body();
+    let next = next?;
     state.pop_scope();
+    Ok(next)
+}
+
+/// Call a function, remembering where it was called from for the trace of errors it fails with
+pub(crate) fn execute_function(
+    state: &mut MachineState,
+    callable: &Callable,
+    f: &FunctionDescriptor,
+) -> Result<(), ExecuteError> {
+    state.enter_call();
+    let result = execute_calls(state, callable, f).map_err(|e| e.traced(state));
+    state.leave_call();
+    result
+}
+
+/// Tail calls reuse this loop instead of growing the Rust stack and the scope stack
+fn execute_calls(
+    state: &mut MachineState,
+    callable: &Callable,
+    f: &FunctionDescriptor,
+) -> Result<(), ExecuteError> {
+    let mut next = call_once(state, callable, f)?;
+    while let Some(callable) = next {
+        next = match &callable.kind {
+            CallableKind::Function(f) => call_once(state, &callable, f)?,
+            CallableKind::Builtin(_) => {
+                callable.execute(state)?;
+                None
+            }
+        };
+    }
     Ok(())
 }
 
+/// Run a program on a machine that already has a global scope, like one returned by [`execute`] or made with
+/// [`ExecuteOptions::new_state`]. The stack and global definitions carry over from earlier runs, so a program
+/// can be fed in piece by piece, as in a REPL. Scopes a failed run opened are closed again, but what it pushed stays
+pub fn execute_in(
+    state: &mut MachineState,
+    main_function: &FunctionDescriptor,
+) -> Result<(), ExecuteError> {
+    let scope_depth = state.scope_depth();
+    let interner = state.interner().clone();
+    let result = interner.enter(|| execute_main(state, main_function));
+    if result.is_err() {
+        state.truncate_scopes(scope_depth);
+    }
+    result
+}
+
+fn execute_main(
+    state: &mut MachineState,
+    main_function: &FunctionDescriptor,
+) -> Result<(), ExecuteError> {
+    let frame = Frame {
+        constants: &main_function.constants,
+        function: None,
+    };
+    // A call right before a top-level `ret` is reported as a tail call too
+    if let Some(f) = execute_code(state, main_function.code(), frame)? {
+        f.execute(state)?;
+    }
+    Ok(())
+}
+
+/// Which groups of builtins a program gets, so embedders can tell what an untrusted script is able to touch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Computation only. Nothing is printed or read, no threads are started, and the host is out of reach
+    Pure,
+    /// `.`, `read-line` and `read-number` on the configured output and input, and `spawn` and channels,
+    /// but nothing else of the host
+    #[default]
+    Stdio,
+    /// Also the file system, environment variables and other programs. Without the `host` feature this
+    /// is the same as [`Profile::Stdio`]
+    Full,
+}
+
+/// The builtins a program can see. Everything that reaches outside the interpreter is off by default
+#[derive(Debug, Clone)]
+pub struct ExecuteOptions {
+    builtins: HashMap<FlyString, Value>,
+    output: Output,
+    limits: Limits,
+    arithmetic: ArithmeticMode,
+    on_op: Option<OperationHook>,
+    fuel: Option<u64>,
+    cancel: Option<Arc<AtomicBool>>,
+    seed: Option<u64>,
+    interner: Option<Interner>,
+    /// Names of the [`BuiltinModule`]s registered so far
+    modules: Vec<String>,
+    type_check: bool,
+    resolve_builtins: bool,
+    verifier: Option<Verifier>,
+}
+
+/// Decides whether a program may run, see [`ExecuteOptions::with_verifier`]
+#[derive(Clone)]
+struct Verifier(Arc<VerifyFn>);
+
+type VerifyFn = dyn Fn(&FunctionDescriptor) -> Result<(), String> + Send + Sync;
+
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Verifier")
+    }
+}
+
+impl ExecuteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the `import` builtin, which loads modules through `resolver`
+    pub fn with_module_resolver(
+        mut self,
+        resolver: impl ModuleResolver + Send + Sync + 'static,
+    ) -> Self {
+        self.builtins
+            .insert("import".into(), Value::builtin(import_builtin(resolver)));
+        self
+    }
+
+    /// Define the helpers from the bundled SSL prelude, like `neg`, `sum` and `reverse`
+    pub fn with_prelude(mut self) -> Self {
+        self.builtins.extend(PRELUDE.iter().cloned());
+        self
+    }
+
+    /// Register exactly the builtin groups `profile` allows, replacing those of earlier calls and of
+    /// [`Self::with_fs_access`] and friends. Other builtins, like ones the host registered, are kept
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        let stdio = get_stdio_builtins();
+        let threads = get_thread_builtins();
+        let host = get_host_builtins();
+        for (name, _) in stdio.iter().chain(&threads).chain(&host) {
+            self.builtins.remove(name);
+        }
+        if profile != Profile::Pure {
+            self.builtins.extend(stdio);
+            self.builtins.extend(threads);
+        }
+        if profile == Profile::Full {
+            self.builtins.extend(host);
+        }
+        self
+    }
+
+    /// Register the `file-*` builtins
+    #[cfg(feature = "host")]
+    pub fn with_fs_access(mut self) -> Self {
+        self.builtins.extend(get_fs_builtins());
+        self
+    }
+
+    /// Register `env`, which reads the host's environment variables
+    #[cfg(feature = "host")]
+    pub fn with_system_access(mut self) -> Self {
+        self.builtins.extend(get_system_builtins());
+        self
+    }
+
+    /// Register `exec`, which runs other programs with the host's privileges
+    #[cfg(feature = "host")]
+    pub fn with_process_access(mut self) -> Self {
+        self.builtins.extend(get_process_builtins());
+        self
+    }
+
+    /// Register a host-defined builtin, replacing any existing one with the same name
+    pub fn with_builtin(
+        mut self,
+        name: impl Into<FlyString>,
+        f: impl Fn(&mut MachineState) -> Result<(), ExecuteError> + Send + Sync + 'static,
+    ) -> Self {
+        self.builtins.insert(name.into(), Value::builtin(f));
+        self
+    }
+
+    /// Register everything a [`BuiltinModule`] provides, replacing builtins with the same names
+    pub fn with_builtin_module(mut self, module: impl BuiltinModule) -> Self {
+        module.register(&mut Environment::new(&mut self.builtins));
+        self.modules.push(module.name().to_string());
+        self
+    }
+
+    /// The names of the builtin modules registered, in order
+    pub fn builtin_modules(&self) -> impl Iterator<Item = &str> {
+        self.modules.iter().map(String::as_str)
+    }
+
+    pub fn without_builtin(mut self, name: impl Into<FlyString>) -> Self {
+        self.builtins.remove(&name.into());
+        self
+    }
+
+    /// Send what `.` prints to `output` instead of stdout
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_max_scope_depth(mut self, depth: usize) -> Self {
+        self.limits.max_scope_depth = depth;
+        self
+    }
+
+    pub fn with_max_stack_size(mut self, size: usize) -> Self {
+        self.limits.max_stack_size = size;
+        self
+    }
+
+    pub fn with_arithmetic(mut self, mode: ArithmeticMode) -> Self {
+        self.arithmetic = mode;
+        self
+    }
+
+    /// Call `f` before every operation the program runs
+    pub fn with_on_op(mut self, f: impl FnMut(&Operation, &MachineState) + Send + 'static) -> Self {
+        self.on_op = Some(OperationHook::new(f));
+        self
+    }
+
+    /// Stop programs with [`ExecuteError::OutOfFuel`] once they have run `fuel` operations
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Stop programs with [`ExecuteError::Cancelled`] before the next operation once `token` is set
+    pub fn with_cancel_token(mut self, token: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Make `rand` and `rand-int` give the same numbers on every run instead of differently seeded ones
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Intern the strings of every machine made with these options in `interner`, instead of a table of their own
+    pub fn with_interner(mut self, interner: Interner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+
+    /// Check every program before it runs, e.g. that it was loaded with a trusted
+    /// [`signature`](FunctionDescriptor::signature). Returning an error stops the program with
+    /// [`ExecuteError::Rejected`] before any of it executes. Runs of an [`Interpreter`](crate::Interpreter) and
+    /// [`execute_with`] are checked, code a [`crate::Vm`] steps through isn't
+    pub fn with_verifier(
+        mut self,
+        f: impl Fn(&FunctionDescriptor) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.verifier = Some(Verifier(Arc::new(f)));
+        self
+    }
+
+    /// Ask the verifier of [`Self::with_verifier`], if any, whether `program` may run
+    pub fn verify(&self, program: &FunctionDescriptor) -> Result<(), ExecuteError> {
+        match &self.verifier {
+            Some(Verifier(f)) => f(program).map_err(ExecuteError::Rejected),
+            None => Ok(()),
+        }
+    }
+
+    /// Make [`Interpreter::compile`](crate::Interpreter::compile) reject programs that
+    /// [`type_check`](crate::typecheck::type_check) finds certain type mismatches in
+    pub fn with_type_check(mut self) -> Self {
+        self.type_check = true;
+        self
+    }
+
+    pub(crate) fn type_checks(&self) -> bool {
+        self.type_check
+    }
+
+    /// Make [`Interpreter::compile`](crate::Interpreter::compile) bind the builtins a program names ahead of
+    /// time, as [`resolve_builtins`](crate::optimize::resolve_builtins) does. Redefining one of them after the
+    /// program was compiled, say in a module it imports, doesn't change what it calls, so this is off by default
+    pub fn with_resolved_builtins(mut self) -> Self {
+        self.resolve_builtins = true;
+        self
+    }
+
+    pub(crate) fn resolves_builtins(&self) -> bool {
+        self.resolve_builtins
+    }
+
+    /// A fresh machine to run programs on with [`execute_in`], without any arguments
+    pub fn new_state(&self) -> MachineState {
+        let mut state = MachineState::default();
+        self.apply(&mut state);
+        state.push_scope(Scope::global(vec![], self.builtins()));
+        state
+    }
+
+    /// Prepare a fresh machine to run with these options
+    pub(crate) fn apply(&self, state: &mut MachineState) {
+        state.set_output(self.output.clone());
+        state.set_limits(self.limits);
+        state.set_arithmetic(self.arithmetic);
+        state.set_on_op(self.on_op.clone());
+        state.set_fuel(self.fuel);
+        state.set_cancel_token(self.cancel.clone());
+        state.set_builtin_names(&self.builtins);
+        if let Some(interner) = &self.interner {
+            state.set_interner(interner.clone());
+        }
+        if let Some(seed) = self.seed {
+            state.set_seed(seed);
+        }
+    }
+
+    pub(crate) fn builtins(&self) -> HashMap<FlyString, Value> {
+        self.builtins.clone()
+    }
+}
+
+impl Default for ExecuteOptions {
+    fn default() -> Self {
+        Self {
+            builtins: get_builtins()
+                .into_iter()
+                .chain(get_stdio_builtins())
+                .chain(get_thread_builtins())
+                .collect(),
+            output: Output::stdout(),
+            limits: Limits::default(),
+            arithmetic: ArithmeticMode::default(),
+            on_op: None,
+            fuel: None,
+            cancel: None,
+            seed: None,
+            interner: None,
+            modules: vec![],
+            type_check: false,
+            resolve_builtins: false,
+            verifier: None,
+        }
+    }
+}
+
+/// Run a program on a fresh machine. Prefer [`crate::Interpreter`], which can also be configured and reused
+#[deprecated(note = "use Interpreter")]
 pub fn execute(
     main_function: &FunctionDescriptor,
     input_args: Vec<Value>,
 ) -> Result<MachineState, ExecuteError> {
-    let mut state = MachineState::default();
-    state.push_scope(Scope::global(input_args));
-    execute_function_code(&mut state, &main_function.operations)?;
+    execute_with(main_function, input_args, &ExecuteOptions::default())
+}
+
+/// Run a program on a fresh machine with the builtins configured in `options`
+pub fn execute_with(
+    main_function: &FunctionDescriptor,
+    input_args: Vec<Value>,
+    options: &ExecuteOptions,
+) -> Result<MachineState, ExecuteError> {
+    options.verify(main_function)?;
+    let mut state = options.new_state();
+    state
+        .global_scope_mut()
+        .set_args(pack_rest(main_function, input_args));
+    execute_in(&mut state, main_function)?;
     Ok(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn run(source: &str, options: ExecuteOptions) -> Result<MachineState, ExecuteError> {
+        execute_with(&parse(source.chars()).unwrap(), vec![], &options)
+    }
+
+    #[test]
+    fn the_hook_sees_every_operation_before_it_runs() {
+        let seen = Arc::new(std::sync::Mutex::new(vec![]));
+        let log = Arc::clone(&seen);
+        let options = ExecuteOptions::default().with_on_op(move |op, state| {
+            log.lock()
+                .unwrap()
+                .push((op.span.column, state.stack().count()));
+        });
+        run("1 2 +", options).unwrap();
+        assert_eq!(*seen.lock().unwrap(), [(1, 0), (3, 1), (5, 2)]);
+    }
+
+    #[test]
+    fn fuel_runs_out_after_that_many_operations() {
+        assert!(run("1 2 +", ExecuteOptions::default().with_fuel(3)).is_ok());
+        let error = run("1 2 +", ExecuteOptions::default().with_fuel(2)).unwrap_err();
+        assert!(matches!(error.kind(), ExecuteError::OutOfFuel));
+        let error = run(
+            "1 0 < while 1 0 < end",
+            ExecuteOptions::default().with_fuel(1000),
+        );
+        assert!(matches!(error.unwrap_err().kind(), ExecuteError::OutOfFuel));
+    }
+
+    #[test]
+    fn setting_the_token_stops_the_program_before_its_next_operation() {
+        let token = Arc::new(AtomicBool::new(false));
+        let (set, ops) = (Arc::clone(&token), Arc::new(AtomicUsize::new(0)));
+        let counted = Arc::clone(&ops);
+        let options = ExecuteOptions::default()
+            .with_cancel_token(Arc::clone(&token))
+            .with_on_op(move |_, _| {
+                if counted.fetch_add(1, Ordering::Relaxed) == 10 {
+                    set.store(true, Ordering::Relaxed);
+                }
+            });
+        let error = run("1 0 < while 1 0 < end", options).unwrap_err();
+        assert!(matches!(error.kind(), ExecuteError::Cancelled));
+        assert_eq!(ops.load(Ordering::Relaxed), 11);
+
+        let options = ExecuteOptions::default().with_cancel_token(token);
+        assert!(matches!(
+            run("1", options).unwrap_err().kind(),
+            ExecuteError::Cancelled
+        ));
+    }
+}
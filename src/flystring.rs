@@ -1,9 +1,31 @@
-use std::{collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard},
+};
 
-use once_cell::unsync::Lazy;
+use once_cell::sync::Lazy;
 
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct FlyString(Rc<str>);
+#[derive(Clone)]
+pub struct FlyString(Arc<str>);
+
+// Strings interned in the same table share their allocation, so comparing those is a pointer comparison.
+// Strings of different tables, e.g. of two interpreters, still compare by their text
+impl PartialEq for FlyString {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for FlyString {}
+
+impl Hash for FlyString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
 
 impl std::fmt::Debug for FlyString {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -12,42 +34,129 @@ impl std::fmt::Debug for FlyString {
 }
 
 impl FlyString {
-    fn from_string(s: String) -> Self {
-        let strings = Self::interned_strings();
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 
-        if let Some(s) = strings.get(&s) {
-            return Self(Rc::clone(s));
-        }
-        let s = Rc::clone(strings.entry(s.clone()).or_insert(s.into()));
-        Self(s)
+    /// Intern `s` in the table of the machine running on this thread, or the global one outside of any
+    fn intern(s: &str) -> Self {
+        CURRENT.with_borrow(|current| match current {
+            Some(interner) => interner.intern(s),
+            None => GLOBAL.intern(s),
+        })
+    }
+}
+
+thread_local! {
+    /// The table of the machine that is running or compiling on this thread, see [`Interner::enter`]
+    static CURRENT: RefCell<Option<Interner>> = const { RefCell::new(None) };
+}
+
+/// Where strings go while no machine's table is in use, like the names of the builtins
+static GLOBAL: Lazy<Interner> = Lazy::new(Interner::new);
+
+/// A table [`FlyString`]s are interned in. Every [`crate::MachineState`] has its own unless
+/// [`crate::ExecuteOptions::with_interner`] gives several the same, so interpreters don't contend on one table
+/// or keep each other's strings alive. Clones share the table
+#[derive(Clone, Default)]
+pub struct Interner(Arc<Mutex<HashSet<Arc<str>>>>);
+
+/// How much memory the interned strings take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternerStats {
+    /// Strings in the table, in use or not
+    pub len: usize,
+    /// Strings the table has room for without growing
+    pub capacity: usize,
+    /// The combined length of the strings
+    pub bytes: usize,
+    /// Strings no [`FlyString`] refers to any more, which [`Interner::clear`] frees
+    pub unused: usize,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    fn from_str(s: &str) -> Self {
-        let strings = Self::interned_strings();
+    /// The table used outside of any machine, e.g. by [`crate::parser::parse`] and for the names of builtins
+    pub fn global() -> Self {
+        GLOBAL.clone()
+    }
+
+    pub fn intern(&self, s: &str) -> FlyString {
+        let mut strings = self.strings();
 
         if let Some(s) = strings.get(s) {
-            return Self(Rc::clone(s));
+            return FlyString(Arc::clone(s));
         }
-        let s = Rc::clone(strings.entry(s.into()).or_insert(s.into()));
-        Self(s)
+        let s: Arc<str> = s.into();
+        strings.insert(Arc::clone(&s));
+        FlyString(s)
     }
 
-    fn interned_strings() -> &'static mut HashMap<String, Rc<str>> {
-        static mut STRINGS: Lazy<HashMap<String, Rc<str>>> = Lazy::new(HashMap::default);
-        //SAFETY: we only create FlyStrings on one thread
-        unsafe { &mut STRINGS }
+    /// Run `f` with every [`FlyString`] made from text on this thread interned here
+    pub fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        struct Guard(Option<Interner>);
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                CURRENT.set(self.0.take());
+            }
+        }
+
+        let _guard = Guard(CURRENT.replace(Some(self.clone())));
+        f()
+    }
+
+    fn strings(&self) -> MutexGuard<'_, HashSet<Arc<str>>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> InternerStats {
+        let strings = self.strings();
+        InternerStats {
+            len: strings.len(),
+            capacity: strings.capacity(),
+            bytes: strings.iter().map(|s| s.len()).sum(),
+            unused: strings.iter().filter(|s| Arc::strong_count(s) == 1).count(),
+        }
+    }
+
+    /// Free every string no [`FlyString`] refers to any more and give the spare capacity back, returning how
+    /// many strings were freed. Strings still in use stay, so they keep sharing their allocation with new ones
+    pub fn clear(&self) -> usize {
+        let mut strings = self.strings();
+        let len = strings.len();
+        strings.retain(|s| Arc::strong_count(s) > 1);
+        strings.shrink_to_fit();
+        len - strings.len()
+    }
+}
+
+impl std::fmt::Debug for Interner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interner({})", self.len())
     }
 }
 
 impl From<String> for FlyString {
     fn from(value: String) -> Self {
-        Self::from_string(value)
+        Self::intern(&value)
     }
 }
 
 impl From<&str> for FlyString {
     fn from(value: &str) -> Self {
-        Self::from_str(value)
+        Self::intern(value)
     }
 }
 
@@ -62,3 +171,46 @@ impl Display for FlyString {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn tables_are_separate_but_strings_compare_by_text() {
+        let (a, b) = (Interner::new(), Interner::new());
+        let x = a.intern("interner test string");
+        assert_eq!(a.len(), 1);
+        assert!(b.is_empty());
+
+        let y = b.intern("interner test string");
+        assert_eq!(x, y);
+        assert!(!Arc::ptr_eq(&x.0, &y.0));
+        assert!(Arc::ptr_eq(&x.0, &a.intern("interner test string").0));
+    }
+
+    #[test]
+    fn entered_table_gets_new_strings() {
+        let interner = Interner::new();
+        let s = interner.enter(|| FlyString::from("entered string"));
+        assert_eq!(interner.len(), 1);
+        drop(s);
+        assert_eq!(interner.stats().unused, 1);
+        assert_eq!(interner.clear(), 1);
+        assert!(interner.is_empty());
+    }
+
+    #[test]
+    fn interpreters_keep_their_strings_apart() {
+        let mut a = Interpreter::new();
+        let b = Interpreter::new();
+        a.repl_step("'only in a' 'name-only-in-a' :=").unwrap();
+        let has = |interpreter: &Interpreter, s: &str| {
+            interpreter.state().interner().strings().contains(s)
+        };
+        assert!(has(&a, "only in a") && has(&a, "name-only-in-a"));
+        assert!(!has(&b, "only in a"));
+        assert!(!Interner::global().strings().contains("only in a"));
+    }
+}
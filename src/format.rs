@@ -0,0 +1,224 @@
+use crate::{
+    callable::{Callable, CallableKind, FunctionDescriptor},
+    operation::{Operation, OperationKind},
+    Value,
+};
+
+/// Blocks whose body fits on one line this long stay on the line that opens them
+const MAX_INLINE_BODY: usize = 60;
+const INDENT: &str = "    ";
+
+/// Turn a program back into canonical source: tokens separated by single spaces, a new line after
+/// every assignment, and blocks that don't fit on one line indented between their keywords.
+/// Values without a literal form, which only the optimizer introduces, are written as `.` prints them
+pub fn format(f: &FunctionDescriptor) -> String {
+    let mut out = String::new();
+    for line in lines(&f.operations, &f.constants, rest_index(f)) {
+        out += &line;
+        out.push('\n');
+    }
+    out
+}
+
+/// Where `$*` reads the rest list from
+fn rest_index(f: &FunctionDescriptor) -> Option<usize> {
+    f.variadic.then_some(f.num_args)
+}
+
+/// The lines of a block, without indentation
+fn lines(operations: &[Operation], constants: &[Value], rest: Option<usize>) -> Vec<String> {
+    let mut writer = Writer {
+        rest,
+        ..Default::default()
+    };
+    for op in operations {
+        writer.operation(op, constants);
+    }
+    writer.finish()
+}
+
+#[derive(Default)]
+struct Writer {
+    lines: Vec<String>,
+    current: String,
+    rest: Option<usize>,
+}
+
+impl Writer {
+    fn token(&mut self, token: &str) {
+        if !self.current.is_empty() {
+            self.current.push(' ');
+        }
+        self.current += token;
+    }
+
+    fn break_line(&mut self) {
+        if !self.current.is_empty() {
+            self.lines.push(std::mem::take(&mut self.current));
+        }
+    }
+
+    /// Write `body` after the keyword already written, either on the same line or indented below it.
+    /// Either way, the keyword closing it can simply follow as a token
+    fn block(&mut self, body: Vec<String>) {
+        match body.as_slice() {
+            [] => {}
+            [line] if line.len() <= MAX_INLINE_BODY => self.token(line),
+            _ => {
+                self.break_line();
+                self.lines
+                    .extend(body.into_iter().map(|line| format!("{INDENT}{line}")));
+            }
+        }
+    }
+
+    fn operation(&mut self, op: &Operation, constants: &[Value]) {
+        use OperationKind as O;
+
+        match &op.kind {
+            O::Push(v) => self.value(v),
+            O::PushConst(index) => self.value(&constants[*index]),
+            O::PushId(id) | O::Call(id, _) => {
+                self.token(id.as_str());
+                if *id == ":=" || *id == ":=global" {
+                    self.break_line();
+                }
+            }
+            O::PushRaw(id) => self.token(&format!("${id}")),
+            O::PushArg(index) if Some(*index) == self.rest => self.token("$*"),
+            O::PushArg(index) => self.token(&format!("${index}")),
+            O::If(if_body, else_body) => {
+                self.token("if");
+                self.block(lines(if_body, constants, self.rest));
+                if !else_body.is_empty() {
+                    self.token("else");
+                    self.block(lines(else_body, constants, self.rest));
+                }
+                self.token("end");
+            }
+            O::While(body) => {
+                self.token("while");
+                self.block(lines(body, constants, self.rest));
+                self.token("end");
+            }
+            O::Repeat(body) => {
+                self.token("repeat");
+                self.block(lines(body, constants, self.rest));
+                self.token("end");
+            }
+            O::List(body) => {
+                self.token("[");
+                self.block(lines(body, constants, self.rest));
+                self.token("]");
+            }
+            O::Try(body, handler) => {
+                self.token("try");
+                self.block(lines(body, constants, self.rest));
+                if !handler.is_empty() {
+                    self.token("catch");
+                    self.block(lines(handler, constants, self.rest));
+                }
+                self.token("end");
+            }
+            O::Case(branches, default) => {
+                self.token("case");
+                for (label, body) in branches {
+                    self.token("of");
+                    self.value(label);
+                    self.token("then");
+                    self.block(lines(body, constants, self.rest));
+                }
+                if !default.is_empty() {
+                    self.token("else");
+                    self.block(lines(default, constants, self.rest));
+                }
+                self.token("end");
+            }
+            O::Recurse => self.token("recurse"),
+            O::Return => self.token("ret"),
+        }
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Nil => self.token("nil"),
+            Value::Int(x) => self.token(&x.to_string()),
+            // Debug keeps the fractional part that tells floats from ints
+            Value::Number(x) => self.token(&format!("{x:?}")),
+            Value::String(_) | Value::OwnedString(_) => {
+                self.token(&quote(value.as_str().unwrap_or_default()))
+            }
+            Value::List(items) => {
+                self.token("[");
+                for item in items.iter() {
+                    self.value(item);
+                }
+                self.token("]");
+            }
+            Value::Function(Callable {
+                kind: CallableKind::Function(f),
+                ..
+            }) => {
+                self.token("fn");
+                self.block(lines(&f.operations, &f.constants, rest_index(f)));
+                self.token("end");
+            }
+            v => self.token(&v.to_string()),
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.break_line();
+        self.lines
+    }
+}
+
+pub(crate) fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('\'');
+    for c in s.chars() {
+        match c {
+            '\n' => quoted += "\\n",
+            '\t' => quoted += "\\t",
+            '\\' => quoted += "\\\\",
+            '\'' => quoted += "\\'",
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse, Interpreter};
+
+    fn run(source: &str) -> Vec<Value> {
+        let program = parse(source.chars()).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program, vec![]).unwrap();
+        interpreter.state().stack().cloned().collect()
+    }
+
+    #[test]
+    fn formatted_source_runs_the_same() {
+        let source = "fn  $0 1 + end 'inc' :=\n 3   inc 'x'\n[1 2] 0 1 < if 'yes' else 'no' end";
+        let formatted = format(&parse(source.chars()).unwrap());
+        assert_eq!(run(&formatted), run(source));
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let source = "fn $0 2 * end 'double' :=   4 double 0 3 < while 1 - end";
+        let once = format(&parse(source.chars()).unwrap());
+        let twice = format(&parse(once.chars()).unwrap());
+        assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn assignments_end_their_line() {
+        let formatted = format(&parse("1 'a' := 2 'b' := a b +".chars()).unwrap());
+        assert_eq!(formatted, "1 'a' :=\n2 'b' :=\na b +\n");
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{
+    callable::{CallableKind, FunctionDescriptor},
+    value::lock,
+    Channel, FlyString, Value,
+};
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::{Arc, Mutex, Weak},
+};
+
+/// The variables `^&` made shareable, the only values that can end up containing themselves: assigning a closure
+/// to a variable it shares closes a cycle that reference counting never frees
+#[derive(Debug, Default)]
+pub(crate) struct CellRegistry {
+    cells: HashMap<usize, Weak<Mutex<Value>>>,
+    /// Forget freed cells once there are this many, so making closures in a loop doesn't grow the registry
+    prune_at: usize,
+}
+
+impl CellRegistry {
+    pub(crate) fn track(&mut self, cell: &Arc<Mutex<Value>>) {
+        self.cells.insert(address(cell), Arc::downgrade(cell));
+        if self.cells.len() >= self.prune_at {
+            self.cells.retain(|_, cell| cell.strong_count() > 0);
+            self.prune_at = (self.cells.len() * 2).max(64);
+        }
+    }
+
+    /// Empty every tracked variable that is only reachable from other tracked variables, returning how many were
+    /// emptied. References are counted, not traced from the machine's roots: a value held anywhere outside the
+    /// graph of tracked variables, including by native code, keeps everything it reaches alive
+    pub(crate) fn collect(&mut self) -> usize {
+        self.cells.retain(|_, cell| cell.strong_count() > 0);
+
+        // Every node reachable from a tracked variable, each held exactly once here, with the number of
+        // references to it from within the graph
+        let mut nodes = HashMap::new();
+        let mut internal_refs: HashMap<usize, usize> = HashMap::new();
+        let mut edges: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut pending = vec![];
+        for cell in self.cells.values().filter_map(Weak::upgrade) {
+            let node = Node::Cell(cell);
+            pending.push(node.key());
+            nodes.insert(node.key(), node);
+        }
+        while let Some(key) = pending.pop() {
+            let children = nodes[&key].children();
+            for child in children {
+                let child_key = child.key();
+                *internal_refs.entry(child_key).or_default() += 1;
+                edges.entry(key).or_default().push(child_key);
+                if let Entry::Vacant(entry) = nodes.entry(child_key) {
+                    entry.insert(child);
+                    pending.push(child_key);
+                }
+            }
+        }
+
+        // Nodes referenced from outside, not counting the handle held here, are alive and so is all they reach
+        let mut alive = HashSet::new();
+        let mut pending: Vec<_> = nodes
+            .iter()
+            .filter(|(key, node)| {
+                node.strong_count() - 1 > internal_refs.get(key).copied().unwrap_or(0)
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        while let Some(key) = pending.pop() {
+            if alive.insert(key) {
+                pending.extend(edges.get(&key).into_iter().flatten().copied());
+            }
+        }
+
+        // Emptying the garbage variables breaks their cycles. The values are dropped only once no lock is held
+        let mut garbage = vec![];
+        for (key, node) in &nodes {
+            if let Node::Cell(cell) = node {
+                if !alive.contains(key) {
+                    garbage.push(std::mem::replace(&mut *lock(cell), Value::Nil));
+                }
+            }
+        }
+        drop(nodes);
+        garbage.len()
+    }
+}
+
+/// Something reference counted that values can be held in
+enum Node {
+    Cell(Arc<Mutex<Value>>),
+    List(Arc<Vec<Value>>),
+    Function(Arc<FunctionDescriptor>),
+    Captures(Arc<HashMap<FlyString, Value>>),
+    Arguments(Arc<[Value]>),
+    Channel(Channel),
+}
+
+impl Node {
+    fn key(&self) -> usize {
+        match self {
+            Node::Cell(cell) => address(cell),
+            Node::List(list) => address(list),
+            Node::Function(f) => address(f),
+            Node::Captures(captures) => address(captures),
+            Node::Arguments(args) => address(args),
+            Node::Channel(channel) => address(&channel.0),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Cell(cell) => Arc::strong_count(cell),
+            Node::List(list) => Arc::strong_count(list),
+            Node::Function(f) => Arc::strong_count(f),
+            Node::Captures(captures) => Arc::strong_count(captures),
+            Node::Arguments(args) => Arc::strong_count(args),
+            Node::Channel(channel) => Arc::strong_count(&channel.0),
+        }
+    }
+
+    /// The nodes this one holds directly. Constants of functions are left out, literals can't hold variables
+    fn children(&self) -> Vec<Node> {
+        let mut children = vec![];
+        match self {
+            Node::Cell(cell) => push_nodes(&lock(cell), &mut children),
+            Node::List(list) => list
+                .iter()
+                .for_each(|value| push_nodes(value, &mut children)),
+            Node::Function(f) => children.push(Node::Captures(Arc::clone(&f.captured_names))),
+            Node::Captures(captures) => captures
+                .values()
+                .for_each(|value| push_nodes(value, &mut children)),
+            Node::Arguments(args) => args
+                .iter()
+                .for_each(|value| push_nodes(value, &mut children)),
+            Node::Channel(channel) => channel
+                .values()
+                .iter()
+                .for_each(|value| push_nodes(value, &mut children)),
+        }
+        children
+    }
+}
+
+fn push_nodes(value: &Value, nodes: &mut Vec<Node>) {
+    match value {
+        Value::Shared(cell) => nodes.push(Node::Cell(Arc::clone(cell))),
+        Value::List(list) => nodes.push(Node::List(Arc::clone(list))),
+        Value::Channel(channel) => nodes.push(Node::Channel(channel.clone())),
+        Value::Function(callable) => {
+            if let CallableKind::Function(f) = &callable.kind {
+                nodes.push(Node::Function(Arc::clone(f)));
+            }
+            nodes.push(Node::Arguments(Arc::clone(&callable.bound_arguments)));
+        }
+        _ => {}
+    }
+}
+
+fn address<T: ?Sized>(arc: &Arc<T>) -> usize {
+    Arc::as_ptr(arc) as *const () as usize
+}
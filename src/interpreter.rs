@@ -0,0 +1,364 @@
+use crate::{
+    callable::FunctionDescriptor,
+    environment::BuiltinModule,
+    execute::{execute_in, pack_rest, ExecuteError, ExecuteOptions, Profile},
+    io::{Input, Output},
+    machine_state::{Limits, MachineState},
+    optimize::resolve_with,
+    parser::{parse, ParseError},
+    scope::Scope,
+    selftest::{run_selftest, SelfTestReport},
+    typecheck::type_check,
+    value::lock,
+    FlyString, Value,
+};
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum InterpreterError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Execute(#[from] ExecuteError),
+}
+
+impl InterpreterError {
+    /// The code the program asked to end with, if it stopped by calling `exit`
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::Execute(e) => e.exit_code(),
+            Self::Parse(_) => None,
+        }
+    }
+
+    /// See [`ExecuteError::code`]. Every parse error has the code `parse`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Execute(e) => e.code(),
+            Self::Parse(_) => "parse",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InterpreterBuilder {
+    options: ExecuteOptions,
+    /// Whether a setter changed [`Self::options`], which [`Self::options`] would throw away
+    customized: bool,
+    args: Vec<Value>,
+    input: Input,
+}
+
+impl InterpreterBuilder {
+    pub fn builtin(
+        mut self,
+        name: impl Into<FlyString>,
+        f: impl Fn(&mut MachineState) -> Result<(), ExecuteError> + Send + Sync + 'static,
+    ) -> Self {
+        self.options = self.options.with_builtin(name, f);
+        self.customized = true;
+        self
+    }
+
+    pub fn builtin_module(mut self, module: impl BuiltinModule) -> Self {
+        self.options = self.options.with_builtin_module(module);
+        self.customized = true;
+        self
+    }
+
+    pub fn without_builtin(mut self, name: impl Into<FlyString>) -> Self {
+        self.options = self.options.without_builtin(name);
+        self.customized = true;
+        self
+    }
+
+    /// Limit the builtins to the groups `profile` allows
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.options = self.options.with_profile(profile);
+        self.customized = true;
+        self
+    }
+
+    pub fn args(mut self, args: Vec<Value>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn input(mut self, input: Input) -> Self {
+        self.input = input;
+        self
+    }
+
+    pub fn output(mut self, output: Output) -> Self {
+        self.options = self.options.with_output(output);
+        self.customized = true;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.options = self.options.with_limits(limits);
+        self.customized = true;
+        self
+    }
+
+    /// Start from `options` instead of the defaults. Has to come before the setters that change the options,
+    /// like [`Self::builtin`], [`Self::profile`], [`Self::output`] and [`Self::limits`]: panics if one of
+    /// them was called already, rather than losing what it did
+    pub fn options(mut self, options: ExecuteOptions) -> Self {
+        assert!(
+            !self.customized,
+            "InterpreterBuilder::options would discard earlier builtin, profile, output or limits calls"
+        );
+        self.options = options;
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let mut state = MachineState::default();
+        state.set_input(self.input.clone());
+        self.options.apply(&mut state);
+        state.push_scope(Scope::global(self.args.clone(), self.options.builtins()));
+        Interpreter {
+            state,
+            config: self,
+        }
+    }
+}
+
+/// A reusable interpreter whose global definitions persist between runs
+#[derive(Debug)]
+pub struct Interpreter {
+    state: MachineState,
+    config: InterpreterBuilder,
+}
+
+impl Interpreter {
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder {
+            options: ExecuteOptions::default(),
+            customized: false,
+            args: vec![],
+            input: Input::stdin(),
+        }
+    }
+
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    /// Parse `source`, also type checking it and resolving its builtins if the options ask for that
+    pub fn compile(&self, source: &str) -> Result<FunctionDescriptor, ParseError> {
+        let program = self.state.interner().enter(|| parse(source.chars()))?;
+        if self.config.options.type_checks() {
+            if let Some(e) = type_check(&program).into_iter().next() {
+                return Err(e);
+            }
+        }
+        if !self.config.options.resolves_builtins() {
+            return Ok(program);
+        }
+        // The globals rather than the options, so builtins the program redefined in earlier runs aren't resolved
+        let globals = self
+            .state
+            .global_scope()
+            .names()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        Ok(resolve_with(&program, globals))
+    }
+
+    /// Run a program on an empty stack with `args` bound to `$0`, `$1`, ...
+    pub fn run(
+        &mut self,
+        program: &FunctionDescriptor,
+        args: Vec<Value>,
+    ) -> Result<(), ExecuteError> {
+        self.config.options.verify(program)?;
+        self.state.reset();
+        self.state
+            .global_scope_mut()
+            .set_args(pack_rest(program, args));
+        execute_in(&mut self.state, program)
+    }
+
+    /// Call a global function with `args` pushed in order, returning whatever it leaves on the stack
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Vec<Value>, ExecuteError> {
+        let name = FlyString::from(name);
+        let Some(Value::Function(f)) = self.state.global_scope().get(&name).map(Value::current)
+        else {
+            return Err(ExecuteError::UnboundIdentifier(name));
+        };
+
+        let depth = self.state.stack_len();
+        args.into_iter().for_each(|arg| self.state.push(arg));
+        let interner = self.state.interner().clone();
+        let result = interner.enter(|| f.execute(&mut self.state));
+        self.state.unwind();
+        result?;
+
+        Ok(self.state.split_stack(depth))
+    }
+
+    /// Swap the global `name` for a function whose body is `source`, keeping the stack, every other definition and
+    /// the threads programs started, e.g. to reload an event handler of a long-running script. Code looking `name`
+    /// up from now on gets the new function, as do closures sharing it with `^&`. Closures that captured it with `^`
+    /// and threads already started by `spawn` keep the old one. The new body is compiled and verified like a program
+    pub fn replace_global(&mut self, name: &str, source: &str) -> Result<(), InterpreterError> {
+        let name = FlyString::from(name);
+        if self.state.global_scope().get(&name).is_none() {
+            return Err(ExecuteError::UnboundIdentifier(name).into());
+        }
+        let body = self.compile(source)?;
+        self.config.options.verify(&body)?;
+        let f = Value::from(body);
+        match self.state.global_scope_mut().get(&name) {
+            Some(Value::Shared(cell)) => *lock(cell) = f,
+            _ => self.state.global_scope_mut().set(name, f),
+        }
+        Ok(())
+    }
+
+    /// Parse and run one chunk of source, keeping the stack and definitions from previous steps
+    pub fn repl_step(&mut self, source: &str) -> Result<(), InterpreterError> {
+        let program = self.compile(source)?;
+        self.config.options.verify(&program)?;
+        let result = execute_in(&mut self.state, &program);
+        self.state.unwind();
+        Ok(result?)
+    }
+
+    /// Allow the following runs `fuel` more operations in total, or any number for `None`
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.state.set_fuel(fuel);
+    }
+
+    /// Wait for the threads that programs started with `spawn` and didn't join to end. This blocks for good on a
+    /// thread stuck in a host call that never returns, like `read-line` without input. Dropping the interpreter
+    /// cancels them instead, and leaves running whatever hasn't stopped a second later
+    pub fn join_tasks(&mut self) {
+        self.state.tasks_mut().join_all();
+    }
+
+    /// Run the built-in conformance scripts, each on a fresh interpreter configured like this one
+    pub fn selftest(&self) -> SelfTestReport {
+        run_selftest(|| self.config.clone().build())
+    }
+
+    pub(crate) fn take_stack(&mut self) -> Vec<Value> {
+        self.state.split_stack(0)
+    }
+
+    pub fn state(&self) -> &MachineState {
+        &self.state
+    }
+
+    pub fn into_state(self) -> MachineState {
+        self.state
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A writer whose bytes can still be read after handing out clones of it
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `source` with the prelude, empty input and no access to the host, for embedders like a browser playground
+/// that have no stdout. Gives the lines the program printed followed by the values it left on the stack, or the
+/// message of the error that stopped it
+pub fn parse_and_run(source: &str) -> Result<Vec<String>, String> {
+    let capture = Capture::default();
+    let mut interpreter = Interpreter::builder()
+        .options(ExecuteOptions::new().with_prelude())
+        .input(Input::new(std::io::empty()))
+        .output(Output::new(capture.clone()))
+        .build();
+    let result = interpreter
+        .compile(source)
+        .map_err(InterpreterError::from)
+        .and_then(|program| Ok(interpreter.run(&program, vec![])?));
+
+    let printed = String::from_utf8_lossy(&capture.0.lock().unwrap_or_else(|e| e.into_inner()))
+        .lines()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    result.map_err(|e| e.to_string())?;
+    let stack = interpreter.take_stack();
+    Ok(printed
+        .into_iter()
+        .chain(stack.iter().map(Value::to_string))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_global_is_seen_by_later_calls_and_shared_closures() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .repl_step(
+                "fn $0 1 + end 'handle' := fn handle end ^& 'shared' := fn handle end ^ 'old' :=",
+            )
+            .unwrap();
+        interpreter.replace_global("handle", "$0 10 +").unwrap();
+        interpreter.repl_step("1 handle 1 shared 1 old").unwrap();
+        assert!(interpreter
+            .state()
+            .stack()
+            .eq(&[11.into(), 11.into(), 2.into()]));
+    }
+
+    #[test]
+    fn perform_resumes_with_what_the_host_handler_leaves() {
+        let mut interpreter = Interpreter::builder()
+            .builtin("ask-user", |state| {
+                let question = state.pop()?;
+                state.push(format!("answer to {question}").into());
+                Ok(())
+            })
+            .build();
+        interpreter
+            .repl_step("'name?' 'ask-user' perform 'x' := x")
+            .unwrap();
+        assert!(interpreter
+            .state()
+            .stack()
+            .eq([&Value::from("answer to name?")]));
+    }
+
+    #[test]
+    fn only_existing_globals_are_replaced() {
+        let mut interpreter = Interpreter::new();
+        let error = interpreter.replace_global("missing", "1").unwrap_err();
+        assert!(matches!(
+            error,
+            InterpreterError::Execute(ExecuteError::UnboundIdentifier(_))
+        ));
+        assert!(matches!(
+            interpreter.replace_global("+", "fn"),
+            Err(InterpreterError::Parse(_))
+        ));
+    }
+}
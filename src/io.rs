@@ -0,0 +1,126 @@
+use std::{
+    io::{BufRead, Write},
+    sync::{Arc, Mutex},
+};
+
+/// Where input builtins like `read-line` read from. Clones share the same reader
+#[derive(Clone, Default)]
+pub struct Input(Option<Arc<Mutex<dyn BufRead + Send>>>);
+
+impl Input {
+    pub fn new(reader: impl BufRead + Send + 'static) -> Self {
+        Self(Some(Arc::new(Mutex::new(reader))))
+    }
+
+    pub fn stdin() -> Self {
+        Self(None)
+    }
+
+    /// Read one line without its line ending, or None at end of input
+    pub(crate) fn read_line(&self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = match &self.0 {
+            Some(reader) => reader
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .read_line(&mut line)?,
+            None => std::io::stdin().read_line(&mut line)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+}
+
+impl std::fmt::Debug for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Input"),
+            None => f.write_str("Stdin"),
+        }
+    }
+}
+
+/// Where output builtins like `.` write to. Clones share the same writer
+#[derive(Clone, Default)]
+pub struct Output(Option<Arc<Mutex<dyn Write + Send>>>);
+
+impl Output {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self(Some(Arc::new(Mutex::new(writer))))
+    }
+
+    pub fn stdout() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn write(&self, text: &str) -> std::io::Result<()> {
+        match &self.0 {
+            Some(writer) => writer
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .write_all(text.as_bytes()),
+            None => std::io::stdout().write_all(text.as_bytes()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Output"),
+            None => f.write_str("Stdout"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{execute::ExecuteOptions, Interpreter};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn clones_write_to_the_same_writer() {
+        let shared = Shared::default();
+        let output = Output::new(shared.clone());
+        output.write("a").unwrap();
+        output.clone().write("b").unwrap();
+        assert_eq!(shared.text(), "ab");
+    }
+
+    #[test]
+    fn printing_goes_to_the_configured_output() {
+        let shared = Shared::default();
+        let mut interpreter = Interpreter::builder()
+            .options(ExecuteOptions::new().with_output(Output::new(shared.clone())))
+            .build();
+        interpreter.repl_step("1 . 'two' .").unwrap();
+        assert_eq!(shared.text(), "1\ntwo\n");
+    }
+}
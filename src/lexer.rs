@@ -0,0 +1,270 @@
+use crate::{parser::ParseError, span::Span};
+
+use std::{iter::Peekable, str::Chars};
+
+/// Words with a meaning of their own, which can't name variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    Fn,
+    If,
+    Elif,
+    Then,
+    Else,
+    Case,
+    Of,
+    While,
+    Repeat,
+    Try,
+    Catch,
+    End,
+    Ret,
+    Recurse,
+    Nil,
+}
+
+impl Keyword {
+    fn from_word(word: &str) -> Option<Self> {
+        Some(match word {
+            "fn" => Self::Fn,
+            "if" => Self::If,
+            "elif" => Self::Elif,
+            "then" => Self::Then,
+            "else" => Self::Else,
+            "case" => Self::Case,
+            "of" => Self::Of,
+            "while" => Self::While,
+            "repeat" => Self::Repeat,
+            "try" => Self::Try,
+            "catch" => Self::Catch,
+            "end" => Self::End,
+            "ret" => Self::Ret,
+            "recurse" => Self::Recurse,
+            "nil" => Self::Nil,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Int(i64),
+    Float(f64),
+    /// A string literal with its escapes resolved
+    String(String),
+    /// A name to look up, which calls it if it is a function
+    Identifier(String),
+    Keyword(Keyword),
+    /// `$0`, `$1`, ...
+    Arg(usize),
+    /// `$*`
+    RestArgs,
+    /// `$name` or `&name`, which push what the name is bound to without calling it
+    RawIdentifier(String),
+    OpenList,
+    CloseList,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Splits source text into tokens. Malformed tokens are reported as errors and skipped, so the rest can still be
+/// scanned, e.g. for highlighting
+pub struct Lexer<I: Iterator<Item = char>> {
+    chars: Peekable<I>,
+    position: Span,
+}
+
+/// The tokens of `source`
+pub fn tokenize(source: &str) -> Lexer<Chars<'_>> {
+    Lexer::new(source.chars())
+}
+
+impl<I: Iterator<Item = char>> Lexer<I> {
+    pub fn new(chars: I) -> Self {
+        Self {
+            chars: chars.peekable(),
+            position: Span {
+                line: 1,
+                column: 1,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Where the next character is
+    pub fn location(&self) -> Span {
+        self.position
+    }
+
+    /// Skip the rest of a malformed token, up to the next whitespace
+    pub(crate) fn skip_token(&mut self) {
+        while self.next_char_if(|c| !c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.position.offset += c.len_utf8();
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn next_char_if(&mut self, f: impl FnOnce(&char) -> bool) -> Option<char> {
+        match self.peek() {
+            Some(c) if f(c) => self.next_char(),
+            _ => None,
+        }
+    }
+
+    fn read_while(&mut self, c: Option<char>, f: impl Fn(&char) -> bool) -> String {
+        let mut s = String::with_capacity(10);
+        if let Some(c) = c {
+            s.push(c);
+        }
+        while let Some(c) = self.next_char_if(&f) {
+            s.push(c);
+        }
+        s
+    }
+
+    /// Read the rest of a word, which ends at whitespace or a bracket. Any other character, ASCII or not,
+    /// can be part of a name
+    fn read_word(&mut self, c: Option<char>) -> String {
+        self.read_while(c, is_word_char)
+    }
+
+    /// Read a number literal starting with `first` (a digit or a minus sign): a decimal one with an optional
+    /// exponent, or an int in hex after `0x` or binary after `0b`. Decimal literals without a fractional part or
+    /// exponent are ints
+    fn read_number(&mut self, first: char) -> Result<TokenKind, ParseError> {
+        let mut s = self.read_while(Some(first), |c| c.is_ascii_digit() || *c == '.');
+        if s == "0" || s == "-0" {
+            let radix = match self.next_char_if(|c| matches!(c, 'x' | 'X' | 'b' | 'B')) {
+                Some('x' | 'X') => Some(16),
+                Some(_) => Some(2),
+                None => None,
+            };
+            if let Some(radix) = radix {
+                return self.read_radix_int(s == "-0", radix);
+            }
+        }
+        if let Some(e) = self.next_char_if(|c| *c == 'e' || *c == 'E') {
+            s.push(e);
+            if let Some(sign) = self.next_char_if(|c| *c == '+' || *c == '-') {
+                s.push(sign);
+            }
+            s += &self.read_while(None, char::is_ascii_digit);
+        }
+        if s.contains(['.', 'e', 'E']) {
+            s.parse()
+                .map(TokenKind::Float)
+                .map_err(ParseError::InvalidNumber)
+        } else {
+            s.parse()
+                .map(TokenKind::Int)
+                .map_err(ParseError::InvalidInteger)
+        }
+    }
+
+    /// Read the digits of a hex or binary int. Up to 64 bits can be given, with the top one as the sign bit,
+    /// so `0xFFFFFFFFFFFFFFFF` is -1
+    fn read_radix_int(&mut self, negative: bool, radix: u32) -> Result<TokenKind, ParseError> {
+        let digits = self.read_while(None, char::is_ascii_alphanumeric);
+        let x = if negative {
+            i64::from_str_radix(&format!("-{digits}"), radix)
+        } else {
+            u64::from_str_radix(&digits, radix).map(|x| x as i64)
+        };
+        x.map(TokenKind::Int).map_err(ParseError::InvalidInteger)
+    }
+
+    fn read_string_literal(&mut self) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            match self.next_char().ok_or(ParseError::InvalidString)? {
+                '\'' => return Ok(s),
+                '\\' => s.push(match self.next_char().ok_or(ParseError::InvalidString)? {
+                    'n' => '\n',
+                    't' => '\t',
+                    '\\' => '\\',
+                    '\'' => '\'',
+                    c => return Err(ParseError::InvalidEscape(c)),
+                }),
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn read_token(&mut self, c: char) -> Result<TokenKind, ParseError> {
+        Ok(match c {
+            c if c.is_ascii_digit() => self.read_number(c)?,
+            '-' if self.peek().is_some_and(char::is_ascii_digit) => self.read_number(c)?,
+            '$' => {
+                let name = self.read_word(None);
+                if name.is_empty() {
+                    return Err(ParseError::InvalidRawPush);
+                }
+                if name == "*" {
+                    TokenKind::RestArgs
+                } else if let Ok(index) = name.parse::<usize>() {
+                    TokenKind::Arg(index)
+                } else {
+                    TokenKind::RawIdentifier(name)
+                }
+            }
+            // Quoting never means an argument, unlike `$`, so it works the same for every name
+            '&' if self.peek().is_some_and(is_word_char) => {
+                TokenKind::RawIdentifier(self.read_word(None))
+            }
+            '\'' => TokenKind::String(self.read_string_literal()?),
+            '[' => TokenKind::OpenList,
+            ']' => TokenKind::CloseList,
+            c => {
+                let word = self.read_word(Some(c));
+                match Keyword::from_word(&word) {
+                    Some(keyword) => TokenKind::Keyword(keyword),
+                    None => TokenKind::Identifier(word),
+                }
+            }
+        })
+    }
+}
+
+fn is_word_char(c: &char) -> bool {
+    !c.is_whitespace() && *c != '[' && *c != ']'
+}
+
+impl<I: Iterator<Item = char>> Iterator for Lexer<I> {
+    type Item = Result<Token, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, c) = loop {
+            let start = self.location();
+            let c = self.next_char()?;
+            if !c.is_whitespace() {
+                break (start, c);
+            }
+        };
+        let result = self.read_token(c);
+        let span = Span {
+            len: self.position.offset - start.offset,
+            ..start
+        };
+        Some(
+            result
+                .map(|kind| Token { kind, span })
+                .map_err(|e| e.at(span)),
+        )
+    }
+}
@@ -1,14 +1,69 @@
+pub mod check;
+pub mod diagnostics;
 pub mod execute;
+pub mod format;
+pub mod lexer;
+pub mod link;
+pub mod module;
+pub mod optimize;
 pub mod parser;
+pub mod typecheck;
+pub mod visit;
 
 mod builtins;
+mod bytecode;
 mod callable;
+mod channel;
+mod constant_pool;
+mod coroutine;
+mod disassemble;
+mod environment;
 mod flystring;
+mod gc;
+mod interpreter;
+mod io;
 mod machine_state;
 mod operation;
+mod prelude;
+mod rng;
 mod scope;
+mod selftest;
+#[cfg(feature = "serde")]
+mod serialize;
+mod span;
+#[cfg(feature = "serde")]
+mod structured;
+mod task;
 mod value;
+mod vm;
 
-pub use callable::Callable;
-pub use flystring::FlyString;
-pub use value::Value;
+pub use callable::{BuiltinFuntion, Callable, FunctionDescriptor, Signature};
+pub use channel::Channel;
+pub use coroutine::Coroutine;
+pub use environment::{BuiltinModule, Environment};
+pub use execute::{CallTrace, ExecuteOptions, Profile};
+pub use flystring::{FlyString, Interner, InternerStats};
+pub use interpreter::{parse_and_run, Interpreter, InterpreterBuilder, InterpreterError};
+pub use io::{Input, Output};
+pub use machine_state::{
+    Allocation, ArithmeticMode, ExecutionReport, FunctionReport, Limit, Limits, MachineState,
+    OperationHook,
+};
+pub use operation::{Operation, OperationKind};
+pub use scope::Scope;
+pub use selftest::{SelfTestFailure, SelfTestReport};
+#[cfg(feature = "serde")]
+pub use serialize::SerializeError;
+pub use span::Span;
+#[cfg(feature = "serde")]
+pub use structured::Structured;
+pub use value::{format_number, Value, ValueKey};
+pub use vm::{Breakpoint, Stop, Vm};
+
+// Programs and their results can be handed to worker threads
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Value>();
+    assert_send_sync::<FunctionDescriptor>();
+    assert_send_sync::<MachineState>();
+};
@@ -0,0 +1,149 @@
+use crate::{
+    callable::{referenced_names, FunctionDescriptor},
+    operation::{Operation, OperationKind},
+    span::Span,
+    FlyString, Value,
+};
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LinkError {
+    /// Only definitions spelled out as `'name' :=` or `'name' :=global` at a unit's top level are found.
+    /// Names that are computed, like `'na' 'me' concat :=`, or assigned inside blocks are not compared
+    #[error("{0} is defined in both unit {1} and unit {2}")]
+    DuplicateDefinition(FlyString, usize, usize),
+    /// A `ret` outside of functions would end the linked program, skipping the units after this one
+    #[error("unit {0} returns outside of a function at {1}, which would skip the units after it")]
+    ReturnOutsideFunction(usize, Span),
+}
+
+/// Where `operations` return, not counting nested functions
+fn top_level_return(operations: &[Operation]) -> Option<Span> {
+    operations.iter().find_map(|op| match op.kind {
+        OperationKind::Return => Some(op.span),
+        _ => op.blocks().find_map(top_level_return),
+    })
+}
+
+/// Names a unit defines at its top level through `'name' :=` or `'name' :=global`
+fn definitions(f: &FunctionDescriptor) -> impl Iterator<Item = &FlyString> {
+    f.operations
+        .windows(2)
+        .filter_map(|ops| match (&ops[0].kind, &ops[1].kind) {
+            (
+                OperationKind::PushConst(index),
+                OperationKind::PushId(assign) | OperationKind::Call(assign, _),
+            ) if *assign == ":=" || *assign == ":=global" => match &f.constants[*index] {
+                Value::String(name) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+/// Shift the constant references of top-level code (but not of nested functions, which keep their own pool),
+/// and move `$*` of a variadic unit from `rest.0` to the linked program's rest list at `rest.1`
+fn relocate(operations: &mut [Operation], offset: usize, rest: Option<(usize, usize)>) {
+    use OperationKind as O;
+
+    for op in operations {
+        match &mut op.kind {
+            O::PushConst(index) => *index += offset,
+            O::PushArg(index) => {
+                if let Some((from, to)) = rest {
+                    if *index == from {
+                        *index = to;
+                    }
+                }
+            }
+            _ => op
+                .blocks_mut()
+                .for_each(|block| relocate(block, offset, rest)),
+        }
+    }
+}
+
+/// Combine several parsed units into one program that runs their top-level code in order.
+/// Units share the global scope, so names defined by one are visible to all that run after it.
+/// Only the last unit may use `ret` outside of a function, as it would end the whole linked program
+pub fn link(units: Vec<FunctionDescriptor>) -> Result<FunctionDescriptor, LinkError> {
+    let mut defined_in = HashMap::new();
+    for (unit, f) in units.iter().enumerate() {
+        if unit + 1 < units.len() {
+            if let Some(span) = top_level_return(&f.operations) {
+                return Err(LinkError::ReturnOutsideFunction(unit, span));
+            }
+        }
+        for name in definitions(f) {
+            match defined_in.get(name) {
+                Some(&other) if other != unit => {
+                    return Err(LinkError::DuplicateDefinition(name.clone(), other, unit))
+                }
+                _ => {
+                    defined_in.insert(name.clone(), unit);
+                }
+            }
+        }
+    }
+
+    let num_args = units.iter().map(|f| f.num_args).max().unwrap_or(0);
+    let variadic = units.iter().any(|f| f.variadic);
+    let mut operations = vec![];
+    let mut constants = vec![];
+    for f in units {
+        let mut unit_operations = f.operations.to_vec();
+        let rest = f.variadic.then_some((f.num_args, num_args));
+        relocate(&mut unit_operations, constants.len(), rest);
+        operations.extend(unit_operations);
+        constants.extend(f.constants.iter().cloned());
+    }
+
+    Ok(FunctionDescriptor {
+        referenced_names: referenced_names(&operations),
+        operations: operations.into(),
+        constants: constants.into(),
+        num_args,
+        variadic,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse, Interpreter};
+
+    fn units(sources: &[&str]) -> Vec<FunctionDescriptor> {
+        sources.iter().map(|s| parse(s.chars()).unwrap()).collect()
+    }
+
+    #[test]
+    fn runs_units_in_order_with_shared_globals() {
+        let program = link(units(&[
+            "2 'x' :=",
+            "fn x 3 * end 'triple' :=",
+            "triple 'a'",
+        ]))
+        .unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program, vec![]).unwrap();
+        let stack: Vec<_> = interpreter.state().stack().cloned().collect();
+        assert_eq!(stack, [Value::Int(6), "a".into()]);
+    }
+
+    #[test]
+    fn rejects_duplicate_definitions() {
+        let error = link(units(&["1 'x' :=", "2 'y' :=", "3 'x' :=global"])).unwrap_err();
+        assert!(matches!(error, LinkError::DuplicateDefinition(name, 0, 2) if name == "x"));
+    }
+
+    #[test]
+    fn rejects_returns_that_would_skip_units() {
+        let error = link(units(&["1 2 < if ret end", "3"])).unwrap_err();
+        assert!(matches!(error, LinkError::ReturnOutsideFunction(0, _)));
+        assert!(link(units(&["fn ret end 'f' :=", "3 ret"])).is_ok());
+    }
+}
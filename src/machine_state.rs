@@ -1,20 +1,494 @@
-use crate::{execute::ExecuteError, scope::Scope, FlyString, Value};
+use crate::{
+    callable::{BuiltinFuntion, Callable, CallableKind},
+    execute::ExecuteError,
+    gc::CellRegistry,
+    io::{Input, Output},
+    operation::Operation,
+    rng::Rng,
+    scope::Scope,
+    span::Span,
+    task::Tasks,
+    vm::Request,
+    FlyString, Interner, Value,
+};
 
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The kinds of values that live on the heap, which [`ExecutionReport`] counts separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Allocation {
+    String,
+    List,
+    Bytes,
+    Function,
+    Error,
+    Channel,
+}
+
+impl Allocation {
+    pub const ALL: [Self; 6] = [
+        Self::String,
+        Self::List,
+        Self::Bytes,
+        Self::Function,
+        Self::Error,
+        Self::Channel,
+    ];
+
+    /// What making `value` allocated, or `None` for values stored inline
+    pub fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Nil | Value::Bool(_) | Value::Int(_) | Value::Number(_) | Value::Shared(_) => {
+                None
+            }
+            Value::String(_) | Value::OwnedString(_) => Some(Self::String),
+            Value::List(_) => Some(Self::List),
+            Value::Bytes(_) => Some(Self::Bytes),
+            Value::Function(_) => Some(Self::Function),
+            Value::Error(_) => Some(Self::Error),
+            Value::Channel(_) => Some(Self::Channel),
+        }
+    }
+}
+
+/// Resources used by a single execution
+#[derive(Debug, Default, Clone)]
+pub struct ExecutionReport {
+    pub operations: usize,
+    pub peak_stack_depth: usize,
+    pub peak_scope_depth: usize,
+    /// Values made while running, by [`Allocation`]. Copying or moving a value doesn't count
+    allocations: [usize; Allocation::ALL.len()],
+    /// Calls of the builtins the machine was configured with, by the name they were given.
+    /// Builtins made while running, like the functions `coroutine` returns, aren't counted
+    pub builtins_invoked: HashMap<FlyString, usize>,
+    /// What the code of each function made, by where the function is defined: the span of its `fn`.
+    /// Code outside of any function is under `None`
+    pub functions: HashMap<Option<Span>, FunctionReport>,
+}
+
+/// What the code of one function made while running, to find the closure or loop memory grows in
+#[derive(Debug, Default, Clone)]
+pub struct FunctionReport {
+    allocations: [usize; Allocation::ALL.len()],
+    /// Values captured by the closures the function made, which live as long as those closures
+    pub captured_values: usize,
+}
+
+impl FunctionReport {
+    /// Values of `kind` the function's own code made, not counting the functions it called
+    pub fn allocations(&self, kind: Allocation) -> usize {
+        self.allocations[kind as usize]
+    }
+}
+
+impl ExecutionReport {
+    pub fn allocations(&self, kind: Allocation) -> usize {
+        self.allocations[kind as usize]
+    }
+
+    /// Every kind of value that was made at least once, with how many were
+    pub fn allocations_by_kind(&self) -> impl Iterator<Item = (Allocation, usize)> + '_ {
+        Allocation::ALL
+            .into_iter()
+            .map(|kind| (kind, self.allocations(kind)))
+            .filter(|(_, count)| *count > 0)
+    }
+}
+
+/// How far a program may grow the machine, so runaway recursion fails instead of overflowing the host's stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Function and block scopes alive at once. Every level costs native stack, so hosts running
+    /// on small thread stacks should lower this
+    pub max_scope_depth: usize,
+    pub max_stack_size: usize,
+    /// Native stack of each thread `spawn` starts, capped at 1 GiB. The default fits the default scope depth;
+    /// hosts raising that should raise this too
+    pub thread_stack_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_scope_depth: 1024,
+            max_stack_size: 1 << 20,
+            thread_stack_size: 8 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    ScopeDepth,
+    StackSize,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::ScopeDepth => f.write_str("scope depth"),
+            Limit::StackSize => f.write_str("stack size"),
+        }
+    }
+}
+
+/// What float arithmetic does when it has no meaningful result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArithmeticMode {
+    /// Follow IEEE 754: dividing by zero gives an infinity and invalid operations give NaN
+    #[default]
+    Ieee,
+    /// Fail with [`ExecuteError::DivisionByZero`] or [`ExecuteError::NanResult`] instead
+    Checked,
+}
+
+type OperationCallback = dyn FnMut(&Operation, &MachineState) + Send;
+
+/// A callback run before every operation, e.g. to log or profile execution. Clones share the callback
+#[derive(Clone)]
+pub struct OperationHook(Arc<Mutex<OperationCallback>>);
+
+impl OperationHook {
+    pub fn new(f: impl FnMut(&Operation, &MachineState) + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(f)))
+    }
+
+    fn call(&self, op: &Operation, state: &MachineState) {
+        (self.0.lock().unwrap_or_else(|e| e.into_inner()))(op, state)
+    }
+}
+
+impl fmt::Debug for OperationHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OperationHook")
+    }
+}
+
+/// What a continuation made by `call/cc` puts back: the stack and the scopes of the calls in progress. The global
+/// scope isn't part of it, so global definitions made since stay, while local variables go back to how they were
+#[derive(Debug, Clone)]
+pub(crate) struct Resumable {
+    scopes: Vec<Scope>,
+    stack: VecDeque<Value>,
+    calls: Vec<Span>,
+    functions: Vec<Span>,
+}
 
 #[derive(Debug, Default)]
 pub struct MachineState {
     scopes: VecDeque<Scope>,
     stack: VecDeque<Value>,
+    report: ExecutionReport,
+    input: Input,
+    output: Output,
+    limits: Limits,
+    arithmetic: ArithmeticMode,
+    on_op: Option<OperationHook>,
+    /// Operations left to run, or `None` for no budget. Machines forked from this one draw from the same budget
+    fuel: Option<Arc<AtomicU64>>,
+    /// Set from another thread to stop the program at the next operation: the host's token, then those of the
+    /// `spawn`ed threads this machine runs in
+    cancel: Vec<Arc<AtomicBool>>,
+    /// Threads started by `spawn` and not joined yet
+    tasks: Tasks,
+    /// The configured builtins by address, so calls of them can be counted under their name
+    builtin_names: Arc<HashMap<usize, FlyString>>,
+    /// Where strings made while this machine runs are interned. Machines forked from it share it
+    interner: Interner,
+    rng: Rng,
+    /// Where the operation that began last is in the source
+    span: Span,
+    /// Where every function being called was called from, outermost first
+    calls: Vec<Span>,
+    /// Where the functions whose code is running are defined, outermost first. Unlike [`Self::calls`],
+    /// a tail call replaces the function it was made from
+    functions: Vec<Span>,
+    /// Whether `yield` can pause the program: only while a [`crate::Vm`] runs a builtin itself
+    can_yield: bool,
+    /// Set by `yield` for the [`crate::Vm`] to pause once the builtin returns
+    yielded: bool,
+    /// What a builtin like `call/cc` asked the [`crate::Vm`] to do once it returns
+    request: Option<Request>,
+    /// Variables shared by closures, which [`Self::collect_garbage`] looks for cycles in
+    cells: CellRegistry,
 }
 
 impl MachineState {
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    pub fn set_input(&mut self, input: Input) {
+        self.input = input;
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn arithmetic(&self) -> ArithmeticMode {
+        self.arithmetic
+    }
+
+    pub fn set_arithmetic(&mut self, mode: ArithmeticMode) {
+        self.arithmetic = mode;
+    }
+
+    pub fn set_on_op(&mut self, hook: Option<OperationHook>) {
+        self.on_op = hook;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.as_ref().map(|fuel| fuel.load(Ordering::Relaxed))
+    }
+
+    /// Allow `fuel` more operations (counting those in blocks and function calls), or any number for `None`
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel.map(|fuel| Arc::new(AtomicU64::new(fuel)));
+    }
+
+    pub fn set_cancel_token(&mut self, token: Option<Arc<AtomicBool>>) {
+        self.cancel = token.into_iter().collect();
+    }
+
+    /// The table strings made while the machine runs are interned in
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    pub fn set_interner(&mut self, interner: Interner) {
+        self.interner = interner;
+    }
+
+    /// Also stop the program once `token` is set, keeping the tokens it already has
+    pub(crate) fn add_cancel_token(&mut self, token: Arc<AtomicBool>) {
+        self.cancel.push(token);
+    }
+
+    pub(crate) fn tasks_mut(&mut self) -> &mut Tasks {
+        &mut self.tasks
+    }
+
+    /// Restart `rand` and `rand-int` from `seed`, so the numbers they give can be reproduced
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub(crate) fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub(crate) fn track_cell(&mut self, cell: &Arc<Mutex<Value>>) {
+        self.cells.track(cell);
+    }
+
+    /// Free variables shared with `^&` that are only kept alive by cycles, like a closure assigned to a
+    /// variable it shares, returning how many were freed. Other threads running programs forked from this
+    /// machine, e.g. by `spawn`, should be done first
+    pub fn collect_garbage(&mut self) -> usize {
+        self.cells.collect()
+    }
+
+    /// Whether a cancel token was set, so the program should stop
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel
+            .iter()
+            .any(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// Count `op` towards the report and the fuel budget and show it to the hook, right before it runs
+    pub(crate) fn begin_operation(&mut self, op: &Operation) -> Result<(), ExecuteError> {
+        if self.is_cancelled() {
+            return Err(ExecuteError::Cancelled);
+        }
+        if let Some(fuel) = &self.fuel {
+            fuel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |fuel| {
+                fuel.checked_sub(1)
+            })
+            .map_err(|_| ExecuteError::OutOfFuel)?;
+        }
+        self.report.operations += 1;
+        self.span = op.span;
+        if let Some(hook) = &self.on_op {
+            hook.clone().call(op, self);
+        }
+        Ok(())
+    }
+
+    /// Remember the operation running now as the site of a call that is starting
+    pub(crate) fn enter_call(&mut self) {
+        self.calls.push(self.span);
+    }
+
+    /// Go back to the operation that made the call that ended
+    pub(crate) fn leave_call(&mut self) {
+        if let Some(span) = self.calls.pop() {
+            self.span = span;
+        }
+    }
+
+    /// Where the functions being called were called from, outermost first
+    pub(crate) fn call_sites(&self) -> impl DoubleEndedIterator<Item = Span> + '_ {
+        self.calls.iter().copied()
+    }
+
+    /// Run a builtin called by a [`crate::Vm`] directly, which can pause it with `yield`
+    pub(crate) fn run_yieldable(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), ExecuteError>,
+    ) -> Result<(), ExecuteError> {
+        self.can_yield = true;
+        let result = f(self);
+        self.can_yield = false;
+        result
+    }
+
+    /// Called whenever code calls a builtin, so only the one the [`crate::Vm`] called can yield
+    pub(crate) fn forbid_yield(&mut self) {
+        self.can_yield = false;
+    }
+
+    /// Ask the [`crate::Vm`] to pause. Fails if the builtin asking wasn't called by one directly, as the native
+    /// stack of everything in between can't be suspended
+    pub(crate) fn request_yield(&mut self) -> Result<(), ExecuteError> {
+        if !self.can_yield {
+            return Err(ExecuteError::YieldOutsideCoroutine);
+        }
+        self.yielded = true;
+        Ok(())
+    }
+
+    pub(crate) fn take_yield(&mut self) -> bool {
+        std::mem::take(&mut self.yielded)
+    }
+
+    /// Hand `request` to the [`crate::Vm`] running the builtin. Like [`Self::request_yield`], this fails unless
+    /// the Vm called the builtin directly
+    pub(crate) fn request(&mut self, request: Request) -> Result<(), ExecuteError> {
+        if !self.can_yield {
+            return Err(ExecuteError::ContinuationOutsideCoroutine);
+        }
+        self.request = Some(request);
+        Ok(())
+    }
+
+    pub(crate) fn take_request(&mut self) -> Option<Request> {
+        self.request.take()
+    }
+
+    /// The parts of the machine a continuation puts back, see [`Resumable`]
+    pub(crate) fn save_resumable(&self) -> Resumable {
+        Resumable {
+            scopes: self.scopes.iter().skip(1).cloned().collect(),
+            stack: self.stack.clone(),
+            calls: self.calls.clone(),
+            functions: self.functions.clone(),
+        }
+    }
+
+    pub(crate) fn restore_resumable(&mut self, resumable: &Resumable) {
+        self.scopes.truncate(1);
+        self.scopes.extend(resumable.scopes.iter().cloned());
+        self.stack.clone_from(&resumable.stack);
+        self.calls.clone_from(&resumable.calls);
+        self.functions.clone_from(&resumable.functions);
+    }
+
+    /// A machine for running code on the side, with the same input, output, limits, hook, fuel budget and
+    /// cancel token and a copy of the global scope as it is now
+    pub(crate) fn fork(&self) -> MachineState {
+        let mut state = MachineState {
+            input: self.input.clone(),
+            output: self.output.clone(),
+            limits: self.limits,
+            arithmetic: self.arithmetic,
+            on_op: self.on_op.clone(),
+            fuel: self.fuel.clone(),
+            cancel: self.cancel.clone(),
+            builtin_names: Arc::clone(&self.builtin_names),
+            interner: self.interner.clone(),
+            ..Default::default()
+        };
+        state.push_scope(self.global_scope().clone());
+        state
+    }
+
+    pub(crate) fn check_stack_size(&self) -> Result<(), ExecuteError> {
+        if self.stack.len() > self.limits.max_stack_size {
+            return Err(ExecuteError::LimitExceeded(
+                Limit::StackSize,
+                self.limits.max_stack_size,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Make sure another scope fits
+    pub(crate) fn check_scope_depth(&self) -> Result<(), ExecuteError> {
+        if self.scopes.len() >= self.limits.max_scope_depth {
+            return Err(ExecuteError::LimitExceeded(
+                Limit::ScopeDepth,
+                self.limits.max_scope_depth,
+            ));
+        }
+        Ok(())
+    }
+
     pub fn pop(&mut self) -> Result<Value, ExecuteError> {
         self.stack.pop_back().ok_or(ExecuteError::EmptyStack)
     }
 
     pub fn push(&mut self, value: Value) {
-        self.stack.push_back(value)
+        self.stack.push_back(value);
+        self.report.peak_stack_depth = self.report.peak_stack_depth.max(self.stack.len());
+    }
+
+    /// The values on the stack, from the bottom to the top
+    pub fn stack(&self) -> impl DoubleEndedIterator<Item = &Value> + ExactSizeIterator {
+        self.stack.iter()
+    }
+
+    /// The value a program left on top of the stack
+    pub fn peek_result(&self) -> Option<&Value> {
+        self.stack.back()
+    }
+
+    pub fn pop_result(&mut self) -> Option<Value> {
+        self.stack.pop_back()
+    }
+
+    /// Take the whole stack, bottom first
+    pub fn into_stack(self) -> Vec<Value> {
+        self.stack.into()
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        self.global_scope().get(&name.into())
+    }
+
+    /// Every scope, from the global one to the innermost
+    pub fn scopes(&self) -> impl DoubleEndedIterator<Item = &Scope> + ExactSizeIterator {
+        self.scopes.iter()
     }
 
     pub fn global_scope(&self) -> &Scope {
@@ -29,9 +503,8 @@ impl MachineState {
         self.scopes.back_mut().expect("Has at least one scope")
     }
 
-    pub fn look_up(&self, name: &FlyString) -> Option<Value> {
-        let mut scopes = self.scopes.iter().rev();
-        while let Some(scope) = scopes.next() {
+    pub fn look_up(&self, name: &FlyString) -> Option<&Value> {
+        for scope in self.scopes.iter().rev() {
             if let Some(var) = scope.get(name) {
                 return Some(var);
             }
@@ -42,9 +515,8 @@ impl MachineState {
         None
     }
 
-    pub fn get_arg(&self, index: usize) -> Result<Value, ExecuteError> {
-        let mut scopes = self.scopes.iter().rev();
-        while let Some(scope) = scopes.next() {
+    pub fn get_arg(&self, index: usize) -> Result<&Value, ExecuteError> {
+        for scope in self.scopes.iter().rev() {
             if let Some(var) = scope.get_arg(index) {
                 return Ok(var);
             }
@@ -56,10 +528,188 @@ impl MachineState {
     }
 
     pub fn push_scope(&mut self, s: Scope) {
-        self.scopes.push_back(s)
+        self.scopes.push_back(s);
+        self.report.peak_scope_depth = self.report.peak_scope_depth.max(self.scopes.len());
     }
 
     pub fn pop_scope(&mut self) -> Scope {
         self.scopes.pop_back().expect("Has scope")
     }
+
+    /// Clear the stack and drop every scope except the global one, keeping allocations around for reuse
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.unwind();
+        self.report = Default::default();
+    }
+
+    /// Drop every scope except the global one, e.g. after an execution failed halfway through a function
+    pub fn unwind(&mut self) {
+        self.scopes.truncate(1);
+        self.span = Span::default();
+    }
+
+    pub fn global_scope_mut(&mut self) -> &mut Scope {
+        self.scopes.front_mut().expect("Has global scope")
+    }
+
+    pub(crate) fn scope_depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Close the scopes blocks opened above `depth`, e.g. when a function returns from inside them
+    pub(crate) fn truncate_scopes(&mut self, depth: usize) {
+        self.scopes.truncate(depth);
+    }
+
+    /// Drop scopes and values left behind by a failed block
+    pub(crate) fn restore(&mut self, scope_depth: usize, stack_len: usize) {
+        self.scopes.truncate(scope_depth);
+        self.stack.truncate(stack_len);
+    }
+
+    /// Replace the stack and every scope, e.g. with those of a restored snapshot
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_contents(&mut self, stack: Vec<Value>, scopes: Vec<Scope>) {
+        self.stack = stack.into();
+        self.scopes = scopes.into();
+    }
+
+    /// The number of values on the stack
+    pub fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn clear_stack(&mut self) {
+        self.stack.clear();
+    }
+
+    pub(crate) fn split_stack(&mut self, depth: usize) -> Vec<Value> {
+        self.stack.split_off(depth.min(self.stack.len())).into()
+    }
+
+    pub fn report(&self) -> &ExecutionReport {
+        &self.report
+    }
+
+    /// Push a value the running operation just made, counting it towards the report's allocations.
+    /// Builtins use this for their results, and [`Self::push`] for values they only pass on
+    pub fn push_new(&mut self, value: Value) {
+        if let Some(kind) = Allocation::of(&value) {
+            self.report.allocations[kind as usize] += 1;
+            self.running_function().allocations[kind as usize] += 1;
+        }
+        self.push(value);
+    }
+
+    /// Count the values a closure that is being made captured towards the function making it
+    pub(crate) fn record_captures(&mut self, count: usize) {
+        self.running_function().captured_values += count;
+    }
+
+    fn running_function(&mut self) -> &mut FunctionReport {
+        let site = self.functions.last().copied();
+        self.report.functions.entry(site).or_default()
+    }
+
+    /// Attribute what runs from now on to the function defined at `definition`, until [`Self::leave_body`]
+    pub(crate) fn enter_body(&mut self, definition: Span) {
+        self.functions.push(definition);
+    }
+
+    pub(crate) fn leave_body(&mut self) {
+        self.functions.pop();
+    }
+
+    /// Remember the names of the builtins the machine was configured with, for counting their calls
+    pub(crate) fn set_builtin_names(&mut self, builtins: &HashMap<FlyString, Value>) {
+        let names = builtins.iter().filter_map(|(name, value)| match value {
+            Value::Function(Callable {
+                kind: CallableKind::Builtin(f),
+                ..
+            }) => Some((builtin_key(f), name.clone())),
+            _ => None,
+        });
+        self.builtin_names = Arc::new(names.collect());
+    }
+
+    pub(crate) fn record_builtin(&mut self, f: &BuiltinFuntion) {
+        let Some(name) = self.builtin_names.get(&builtin_key(f)) else {
+            return;
+        };
+        match self.report.builtins_invoked.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                self.report.builtins_invoked.insert(name.clone(), 1);
+            }
+        }
+    }
+}
+
+/// Identifies a builtin by its address, which every copy of it shares
+fn builtin_key(f: &BuiltinFuntion) -> usize {
+    Arc::as_ptr(f) as *const () as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    fn report(source: &str) -> ExecutionReport {
+        let mut interpreter = Interpreter::new();
+        interpreter.repl_step(source).unwrap();
+        interpreter.state().report().clone()
+    }
+
+    #[test]
+    fn counts_values_where_they_are_made() {
+        let report = report("[ 1 2 ] dup dup swap drop 1 to-string 1 to-string");
+        assert_eq!(report.allocations(Allocation::List), 1);
+        assert_eq!(report.allocations(Allocation::String), 2);
+        assert_eq!(
+            report.allocations_by_kind().collect::<Vec<_>>(),
+            [(Allocation::String, 2), (Allocation::List, 1)]
+        );
+    }
+
+    #[test]
+    fn attributes_values_and_captures_to_the_function_making_them() {
+        let report = report("[ ]\nfn 1 'x' := fn x end ^ [ 1 ] end 'make' :=\nmake make");
+        let (_, make) = report
+            .functions
+            .iter()
+            .find(|(span, _)| span.is_some_and(|span| span.line == 2 && span.column == 1))
+            .unwrap();
+        assert_eq!(make.allocations(Allocation::Function), 2);
+        assert_eq!(make.allocations(Allocation::List), 2);
+        assert_eq!(make.captured_values, 2);
+        let top_level = &report.functions[&None];
+        assert_eq!(top_level.allocations(Allocation::List), 1);
+        assert_eq!(top_level.captured_values, 0);
+    }
+
+    #[test]
+    fn counts_builtins_however_they_are_called() {
+        let report = report("[ 1 2 3 ] $to-string map drop 1 2 $+ call");
+        assert_eq!(report.builtins_invoked[&FlyString::from("to-string")], 3);
+        assert_eq!(report.builtins_invoked[&FlyString::from("map")], 1);
+        assert_eq!(report.builtins_invoked[&FlyString::from("+")], 1);
+    }
+
+    #[test]
+    fn reset_keeps_only_the_globals() {
+        let program = crate::parser::parse("1 'x' := [ 2 ] 3".chars()).unwrap();
+        let mut vm = crate::vm::Vm::new(program, vec![]);
+        vm.run().unwrap();
+        let mut state = vm.into_state();
+        state.push_scope(Scope::conditional());
+        assert_eq!(state.report().allocations(Allocation::List), 1);
+
+        state.reset();
+        assert_eq!(state.stack().count(), 0);
+        assert_eq!(state.scopes().len(), 1);
+        assert_eq!(state.get_global("x"), Some(&Value::Int(1)));
+        assert_eq!(state.report().allocations(Allocation::List), 0);
+    }
 }
@@ -1,12 +1,212 @@
-use ssl::{execute::execute, parser::parse};
+use ssl::{
+    check::check,
+    diagnostics::render,
+    format::format,
+    module::FsResolver,
+    parser::{parse, parse_all_errors, ParseError},
+    typecheck::type_check,
+    ExecuteOptions, FunctionDescriptor, Interpreter, InterpreterError,
+};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let input = r"
-        $0 .
-    ";
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-    let code = parse(input.chars())?;
+const USAGE: &str = "\
+Usage:
+    ssl                        start an interactive session
+    ssl repl                   start an interactive session
+    ssl selftest               run the built-in conformance scripts
+    ssl fmt <script>           print a script in canonical form
+    ssl check <script>         report likely stack and type mistakes in a script without running it
+    ssl --dump <script>        print the operations a script compiles to
+    ssl <script> [args...]     run a script, binding args to $0, $1, ...
+    ssl --help                 show this message
 
-    execute(&code, vec!["Hello, world".into()])?;
-    Ok(())
+Exit codes:
+    0   success
+    1   a self test failed, or check found likely mistakes
+    64  invalid command line
+    65  the script failed to parse
+    66  the script could not be read
+    70  the script failed while running
+    Scripts that call `exit` end with the code they pass it";
+
+/// An interpreter with the prelude whose `import` loads files relative to `root`
+fn interpreter(root: impl Into<PathBuf>) -> Interpreter {
+    let options = ExecuteOptions::new()
+        .with_prelude()
+        .with_module_resolver(FsResolver::new(root));
+    Interpreter::builder().options(options).build()
+}
+
+fn selftest() -> ExitCode {
+    let report = Interpreter::new().selftest();
+    for failure in &report.failures {
+        println!(
+            "FAILED {}: `{}` expected {}, got {}",
+            failure.name, failure.source, failure.expected, failure.actual
+        );
+    }
+    println!("{} passed, {} failed", report.passed, report.failures.len());
+    if report.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Read and run one line at a time. Definitions and the stack survive between lines, errors are only reported.
+/// Ends at the end of input or when a line calls `exit`
+fn repl() -> std::io::Result<ExitCode> {
+    let mut interpreter = interpreter(".");
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+
+        line.clear();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if let Err(e) = interpreter.repl_step(&line) {
+            if let Some(code) = e.exit_code() {
+                return Ok(exit_code(code));
+            }
+            print!("{}", render("<repl>", &line, &e));
+        }
+    }
+}
+
+/// The process exit code for a script's `exit`, truncated like the OS does
+fn exit_code(code: i32) -> ExitCode {
+    ExitCode::from(code as u8)
+}
+
+fn read_script(path: &str) -> Result<String, ExitCode> {
+    std::fs::read_to_string(path).map_err(|e| {
+        eprintln!("Failed to read {path}: {e}");
+        ExitCode::from(66)
+    })
+}
+
+/// Print every error in a script that failed to parse with `first`, rather than only the first one
+fn report_parse_errors(path: &str, source: &str, first: &ParseError) -> ExitCode {
+    match parse_all_errors(source.chars()) {
+        Err(errors) => errors
+            .iter()
+            .for_each(|e| eprint!("{}", render(path, source, e))),
+        Ok(_) => eprint!("{}", render(path, source, first)),
+    }
+    ExitCode::from(65)
+}
+
+/// Parse a script and print what `show` makes of it
+fn print_script(path: &str, show: fn(&FunctionDescriptor) -> String) -> ExitCode {
+    let source = match read_script(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    match parse(source.chars()) {
+        Ok(program) => {
+            print!("{}", show(&program));
+            ExitCode::SUCCESS
+        }
+        Err(e) => report_parse_errors(path, &source, &e),
+    }
+}
+
+/// Print what [`check`] finds in a script, failing if it finds anything
+fn check_script(path: &str) -> ExitCode {
+    let source = match read_script(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    let program = match parse(source.chars()) {
+        Ok(program) => program,
+        Err(e) => return report_parse_errors(path, &source, &e),
+    };
+    let warnings = check(&program);
+    for warning in &warnings {
+        let span = warning.span;
+        println!(
+            "{path}:{}:{}: warning: {}",
+            span.line, span.column, warning.kind
+        );
+    }
+    let errors = type_check(&program);
+    for e in &errors {
+        let span = e.span().unwrap_or_default();
+        println!("{path}:{}:{}: error: {}", span.line, span.column, e.kind());
+    }
+    if warnings.is_empty() && errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn run_script(path: &str, args: Vec<String>) -> ExitCode {
+    let source = match read_script(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+
+    let root = Path::new(path).parent().unwrap_or(Path::new("."));
+    let mut interpreter = interpreter(root);
+    let result = interpreter
+        .compile(&source)
+        .map_err(InterpreterError::from)
+        .and_then(|program| {
+            let args = args.into_iter().map(Into::into).collect();
+            Ok(interpreter.run(&program, args)?)
+        });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(InterpreterError::Parse(e)) => report_parse_errors(path, &source, &e),
+        Err(InterpreterError::Execute(e)) => match e.exit_code() {
+            Some(code) => exit_code(code),
+            None => {
+                eprint!("{}", render(path, &source, &e));
+                ExitCode::from(70)
+            }
+        },
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("--help" | "-h") => {
+            println!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        Some("selftest") => selftest(),
+        Some(command @ ("fmt" | "--dump" | "check")) => match (args.next(), args.next()) {
+            (Some(path), None) if command == "fmt" => print_script(&path, format),
+            (Some(path), None) if command == "check" => check_script(&path),
+            (Some(path), None) => print_script(&path, FunctionDescriptor::disassemble),
+            _ => {
+                eprintln!("{USAGE}");
+                ExitCode::from(64)
+            }
+        },
+        Some("repl") | None => match repl() {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::FAILURE
+            }
+        },
+        Some(flag) if flag.starts_with('-') => {
+            eprintln!("Unknown option {flag}\n\n{USAGE}");
+            ExitCode::from(64)
+        }
+        Some(path) => run_script(path, args.collect()),
+    }
 }
@@ -0,0 +1,124 @@
+use crate::{
+    builtins::to_text,
+    callable::FunctionDescriptor,
+    execute::{execute_in, ExecuteError},
+    machine_state::MachineState,
+    parser::{parse, ParseError},
+    scope::Scope,
+};
+
+use std::collections::HashMap;
+#[cfg(feature = "host")]
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ModuleError {
+    #[error("Module {0} not found")]
+    NotFound(String),
+    #[error("Module {0} is outside the module root")]
+    OutsideRoot(String),
+    #[error("Failed to read module: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse module: {0}")]
+    Parse(#[from] ParseError),
+}
+
+#[derive(Debug, Clone)]
+pub enum Module {
+    Source(String),
+    Compiled(FunctionDescriptor),
+}
+
+impl Module {
+    pub fn compile(self) -> Result<FunctionDescriptor, ModuleError> {
+        match self {
+            Module::Source(source) => Ok(parse(source.chars())?),
+            Module::Compiled(f) => Ok(f),
+        }
+    }
+}
+
+pub trait ModuleResolver {
+    fn resolve(&self, name: &str) -> Result<Module, ModuleError>;
+}
+
+/// The `import` builtin: run the named module's top-level code and merge its definitions into the importing scope.
+/// The module runs in a scope of its own first, so import cycles run into the scope depth limit
+pub(crate) fn import_builtin(
+    resolver: impl ModuleResolver,
+) -> impl Fn(&mut MachineState) -> Result<(), ExecuteError> {
+    move |state| {
+        let name = state.pop()?;
+        let module = resolver.resolve(to_text(&name)?)?.compile()?;
+
+        state.check_scope_depth()?;
+        state.push_scope(Scope::conditional());
+        let result = execute_in(state, &module);
+        let scope = state.pop_scope();
+        result?;
+
+        for (name, value) in scope.into_names() {
+            state.current_scope_mut().set(name, value);
+        }
+        Ok(())
+    }
+}
+
+/// Resolves module names as paths relative to a root directory. Names can't leave the root, so absolute paths
+/// and `..` are rejected
+#[cfg(feature = "host")]
+#[derive(Debug, Clone)]
+pub struct FsResolver {
+    root: PathBuf,
+}
+
+#[cfg(feature = "host")]
+impl FsResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(feature = "host")]
+impl ModuleResolver for FsResolver {
+    fn resolve(&self, name: &str) -> Result<Module, ModuleError> {
+        let relative = Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !relative {
+            return Err(ModuleError::OutsideRoot(name.into()));
+        }
+        let path = self.root.join(name);
+        if !path.is_file() {
+            return Err(ModuleError::NotFound(name.into()));
+        }
+        Ok(Module::Source(std::fs::read_to_string(path)?))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    modules: HashMap<String, Module>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, module: Module) -> &mut Self {
+        self.modules.insert(name.into(), module);
+        self
+    }
+}
+
+impl ModuleResolver for MemoryResolver {
+    fn resolve(&self, name: &str) -> Result<Module, ModuleError> {
+        self.modules
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ModuleError::NotFound(name.into()))
+    }
+}
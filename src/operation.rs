@@ -1,11 +1,79 @@
-use crate::{FlyString, Value};
+use crate::{callable::Callable, span::Span, FlyString, Value};
 
 #[derive(Debug, Clone)]
-pub enum Operation {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OperationKind {
     Push(Value),
+    PushConst(usize),
     PushId(FlyString),
     PushRaw(FlyString),
     PushArg(usize),
+    /// Call a builtin the optimizer resolved ahead of time. The name is kept for printing the program
+    Call(FlyString, Callable),
     If(Vec<Operation>, Vec<Operation>),
+    While(Vec<Operation>),
+    /// Pop a count and run the block that many times
+    Repeat(Vec<Operation>),
+    List(Vec<Operation>),
+    /// Run the first block, and the second with the error on the stack if the first fails
+    Try(Vec<Operation>, Vec<Operation>),
+    /// Pop a value and run the block of the first label equal to it, or the last block if none is
+    Case(Vec<(Value, Vec<Operation>)>, Vec<Operation>),
+    /// Call the function currently running again
+    Recurse,
     Return,
 }
+
+/// An operation together with where it was parsed from, so errors can point back at the source
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Operation {
+    pub(crate) kind: OperationKind,
+    pub(crate) span: Span,
+}
+
+impl Operation {
+    pub fn kind(&self) -> &OperationKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Blocks of code nested directly in this operation. Function literals are not blocks, they are values
+    pub fn blocks(&self) -> impl Iterator<Item = &[Operation]> {
+        let (first, branches, last) = match &self.kind {
+            OperationKind::If(first, second) | OperationKind::Try(first, second) => {
+                (Some(first), &[][..], Some(second))
+            }
+            OperationKind::While(body)
+            | OperationKind::Repeat(body)
+            | OperationKind::List(body) => (Some(body), &[][..], None),
+            OperationKind::Case(branches, default) => (None, &branches[..], Some(default)),
+            _ => (None, &[][..], None),
+        };
+        first
+            .into_iter()
+            .chain(branches.iter().map(|(_, body)| body))
+            .chain(last)
+            .map(Vec::as_slice)
+    }
+
+    pub(crate) fn blocks_mut(&mut self) -> impl Iterator<Item = &mut Vec<Operation>> {
+        let (first, branches, last) = match &mut self.kind {
+            OperationKind::If(first, second) | OperationKind::Try(first, second) => {
+                (Some(first), &mut [][..], Some(second))
+            }
+            OperationKind::While(body)
+            | OperationKind::Repeat(body)
+            | OperationKind::List(body) => (Some(body), &mut [][..], None),
+            OperationKind::Case(branches, default) => (None, &mut branches[..], Some(default)),
+            _ => (None, &mut [][..], None),
+        };
+        first
+            .into_iter()
+            .chain(branches.iter_mut().map(|(_, body)| body))
+            .chain(last)
+    }
+}
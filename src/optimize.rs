@@ -0,0 +1,241 @@
+use crate::{
+    builtins::is_core_builtin,
+    callable::{Callable, CallableKind, FunctionDescriptor},
+    execute::ExecuteOptions,
+    machine_state::MachineState,
+    operation::{Operation, OperationKind},
+    FlyString, Value,
+};
+
+use std::collections::{HashMap, HashSet};
+
+/// Builtins without side effects, so calls with constant arguments can be run ahead of time. Only the core
+/// builtins of these names are: a replacement from the host or the program may do anything
+const PURE_BUILTINS: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "=",
+    "!=",
+    "and",
+    "or",
+    "not",
+    "mod",
+    "pow",
+    "min",
+    "max",
+    "sqrt",
+    "floor",
+    "ceil",
+    "abs",
+    "to-int",
+    "to-float",
+    "to-number",
+    "to-bool",
+    "parse-number",
+    "typeof",
+];
+
+struct Optimizer {
+    builtins: HashMap<FlyString, Value>,
+    /// A machine with the options' arithmetic mode to fold constants on, `None` to only resolve builtins
+    scratch: Option<MachineState>,
+}
+
+/// Optimize a program for the default builtins. See [`optimize_with`]
+pub fn optimize(f: FunctionDescriptor) -> FunctionDescriptor {
+    optimize_with(f, &ExecuteOptions::default())
+}
+
+/// Optimize a program that will run with `options`:
+/// builtin identifiers are resolved ahead of time, pure builtins with constant arguments are folded
+/// into their result and code after `ret` is removed.
+/// Names the program mentions in a string literal may be rebound by it and are left alone, but
+/// builtins redefined by imported modules are not noticed. Resolved builtins can't be serialized
+pub fn optimize_with(f: FunctionDescriptor, options: &ExecuteOptions) -> FunctionDescriptor {
+    let mut scratch = MachineState::default();
+    options.apply(&mut scratch);
+    Optimizer::new(&f, options.builtins(), Some(scratch)).function(&f)
+}
+
+/// Bind the builtins a program names to the functions themselves, so running a name no longer looks it up
+/// through every scope. This is the part of [`optimize_with`] that leaves the program's operations as they are
+pub fn resolve_builtins(f: FunctionDescriptor, options: &ExecuteOptions) -> FunctionDescriptor {
+    resolve_with(&f, options.builtins())
+}
+
+/// Resolve the builtins among `names`, e.g. the global scope of a machine the program will run on
+pub(crate) fn resolve_with(
+    f: &FunctionDescriptor,
+    names: HashMap<FlyString, Value>,
+) -> FunctionDescriptor {
+    Optimizer::new(f, names, None).function(f)
+}
+
+/// The strings in the constants of `f` and the functions nested in it, which the program may assign to
+fn mentioned_names(f: &FunctionDescriptor, names: &mut HashSet<FlyString>) {
+    for constant in f.constants.iter() {
+        if let Value::String(name) = constant {
+            names.insert(name.clone());
+        }
+    }
+    for op in f.operations.iter() {
+        nested_names(op, names);
+    }
+}
+
+fn nested_names(op: &Operation, names: &mut HashSet<FlyString>) {
+    match &op.kind {
+        OperationKind::Push(Value::Function(Callable {
+            kind: CallableKind::Function(f),
+            ..
+        })) => mentioned_names(f, names),
+        _ => op.blocks().flatten().for_each(|op| nested_names(op, names)),
+    }
+}
+
+impl Optimizer {
+    fn new(
+        f: &FunctionDescriptor,
+        mut builtins: HashMap<FlyString, Value>,
+        scratch: Option<MachineState>,
+    ) -> Self {
+        let mut mentioned = HashSet::new();
+        mentioned_names(f, &mut mentioned);
+        builtins.retain(|name, value| {
+            let is_builtin = matches!(
+                value,
+                Value::Function(Callable {
+                    kind: CallableKind::Builtin(_),
+                    ..
+                })
+            );
+            is_builtin && !mentioned.contains(name)
+        });
+        Self { builtins, scratch }
+    }
+
+    fn function(&mut self, f: &FunctionDescriptor) -> FunctionDescriptor {
+        FunctionDescriptor {
+            operations: self.block(&f.operations, &f.constants).into(),
+            code: Default::default(),
+            signature: None,
+            ..f.clone()
+        }
+    }
+
+    fn block(&mut self, operations: &[Operation], constants: &[Value]) -> Vec<Operation> {
+        use OperationKind as O;
+
+        let mut optimized: Vec<Operation> = Vec::with_capacity(operations.len());
+        for op in operations {
+            let mut op = op.clone();
+            match &mut op.kind {
+                O::Push(Value::Function(Callable {
+                    kind: CallableKind::Function(f),
+                    ..
+                })) => *f = self.function(f).into(),
+                O::PushId(id) => {
+                    if let Some(Value::Function(f)) = self.builtins.get(id) {
+                        op.kind = O::Call(id.clone(), f.clone());
+                    }
+                }
+                _ => {
+                    for block in op.blocks_mut() {
+                        *block = self.block(block, constants);
+                    }
+                }
+            }
+
+            if let O::Call(name, f) = &op.kind {
+                if let Some(folded) = self.fold(&mut optimized, name, f, constants) {
+                    optimized.push(Operation {
+                        kind: O::Push(folded),
+                        span: op.span,
+                    });
+                    continue;
+                }
+            }
+
+            let is_return = matches!(op.kind, O::Return) && self.scratch.is_some();
+            optimized.push(op);
+            if is_return {
+                break;
+            }
+        }
+        optimized
+    }
+
+    /// Run a pure builtin on the constants pushed right before it, removing the ones it consumed
+    fn fold(
+        &mut self,
+        optimized: &mut Vec<Operation>,
+        name: &FlyString,
+        f: &Callable,
+        constants: &[Value],
+    ) -> Option<Value> {
+        if !PURE_BUILTINS.iter().any(|pure| *name == *pure) || !is_core_builtin(name, f) {
+            return None;
+        }
+        let scratch = self.scratch.as_mut()?;
+        let args: Vec<_> = optimized
+            .iter()
+            .rev()
+            .map_while(|op| constant(op, constants))
+            .collect();
+        if args.is_empty() {
+            return None;
+        }
+
+        while scratch.pop_result().is_some() {}
+        args.iter().rev().for_each(|arg| scratch.push(arg.clone()));
+        f.execute(scratch).ok()?;
+        let result = scratch.pop_result()?;
+        // Builtins only take from the top, so whatever is left was not an argument
+        let consumed = args.len() - scratch.stack().len();
+        optimized.truncate(optimized.len() - consumed);
+        Some(result)
+    }
+}
+
+fn constant(op: &Operation, constants: &[Value]) -> Option<Value> {
+    match &op.kind {
+        OperationKind::PushConst(index) => Some(constants[*index].clone()),
+        OperationKind::Push(v) if !matches!(v, Value::Function(_)) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn kinds(f: &FunctionDescriptor) -> Vec<&OperationKind> {
+        f.operations().iter().map(|op| &op.kind).collect()
+    }
+
+    #[test]
+    fn folds_core_builtins() {
+        let f = optimize(parse("1 2 +".chars()).unwrap());
+        assert!(matches!(
+            kinds(&f)[..],
+            [OperationKind::Push(Value::Int(3))]
+        ));
+    }
+
+    #[test]
+    fn leaves_replaced_builtins_alone() {
+        let options = ExecuteOptions::default().with_builtin("+", |state| {
+            state.push(Value::Int(42));
+            Ok(())
+        });
+        let f = optimize_with(parse("1 2 +".chars()).unwrap(), &options);
+        assert!(matches!(kinds(&f).last(), Some(OperationKind::Call(..))));
+    }
+}
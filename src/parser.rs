@@ -1,8 +1,11 @@
-use crate::callable::FunctionDescriptor;
-use crate::operation::Operation;
-use crate::Value;
+use crate::callable::{referenced_names, FunctionDescriptor};
+use crate::constant_pool::{share_constants, ConstantPool};
+use crate::lexer::{Keyword, Lexer, Token, TokenKind};
+use crate::operation::{Operation, OperationKind};
+use crate::span::Span;
+use crate::{FlyString, Value};
 
-use std::{iter::Peekable, num::ParseFloatError};
+use std::num::{ParseFloatError, ParseIntError};
 
 use thiserror::Error;
 
@@ -10,107 +13,502 @@ use thiserror::Error;
 pub enum ParseError {
     #[error("Invalid numeric literal {0}")]
     InvalidNumber(ParseFloatError),
+    #[error("Invalid integer literal {0}")]
+    InvalidInteger(ParseIntError),
     #[error("Must have an identifier after $")]
     InvalidRawPush,
     #[error("Unclosed string literal")]
     InvalidString,
+    #[error("Invalid escape sequence \\{0} in string literal")]
+    InvalidEscape(char),
+    #[error("else outside of an if block")]
+    UnexpectedElse,
+    #[error("catch outside of a try block")]
+    UnexpectedCatch,
+    #[error("elif outside of an if block")]
+    UnexpectedElif,
+    #[error("end outside of a block")]
+    UnexpectedEnd,
+    #[error("{0} without a matching end")]
+    UnclosedBlock(&'static str),
+    #[error("then without an elif")]
+    UnexpectedThen,
+    #[error("elif condition must be followed by then")]
+    MissingThen,
+    #[error("of outside of a case block")]
+    UnexpectedOf,
+    #[error("case must be followed by of, else or end")]
+    MissingOf,
+    #[error("case labels must be a single literal followed by then")]
+    InvalidCaseLabel,
+    #[error("Unmatched [ or ]")]
+    UnmatchedBracket,
+    /// Found by [`type_check`](crate::typecheck::type_check) rather than while parsing
+    #[error("Type mismatch: {name} expects {expected}, got {actual}")]
+    TypeMismatch {
+        name: FlyString,
+        expected: FlyString,
+        actual: &'static str,
+    },
+    #[error("{1} (at {0})")]
+    At(Span, Box<ParseError>),
+}
+
+impl ParseError {
+    /// Attach the location of the offending token, unless a more precise one is already known
+    pub(crate) fn at(self, span: Span) -> Self {
+        match self {
+            Self::At(..) => self,
+            e => Self::At(span, Box::new(e)),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::At(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// The error without its location
+    pub fn kind(&self) -> &ParseError {
+        match self {
+            Self::At(_, e) => e,
+            e => e,
+        }
+    }
 }
 
 pub fn parse<I>(input: I) -> Result<FunctionDescriptor, ParseError>
 where
     I: Iterator<Item = char>,
 {
-    parse_internal(&mut input.peekable())
+    parse_with(input, &mut Context::default())
+}
+
+/// Like [`parse`], but carries on after an error to report every one, in the order they were found. Parsing
+/// resumes after the token or block the error was found in
+pub fn parse_all_errors<I>(input: I) -> Result<FunctionDescriptor, Vec<ParseError>>
+where
+    I: Iterator<Item = char>,
+{
+    let mut cx = Context {
+        errors: Some(vec![]),
+        ..Default::default()
+    };
+    let result = parse_with(input, &mut cx);
+    let mut errors = cx.errors.unwrap_or_default();
+    match result {
+        Ok(f) if errors.is_empty() => Ok(f),
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.push(e);
+            Err(errors)
+        }
+    }
 }
 
-fn read_while<I, F>(input: &mut Peekable<I>, c: Option<char>, f: F) -> String
+fn parse_with<I>(input: I, cx: &mut Context) -> Result<FunctionDescriptor, ParseError>
 where
     I: Iterator<Item = char>,
-    F: Fn(&char) -> bool,
 {
-    let mut s = String::with_capacity(10);
-    if let Some(c) = c {
-        s.push(c);
+    let mut input = Lexer::new(input);
+    let mut block = parse_internal(&mut input, cx)?;
+    while let Err(e) = block.expect_eof() {
+        cx.recover(e)?;
+        // A stray `else` or the like doesn't end the program when recovering, the rest still gets parsed
+        let rest = parse_internal(&mut input, cx)?;
+        block.absorb(&rest);
+        block.operations.extend(rest.operations);
+        (block.terminator, block.end) = (rest.terminator, rest.end);
+    }
+    block.place_rest();
+    let Block {
+        mut operations,
+        num_args,
+        variadic,
+        ..
+    } = block;
+
+    let constants = std::mem::take(&mut cx.pool).finish();
+    share_constants(&mut operations, &constants);
+    Ok(FunctionDescriptor {
+        referenced_names: referenced_names(&operations),
+        operations: operations.into(),
+        constants,
+        num_args,
+        variadic,
+        ..Default::default()
+    })
+}
+
+/// State shared by all blocks of the program being parsed
+#[derive(Default)]
+struct Context {
+    pool: ConstantPool,
+    /// The errors recovered from so far, or `None` to stop at the first one
+    errors: Option<Vec<ParseError>>,
+}
+
+impl Context {
+    /// Record `e` to carry on parsing, or give it back if parsing should stop
+    fn recover(&mut self, e: ParseError) -> Result<(), ParseError> {
+        match &mut self.errors {
+            Some(errors) => {
+                errors.push(e);
+                Ok(())
+            }
+            None => Err(e),
+        }
+    }
+}
+
+/// Stands in for the index of the rest list until the function's arity is known
+const REST_PLACEHOLDER: usize = usize::MAX;
+
+/// Point `$*` at the argument slot after the numbered ones, where calls put the rest list
+fn place_rest(operations: &mut [Operation], index: usize) {
+    for op in operations {
+        match &mut op.kind {
+            OperationKind::PushArg(i) if *i == REST_PLACEHOLDER => *i = index,
+            _ => op.blocks_mut().for_each(|block| place_rest(block, index)),
+        }
+    }
+}
+
+#[derive(Default, PartialEq)]
+enum Terminator {
+    #[default]
+    Eof,
+    End,
+    Else,
+    Elif,
+    Then,
+    Of,
+    Catch,
+    CloseList,
+}
+
+#[derive(Default)]
+struct Block {
+    operations: Vec<Operation>,
+    num_args: usize,
+    /// Whether `$*` was used
+    variadic: bool,
+    terminator: Terminator,
+    /// Where the terminator was read
+    end: Span,
+}
+
+impl Block {
+    /// Check that a keyword block was not closed by `else`, `elif`, `then`, `of`, `catch` or `]`
+    fn expect_end(&self) -> Result<(), ParseError> {
+        match self.terminator {
+            Terminator::Else => Err(ParseError::UnexpectedElse.at(self.end)),
+            Terminator::Elif => Err(ParseError::UnexpectedElif.at(self.end)),
+            Terminator::Then => Err(ParseError::UnexpectedThen.at(self.end)),
+            Terminator::Of => Err(ParseError::UnexpectedOf.at(self.end)),
+            Terminator::Catch => Err(ParseError::UnexpectedCatch.at(self.end)),
+            Terminator::CloseList => Err(ParseError::UnmatchedBracket.at(self.end)),
+            Terminator::End | Terminator::Eof => Ok(()),
+        }
+    }
+
+    /// Check that a block opened by `keyword` was closed by `end` rather than the end of the input
+    fn expect_closed(&self, keyword: &'static str) -> Result<(), ParseError> {
+        match self.terminator {
+            Terminator::Eof => Err(ParseError::UnclosedBlock(keyword)),
+            _ => self.expect_end(),
+        }
+    }
+
+    /// Check that the program itself ran to the end of the input
+    fn expect_eof(&self) -> Result<(), ParseError> {
+        match self.terminator {
+            Terminator::End => Err(ParseError::UnexpectedEnd.at(self.end)),
+            _ => self.expect_end(),
+        }
+    }
+
+    /// Record what ended the block, for the operation that was being parsed
+    fn terminate(&mut self, terminator: Terminator) -> Result<Option<OperationKind>, ParseError> {
+        self.terminator = terminator;
+        Ok(None)
+    }
+
+    /// Take on the arguments used by a block nested in this one
+    fn absorb(&mut self, body: &Block) {
+        self.num_args = usize::max(self.num_args, body.num_args);
+        self.variadic |= body.variadic;
     }
-    while let Some(c) = input.peek() {
-        if !f(c) {
+
+    /// Finish a function body once its arity is known
+    fn place_rest(&mut self) {
+        if self.variadic {
+            place_rest(&mut self.operations, self.num_args);
+        }
+    }
+}
+
+impl From<Block> for FunctionDescriptor {
+    fn from(mut value: Block) -> Self {
+        value.place_rest();
+        Self {
+            referenced_names: referenced_names(&value.operations),
+            operations: value.operations.into(),
+            num_args: value.num_args,
+            variadic: value.variadic,
+            ..Default::default()
+        }
+    }
+}
+
+fn parse_internal<I>(input: &mut Lexer<I>, cx: &mut Context) -> Result<Block, ParseError>
+where
+    I: Iterator<Item = char>,
+{
+    let mut f = Block::default();
+
+    loop {
+        let Some(token) = input.next() else {
+            f.end = input.location();
             break;
+        };
+        let Token { kind, span } = match token {
+            Ok(token) => token,
+            Err(e) => {
+                cx.recover(e)?;
+                input.skip_token();
+                continue;
+            }
+        };
+        match parse_operation(input, cx, &mut f, kind, span).map_err(|e| e.at(span)) {
+            Ok(Some(kind)) => f.operations.push(Operation { kind, span }),
+            Ok(None) => {
+                f.end = span;
+                break;
+            }
+            // The operation's blocks were read to their end, so parsing carries on after them
+            Err(e) => cx.recover(e)?,
         }
-        s.push(*c);
-        input.next();
     }
-    s
+
+    Ok(f)
 }
 
-fn read_string<I>(input: &mut Peekable<I>, c: Option<char>) -> String
+/// Parse the rest of an `if` whose keyword was just read. `elif <condition> then` continues the chain
+/// in the else branch, as if it were a nested `if` there
+fn parse_if<I>(
+    input: &mut Lexer<I>,
+    cx: &mut Context,
+    f: &mut Block,
+) -> Result<OperationKind, ParseError>
 where
     I: Iterator<Item = char>,
 {
-    read_while(input, c, |c| !c.is_ascii_whitespace())
+    let if_body = parse_internal(input, cx)?;
+    let else_body = match if_body.terminator {
+        Terminator::Else => {
+            let else_body = parse_internal(input, cx)?;
+            else_body.expect_closed("if")?;
+            f.absorb(&else_body);
+            else_body.operations
+        }
+        Terminator::Elif => {
+            let mut condition = parse_internal(input, cx)?;
+            if condition.terminator != Terminator::Then {
+                return Err(ParseError::MissingThen.at(condition.end));
+            }
+            f.absorb(&condition);
+            let kind = parse_if(input, cx, f)?;
+            condition.operations.push(Operation {
+                kind,
+                span: condition.end,
+            });
+            condition.operations
+        }
+        _ => {
+            if_body.expect_closed("if")?;
+            vec![]
+        }
+    };
+    f.absorb(&if_body);
+    Ok(OperationKind::If(if_body.operations, else_body))
 }
 
-fn parse_internal<I>(input: &mut Peekable<I>) -> Result<FunctionDescriptor, ParseError>
+/// Parse the rest of `case of <literal> then <body> ... else <body> end`, whose keyword was just read
+fn parse_case<I>(
+    input: &mut Lexer<I>,
+    cx: &mut Context,
+    f: &mut Block,
+) -> Result<OperationKind, ParseError>
 where
     I: Iterator<Item = char>,
 {
-    use Operation as O;
-
-    let mut f = FunctionDescriptor::default();
-
-    while let Some(c) = input.next() {
-        let op = match c {
-            c if c.is_ascii_whitespace() => continue,
-            c if c.is_ascii_digit() => {
-                let s = read_while(input, Some(c), |c| c.is_ascii_digit() || *c == '.');
-                s.parse()
-                    .map(Value::Number)
-                    .map(O::Push)
-                    .map_err(ParseError::InvalidNumber)?
+    let head = parse_internal(input, cx)?;
+    if !head.operations.is_empty() {
+        return Err(ParseError::MissingOf.at(head.operations[0].span));
+    }
+
+    let mut branches = vec![];
+    let mut terminator = head.terminator;
+    let mut end = head.end;
+    while terminator == Terminator::Of {
+        let label = parse_internal(input, cx)?;
+        let value = match label.operations.as_slice() {
+            [Operation {
+                kind: OperationKind::PushConst(index),
+                ..
+            }] if label.terminator == Terminator::Then => cx.pool.get(*index).clone(),
+            [Operation {
+                kind: OperationKind::Push(Value::Nil),
+                ..
+            }] if label.terminator == Terminator::Then => Value::Nil,
+            _ => return Err(ParseError::InvalidCaseLabel.at(label.end)),
+        };
+        let body = parse_internal(input, cx)?;
+        f.absorb(&body);
+        (terminator, end) = (body.terminator, body.end);
+        branches.push((value, body.operations));
+    }
+
+    let default = match terminator {
+        Terminator::Else => {
+            let default = parse_internal(input, cx)?;
+            default.expect_closed("case")?;
+            f.absorb(&default);
+            default.operations
+        }
+        terminator => {
+            Block {
+                terminator,
+                end,
+                ..Default::default()
             }
-            '$' => {
-                let name = read_string(input, None);
-                if name.is_empty() {
-                    return Err(ParseError::InvalidRawPush);
-                }
-
-                if let Ok(index) = name.parse::<usize>() {
-                    f.num_args = usize::max(index + 1, f.num_args);
-                    O::PushArg(index)
-                } else {
-                    O::PushRaw(name.into())
-                }
+            .expect_closed("case")?;
+            vec![]
+        }
+    };
+    Ok(OperationKind::Case(branches, default))
+}
+
+/// Parse the operation starting with a token of `kind`. Returns `None` after recording a block terminator in `f`
+fn parse_operation<I>(
+    input: &mut Lexer<I>,
+    cx: &mut Context,
+    f: &mut Block,
+    kind: TokenKind,
+    span: Span,
+) -> Result<Option<OperationKind>, ParseError>
+where
+    I: Iterator<Item = char>,
+{
+    use OperationKind as O;
+
+    let op = match kind {
+        TokenKind::Int(x) => O::PushConst(cx.pool.add(Value::Int(x))),
+        TokenKind::Float(x) => O::PushConst(cx.pool.add(Value::Number(x))),
+        TokenKind::String(s) => O::PushConst(cx.pool.add(Value::String(s.into()))),
+        TokenKind::Identifier(name) => O::PushId(name.into()),
+        TokenKind::RawIdentifier(name) => O::PushRaw(name.into()),
+        TokenKind::Arg(index) => {
+            f.num_args = usize::max(index + 1, f.num_args);
+            O::PushArg(index)
+        }
+        TokenKind::RestArgs => {
+            f.variadic = true;
+            O::PushArg(REST_PLACEHOLDER)
+        }
+        TokenKind::OpenList => {
+            let body = parse_internal(input, cx)?;
+            if body.terminator != Terminator::CloseList {
+                return Err(ParseError::UnmatchedBracket);
             }
-            '\'' => {
-                let s = read_while(input, None, |c| !c.is_ascii_whitespace() && *c != '\'');
-                let Some('\'') = input.next() else {
-                    return Err(ParseError::InvalidString);
+            f.absorb(&body);
+            O::List(body.operations)
+        }
+        TokenKind::CloseList => return f.terminate(Terminator::CloseList),
+        TokenKind::Keyword(keyword) => match keyword {
+            Keyword::End => return f.terminate(Terminator::End),
+            Keyword::Else => return f.terminate(Terminator::Else),
+            Keyword::Elif => return f.terminate(Terminator::Elif),
+            Keyword::Then => return f.terminate(Terminator::Then),
+            Keyword::Of => return f.terminate(Terminator::Of),
+            Keyword::Catch => return f.terminate(Terminator::Catch),
+            Keyword::Fn => {
+                let body = parse_internal(input, cx)?;
+                body.expect_closed("fn")?;
+                let f = FunctionDescriptor {
+                    span,
+                    ..body.into()
                 };
-                O::Push(s.into())
+                O::Push(f.into())
             }
-            c => {
-                let s = read_string(input, Some(c));
-                match s.as_str() {
-                    "end" => break,
-                    "fn" => {
-                        let f = parse_internal(input)?;
-                        O::Push(f.into())
+            Keyword::If => parse_if(input, cx, f)?,
+            Keyword::Case => parse_case(input, cx, f)?,
+            Keyword::Try => {
+                let body = parse_internal(input, cx)?;
+                let handler = match body.terminator {
+                    Terminator::Catch => {
+                        let handler = parse_internal(input, cx)?;
+                        handler.expect_closed("try")?;
+                        handler
                     }
-                    "if" => {
-                        let FunctionDescriptor {
-                            operations,
-                            num_args,
-                            ..
-                        } = parse_internal(input)?;
-                        f.num_args = usize::max(f.num_args, num_args);
-                        O::If(operations, vec![])
+                    _ => {
+                        body.expect_closed("try")?;
+                        Block::default()
                     }
-                    "ret" => O::Return,
-                    _ => O::PushId(s.into()),
-                }
+                };
+                f.absorb(&body);
+                f.absorb(&handler);
+                O::Try(body.operations, handler.operations)
             }
-        };
-        f.operations.push(op);
+            Keyword::While => {
+                let body = parse_internal(input, cx)?;
+                body.expect_closed("while")?;
+                f.absorb(&body);
+                O::While(body.operations)
+            }
+            Keyword::Repeat => {
+                let body = parse_internal(input, cx)?;
+                body.expect_closed("repeat")?;
+                f.absorb(&body);
+                O::Repeat(body.operations)
+            }
+            Keyword::Ret => O::Return,
+            Keyword::Recurse => O::Recurse,
+            Keyword::Nil => O::Push(Value::Nil),
+        },
+    };
+    Ok(Some(op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_error_in_order() {
+        let errors = parse_all_errors("1 ] 2\nif 3 end end\n'abc".chars()).unwrap_err();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                let span = e.span().unwrap();
+                (span.line, span.column)
+            })
+            .collect();
+        assert_eq!(found, [(1, 3), (2, 10), (3, 1)]);
+        assert!(matches!(errors[0].kind(), ParseError::UnmatchedBracket));
+        assert!(matches!(errors[2].kind(), ParseError::InvalidString));
     }
 
-    Ok(f)
+    #[test]
+    fn agrees_with_parse_on_valid_programs() {
+        let source = "fn $0 1 + end 'inc' := 2 inc";
+        let program = parse_all_errors(source.chars()).unwrap();
+        assert_eq!(
+            program.disassemble(),
+            parse(source.chars()).unwrap().disassemble()
+        );
+    }
 }
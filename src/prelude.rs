@@ -0,0 +1,18 @@
+use crate::{
+    builtins::get_builtins, execute::execute_in, machine_state::MachineState, parser::parse,
+    scope::Scope, FlyString, Value,
+};
+
+use once_cell::sync::Lazy;
+
+const SOURCE: &str = include_str!("prelude.ssl");
+
+/// Everything the prelude defines, compiled once on first use
+pub(crate) static PRELUDE: Lazy<Vec<(FlyString, Value)>> = Lazy::new(|| {
+    let prelude = parse(SOURCE.chars()).expect("The prelude parses");
+    let mut state = MachineState::default();
+    state.push_scope(Scope::global(vec![], get_builtins()));
+    state.push_scope(Scope::conditional());
+    execute_in(&mut state, &prelude).expect("The prelude runs");
+    state.pop_scope().into_names().collect()
+});
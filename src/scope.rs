@@ -1,26 +1,36 @@
-use crate::{builtins::get_builtins, FlyString, Value};
+use crate::{
+    callable::{Callable, CallableKind},
+    FlyString, Value,
+};
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Scope {
     names: HashMap<FlyString, Value>,
+    captured_names: Arc<HashMap<FlyString, Value>>,
     args: Vec<Value>,
     pub(crate) inherits_from_parent: bool,
 }
 
 impl Scope {
-    pub fn global(args: Vec<Value>) -> Self {
+    pub fn global(args: Vec<Value>, builtins: HashMap<FlyString, Value>) -> Self {
         Self {
-            names: get_builtins(),
+            names: builtins,
+            captured_names: Default::default(),
             args,
             inherits_from_parent: false,
         }
     }
 
-    pub fn function(args: Vec<Value>, captured_names: HashMap<FlyString, Value>) -> Self {
+    pub fn function(args: Vec<Value>, captured_names: Arc<HashMap<FlyString, Value>>) -> Self {
         Self {
-            names: captured_names,
+            names: Default::default(),
+            captured_names,
             args,
             inherits_from_parent: false,
         }
@@ -29,24 +39,86 @@ impl Scope {
     pub fn conditional() -> Self {
         Self {
             names: Default::default(),
+            captured_names: Default::default(),
             args: Default::default(),
             inherits_from_parent: true,
         }
     }
 
-    pub fn names(&self) -> &HashMap<FlyString, Value> {
-        &self.names
+    /// Snapshot of the given names as currently visible in this scope; names that aren't bound are skipped
+    pub fn capture(&self, names: &[FlyString]) -> Arc<HashMap<FlyString, Value>> {
+        names
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.get(name)?.current())))
+            .collect::<HashMap<_, _>>()
+            .into()
+    }
+
+    /// Like [`Self::capture`], but the captures share the variables with this scope, so assignments on
+    /// either side are seen by both. Builtins are captured by value, they can't be assigned anyway
+    pub fn share(&mut self, names: &[FlyString]) -> Arc<HashMap<FlyString, Value>> {
+        let mut shared = HashMap::new();
+        for name in names {
+            let value = match self.names.get_mut(name) {
+                Some(
+                    value @ Value::Function(Callable {
+                        kind: CallableKind::Builtin(_),
+                        ..
+                    }),
+                ) => value.clone(),
+                Some(value @ Value::Shared(_)) => value.clone(),
+                Some(value) => {
+                    let cell = Value::Shared(Arc::new(Mutex::new(value.clone())));
+                    *value = cell.clone();
+                    cell
+                }
+                // A copy captured by an enclosing `^` can't be shared with its origin any more
+                None => match self.captured_names.get(name) {
+                    Some(value @ Value::Shared(_)) => value.clone(),
+                    Some(value) => Value::Shared(Arc::new(Mutex::new(value.clone()))),
+                    None => continue,
+                },
+            };
+            shared.insert(name.clone(), value);
+        }
+        shared.into()
     }
 
-    pub fn get(&self, id: &FlyString) -> Option<Value> {
-        self.names.get(id).cloned()
+    pub fn get(&self, id: &FlyString) -> Option<&Value> {
+        self.names.get(id).or_else(|| self.captured_names.get(id))
     }
 
     pub fn set(&mut self, name: FlyString, value: Value) {
         self.names.insert(name, value);
     }
 
-    pub fn get_arg(&self, index: usize) -> Option<Value> {
-        self.args.get(index).cloned()
+    pub fn get_arg(&self, index: usize) -> Option<&Value> {
+        self.args.get(index)
+    }
+
+    /// Names assigned in this scope, without captured ones
+    pub fn names(&self) -> impl Iterator<Item = (&FlyString, &Value)> {
+        self.names.iter()
+    }
+
+    pub fn captured_names(&self) -> impl Iterator<Item = (&FlyString, &Value)> {
+        self.captured_names.iter()
+    }
+
+    pub fn args(&self) -> &[Value] {
+        &self.args
+    }
+
+    pub(crate) fn into_names(self) -> impl Iterator<Item = (FlyString, Value)> {
+        self.names.into_iter()
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn retain_names(&mut self, f: impl FnMut(&FlyString, &mut Value) -> bool) {
+        self.names.retain(f);
+    }
+
+    pub(crate) fn set_args(&mut self, args: Vec<Value>) {
+        self.args = args;
     }
 }
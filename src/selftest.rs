@@ -0,0 +1,777 @@
+use crate::{callable::CallableKind, value::format_number, Interpreter, Value};
+
+enum Expected {
+    Stack(&'static [&'static str]),
+    Failure,
+}
+
+struct Case {
+    name: &'static str,
+    source: &'static str,
+    expected: Expected,
+}
+
+const fn case(name: &'static str, source: &'static str, stack: &'static [&'static str]) -> Case {
+    Case {
+        name,
+        source,
+        expected: Expected::Stack(stack),
+    }
+}
+
+const fn failure(name: &'static str, source: &'static str) -> Case {
+    Case {
+        name,
+        source,
+        expected: Expected::Failure,
+    }
+}
+
+const CASES: &[Case] = &[
+    case("add", "2 3 +", &["5"]),
+    case("sub", "2 3 -", &["1"]),
+    case("mul", "2 3 *", &["6"]),
+    case("div", "2 6 /", &["3.0"]),
+    case(
+        "int arithmetic stays int",
+        "4 3 + 'int' ! 4 3 * 'int' !",
+        &[],
+    ),
+    case("mixed arithmetic is float", "4 3.5 + 'number' !", &[]),
+    case("division is float", "2 6 / 'number' !", &[]),
+    case("division by zero is IEEE by default", "0 1 /", &["inf"]),
+    case(
+        "int and float equality",
+        "1 1.0 = 1 1.5 =",
+        &["true", "false"],
+    ),
+    failure("int overflow", "1 9223372036854775807 +"),
+    failure("int literal overflow", "9223372036854775808"),
+    failure("int mod by zero", "0 1 mod"),
+    case(
+        "to-int and to-float",
+        "-2.7 to-int 2 to-float 'number' !",
+        &["-2"],
+    ),
+    case("mod", "3 7 mod -3 7 mod", &["1", "1"]),
+    case("pow", "3 2 pow", &["8"]),
+    case("min and max", "1 2 min 1 2 max", &["1", "2"]),
+    case("sqrt", "9 sqrt", &["3.0"]),
+    case(
+        "rounding",
+        "1.5 floor 1.5 ceil -1.5 abs",
+        &["1.0", "2.0", "1.5"],
+    ),
+    case("negative literal", "-3 -0.5", &["-3", "-0.5"]),
+    case(
+        "exponent literal",
+        "1e6 2.5E-3 1e+2",
+        &["1000000.0", "0.0025", "100.0"],
+    ),
+    case("minus is still subtraction", "5 3 -", &["-2"]),
+    case(
+        "hex and binary literals",
+        "0xFF 0X1a 0b1010 -0x10",
+        &["255", "26", "10", "-16"],
+    ),
+    case("hex literal with the sign bit set", "0xFFFFFFFFFFFFFFFF", &["-1"]),
+    failure("hex literal without digits", "0x"),
+    failure("invalid binary digit", "0b102"),
+    failure("hex literal too long", "0x10000000000000000"),
+    case(
+        "bitwise operators",
+        "0b1100 0b1010 band 0b1100 0b1010 bor 0b1100 0b1010 bxor 0 bnot",
+        &["8", "14", "6", "-1"],
+    ),
+    case("shifts", "4 1 shl 2 -16 shr", &["16", "-4"]),
+    case("bitwise on whole floats", "1.0 3 band", &["1"]),
+    failure("bitwise on fractional floats", "1.5 3 band"),
+    failure("shift by 64", "64 1 shl"),
+    failure("negative shift", "-1 1 shr"),
+    failure("missing exponent", "1e"),
+    case("lt", "1 2 <", &["false"]),
+    case("equal numbers", "2 2 = 2 3 =", &["true", "false"]),
+    case("equal strings", "'a' 'a' = 'a' 'b' !=", &["true", "true"]),
+    case("equal across types", "1 '1' = 1 '1' !=", &["false", "true"]),
+    case(
+        "equal functions",
+        "fn end 'f' := $f $f = $f fn end =",
+        &["true", "false"],
+    ),
+    case("equal builtins", "$+ $+ = $+ $- =", &["true", "false"]),
+    case("quoted builtin", "&+ $+ = [ 1 2 ] 10 1 &+ bind map", &["true", "[11, 12]"]),
+    case("quoted function", "fn 1 end 'f' := &f typeof", &["<function>", "function"]),
+    failure("quoted unbound name", "&missing"),
+    case("and", "1 1 = 1 1 = and 1 1 = 1 2 = and", &["true", "false"]),
+    case("or", "1 2 = 1 1 = or 1 2 = 1 2 = or", &["true", "false"]),
+    case("not", "1 1 = not", &["false"]),
+    failure("logic type mismatch", "1 1 = 1 and"),
+    failure("arithmetic type mismatch", "'a' 1 +"),
+    failure("arithmetic underflow", "1 +"),
+    case("string with spaces", "'Hello, world'", &["Hello, world"]),
+    case("string escapes", r"'it\'s\t\\ \n'", &["it's\t\\ \n"]),
+    failure("unterminated string", "'abc"),
+    failure("unknown escape", r"'\q'"),
+    case(
+        "list literal",
+        "[ 1 2 3 + ] [] [[1] 'a']",
+        &["[1, 5]", "[]", "[[1], a]"],
+    ),
+    case("list push and len", "[ 1 ] 2 push len", &["2"]),
+    case("list get", "[ 1 2 ] 1 get", &["2"]),
+    failure("list get out of range", "[ 1 2 ] 2 get"),
+    failure("list get fractional index", "[ 1 2 ] 0.5 get"),
+    case("list set", "[ 1 2 ] 0 'a' set", &["[a, 2]"]),
+    case(
+        "list set copies",
+        "[ 1 ] 'l' := l 0 2 set l",
+        &["[2]", "[1]"],
+    ),
+    case(
+        "list head and tail",
+        "[ 1 2 3 ] 'l' := l head l tail",
+        &["1", "[2, 3]"],
+    ),
+    failure("head of empty list", "[] head"),
+    case("list equality", "[ 1 [ 2 ] ] [ 1 [ 2 ] ] =", &["true"]),
+    failure("unmatched bracket", "1 ]"),
+    failure("unclosed list", "[ 1"),
+    case("nil", "nil nil is-nil 0 is-nil", &["nil", "true", "false"]),
+    case("nil type", "nil 'nil' !", &[]),
+    case("nil equality", "nil nil = nil 0 =", &["true", "false"]),
+    case("assign", "7 'x' := x x", &["7", "7"]),
+    case("raw push of a value", "7 'x' := $x", &["7"]),
+    failure("unbound identifier", "missing"),
+    case("assert type", "5.5 'number' ! 5 'int' !", &[]),
+    failure("failed type assertion", "'x' 'number' !"),
+    case("function call", "fn $0 $1 + end 'add' := 1 2 add", &["3"]),
+    case(
+        "raw push of a function",
+        "fn end 'f' := $f",
+        &["<function>"],
+    ),
+    case("if taken", "2 1 < if 'yes' end", &["yes"]),
+    case("if not taken", "1 2 < if 'yes' end", &[]),
+    case("else not taken", "2 1 < if 'yes' else 'no' end", &["yes"]),
+    case("else taken", "1 2 < if 'yes' else 'no' end", &["no"]),
+    failure("else scope", "1 2 < if else 1 'x' := end x"),
+    case(
+        "while",
+        "0 'i' := 3 i < while 1 i + 'i' := i 3 i < end",
+        &["1", "2", "3"],
+    ),
+    case("while not entered", "1 2 < while 'body' 1 2 < end", &[]),
+    case(
+        "return from while",
+        "fn 2 1 < while 1 ret end 2 end 'f' := f",
+        &["1"],
+    ),
+    case("repeat", "3 repeat 'x' end", &["x", "x", "x"]),
+    case("repeat zero times", "0 repeat 'x' end -1 repeat 'y' end", &[]),
+    case(
+        "repeat keeps variables per loop",
+        "0 'i' := 3 repeat 1 i + 'i' := i end",
+        &["1", "2", "3"],
+    ),
+    case(
+        "nested repeat",
+        "[ 2 repeat 3 repeat 1 end end ] len",
+        &["6"],
+    ),
+    case("return from repeat", "fn 5 repeat 1 ret end 2 end 'f' := f", &["1"]),
+    case(
+        "error in repeat is caught",
+        "try 2 repeat 'boom' throw end catch end 3 repeat 1 end",
+        &["<error: boom>", "1", "1", "1"],
+    ),
+    failure("repeat needs an int", "'3' repeat 1 end"),
+    case("return", "fn 1 ret 2 end 'f' := f", &["1"]),
+    case(
+        "return from if",
+        "fn 2 1 < if 1 ret end 2 end 'f' := f",
+        &["1"],
+    ),
+    case(
+        "recurse",
+        "fn $0 1 < if $0 1 $0 - recurse * else 1 end end 'fact' := 5 fact",
+        &["120"],
+    ),
+    case(
+        "recurse in a local function",
+        "fn fn $0 0 < if 1 $0 - recurse end end 'count' := $0 count end 'f' := 3 f",
+        &[],
+    ),
+    case(
+        "recurse in a closure",
+        "2 'n' := fn $0 0 < if n 1 $0 - recurse + else 0 end end ^ 'f' := 3 f",
+        &["6"],
+    ),
+    failure("recurse outside a function", "recurse"),
+    failure("runaway recursion", "fn recurse 1 + end 'f' := f"),
+    case(
+        "deep tail recursion",
+        "fn $0 0 < if 1 $0 - recurse else 'done' end end 'f' := 100000 f",
+        &["done"],
+    ),
+    case(
+        "tail call before ret",
+        "fn $0 0 < if 1 $0 - g ret end 'done' end 'g' := 100000 g",
+        &["done"],
+    ),
+    case("closure", "1 'a' := fn a end ^ 'f' := 2 'a' := f", &["1"]),
+    case("bind", "5 1 fn $0 end bind 'f' := f", &["5"]),
+    failure("bind too many", "1 2 2 fn $0 end bind"),
+    case("call", "2 3 $+ call fn 'called' end call", &["5", "called"]),
+    failure("call a non-function", "1 call"),
+    case("apply", "[ 1 2 ] fn $0 $1 - end apply", &["1"]),
+    case("apply a builtin", "[ 2 3 ] $- apply", &["1"]),
+    case("apply a variadic function", "[ 1 2 3 ] fn $0 $* end apply", &["1", "[2, 3]"]),
+    case(
+        "apply a bound function",
+        "[ 2 ] 10 1 fn $0 $1 - end bind apply",
+        &["-8"],
+    ),
+    failure("apply with too few arguments", "[ 1 ] fn $0 $1 + end apply"),
+    failure("apply with too many arguments", "[ 1 2 3 ] fn $0 end apply"),
+    failure("call with too few arguments", "fn $0 $1 + end 'f' := 1 f"),
+    case(
+        "arity mismatch is caught",
+        "try fn $0 $1 + end 'f' := 1 f catch error-message end",
+        &["Arity mismatch: Expected 2 arguments, got 1"],
+    ),
+    case(
+        "compose",
+        "5 fn 1 $0 + end fn 2 $0 * end compose call",
+        &["11"],
+    ),
+    case("compose builtins", "2 3 $+ fn 10 $0 * end compose call", &["32"]),
+    case(
+        "curry",
+        "fn $0 $1 - end curry 'c' := 3 c 'g' := 10 g 20 g",
+        &["7", "17"],
+    ),
+    case(
+        "curry a bound function",
+        "1 1 fn $0 $1 $2 + + end bind curry 'c' := 2 c 3 swap call",
+        &["6"],
+    ),
+    failure("curry a builtin", "$+ curry"),
+    failure("curry a variadic function", "fn $* end curry"),
+    case(
+        "bound function called repeatedly",
+        "1 2 2 fn $0 $1 + end bind 'f' := f f + f +",
+        &["9"],
+    ),
+    case(
+        "coroutine",
+        "fn 1 yield 2 yield end coroutine 'g' := g g g g",
+        &["1", "2", "nil", "nil"],
+    ),
+    case(
+        "coroutine keeps its locals between resumes",
+        "fn 0 'i' := 3 i < while i yield 1 i + 'i' := 3 i < end end coroutine 'g' := g g g g",
+        &["0", "1", "2", "nil"],
+    ),
+    case(
+        "yield from a nested call",
+        "fn $0 yield end 'emit' := fn 'a' emit 'b' emit end coroutine 'g' := g g",
+        &["a", "b"],
+    ),
+    case(
+        "error in a coroutine",
+        "fn 'boom' throw end coroutine 'g' := try g catch error-message end g",
+        &["boom", "nil"],
+    ),
+    case(
+        "tail calls in a coroutine",
+        "fn 0 $0 = if 'done' yield else 1 $0 - count end end 'count' := fn 5000 count end coroutine 'g' := g g",
+        &["done", "nil"],
+    ),
+    case(
+        "recurse in tail position in a coroutine",
+        "fn 0 $0 = if 'done' else 1 $0 - recurse end end 'count' := fn 5000 count yield end coroutine 'g' := g",
+        &["done"],
+    ),
+    failure(
+        "deep calls in a coroutine still hit the scope limit",
+        "fn 0 $0 = if 0 else 1 $0 - count 1 + end end 'count' := fn 5000 count end coroutine 'g' := g",
+    ),
+    case(
+        "continuation escapes from a function",
+        "fn fn 'k' := 5 k 'not reached' end call/cc 1 + yield end coroutine 'g' := g",
+        &["6"],
+    ),
+    case(
+        "returning from call/cc normally",
+        "fn fn drop 5 end call/cc 1 + yield end coroutine 'g' := g",
+        &["6"],
+    ),
+    case(
+        "continuation called again loops",
+        "fn 0 'n' :=global fn end call/cc 'again' :=global 1 n + 'n' :=global 3 n < if &again again end n yield end coroutine 'g' := g",
+        &["3"],
+    ),
+    case(
+        "continuation restores the stack and locals",
+        "fn 1 2 1 'x' := 0 'n' :=global fn end call/cc 'k' :=global x 2 'x' := 1 n + 'n' :=global 3 n < if &k k end yield depth yield end coroutine 'g' := g g",
+        &["1", "2"],
+    ),
+    failure("call/cc outside of a coroutine", "fn end call/cc"),
+    failure(
+        "continuation called through a builtin",
+        "fn fn [ 1 ] swap map end call/cc end coroutine 'g' := g",
+    ),
+    failure("yield outside of a coroutine", "1 yield"),
+    failure(
+        "yield through a builtin",
+        "fn [ 1 2 ] $yield map end coroutine 'g' := g",
+    ),
+    failure("perform without a handler", "1 'ask-user' perform"),
+    failure(
+        "scripts can't handle effects",
+        "fn 2 end 'ask-user' :=global 1 'ask-user' perform",
+    ),
+    case("assert", "1 1 = assert 1 1 = 'holds' assert", &[]),
+    failure("failed assert", "1 2 = assert"),
+    case(
+        "failed assert with a message",
+        "try 1 2 = 'differ' assert catch error-message end",
+        &["Assertion failed: differ"],
+    ),
+    failure("exit can't be caught", "try 3 exit catch 'caught' end"),
+    case(
+        "channel",
+        "chan 'c' := 1 c send 2 c send c recv c recv",
+        &["1", "2"],
+    ),
+    failure("send to a non-channel", "1 2 send"),
+    case("spawn", "fn 2 3 + end spawn recv", &["5"]),
+    case(
+        "spawned function talks through a channel",
+        "chan 'c' := fn 'hi' c send end ^ spawn recv c recv",
+        &["nil", "hi"],
+    ),
+    case(
+        "error in a spawned function",
+        "fn 'boom' throw end spawn recv error-message",
+        &["boom"],
+    ),
+    case("join", "fn 2 3 + end spawn join", &["5"]),
+    case(
+        "cancel a spawned function",
+        "chan 'c' := fn c recv end ^ spawn 't' := t cancel t join error-message",
+        &["Cancelled"],
+    ),
+    case(
+        "task group waits for its tasks",
+        "chan 'c' := fn fn 5 c send end ^ spawn drop end ^ task-group c recv",
+        &["5"],
+    ),
+    case(
+        "failing task group cancels its tasks",
+        "chan 'c' := chan 'out' := try fn fn c recv end ^ spawn out send 'boom' throw end ^ task-group catch drop end out recv join error-message",
+        &["Cancelled"],
+    ),
+    case(
+        "try without error",
+        "try 1 2 catch 'caught' end",
+        &["1", "2"],
+    ),
+    case(
+        "catch builtin error",
+        "try 1 + catch error-message end",
+        &["Arity mismatch: Expected 2 arguments, got 1"],
+    ),
+    case(
+        "catch thrown error",
+        "try 'boom' throw catch error-message end",
+        &["boom"],
+    ),
+    case(
+        "catch restores the stack",
+        "1 try 2 missing catch 'error' ! end",
+        &["1"],
+    ),
+    case(
+        "catch error from a call",
+        "fn 'inner' throw end 'f' := try f catch error-message end",
+        &["inner"],
+    ),
+    case(
+        "call before ret in try",
+        "fn 'boom' throw end 'f' := fn try f ret catch error-message end end call",
+        &["boom"],
+    ),
+    case(
+        "try without catch",
+        "try 'boom' throw end 'after'",
+        &["<error: boom>", "after"],
+    ),
+    failure("rethrow", "try 'boom' throw catch throw end"),
+    failure("throw non-string", "1 throw"),
+    failure("catch outside try", "catch"),
+    failure("end outside block", "1 end 2"),
+    failure("unclosed fn", "fn 1 'f' :="),
+    failure("unclosed if", "2 1 < if 1 else 2"),
+    failure("unclosed while", "2 1 < while 1 2 <"),
+    case("greater than", "1 2 > 2 1 >", &["true", "false"]),
+    case("at most", "2 2 <= 1 2 <=", &["true", "false"]),
+    case("at least", "1 2 >= 2 1 >=", &["true", "false"]),
+    case(
+        "compare int with float",
+        "1.5 1 < 1 1.0 >= 0 0.0 / 1 <",
+        &["true", "true", "false"],
+    ),
+    failure("compare lists", "[ 1 ] [ 1 ] <="),
+    failure("compare string with number", "1 'a' <"),
+    case(
+        "string comparison",
+        "'b' 'a' < 'a' 'a' >=",
+        &["true", "true"],
+    ),
+    failure("comparing a string to a number", "'a' 1 <"),
+    case("dup", "1 2 dup", &["1", "2", "2"]),
+    case("drop", "1 2 drop", &["1"]),
+    case("swap", "1 2 swap", &["2", "1"]),
+    case("over", "1 2 over", &["1", "2", "1"]),
+    case("rot", "1 2 3 rot", &["2", "3", "1"]),
+    failure("dup on an empty stack", "dup"),
+    failure("drop on an empty stack", "drop"),
+    failure("swap with one value", "1 swap"),
+    failure("over with one value", "1 over"),
+    failure("rot with two values", "1 2 rot"),
+    case("depth", "depth 'a' 'b' depth", &["0", "a", "b", "3"]),
+    case("clear-stack", "1 2 3 clear-stack depth", &["0"]),
+    case(
+        "rest arguments",
+        "fn $* end 'f' := 1 2 3 3 f",
+        &["[1, 2, 3]"],
+    ),
+    case(
+        "numbered and rest arguments",
+        "fn $* $0 end 'f' := 'a' 1 2 2 f",
+        &["[1, 2]", "a"],
+    ),
+    case("no rest arguments", "fn $* len end 'f' := 0 f", &["0"]),
+    case(
+        "rest arguments in a block",
+        "fn 1 1 = if $* end end 'f' := 5 1 f",
+        &["[5]"],
+    ),
+    failure("rest arguments without a count", "fn $* end 'f' := f"),
+    case(
+        "global assignment",
+        "fn 5 'x' :=global end 'f' := f x",
+        &["5"],
+    ),
+    failure("local assignment stays local", "fn 5 'x' := end 'f' := f x"),
+    case(
+        "shared capture",
+        "0 'n' := fn n 1 + 'n' := end ^& 'inc' := inc inc n",
+        &["2"],
+    ),
+    case(
+        "sibling shared captures",
+        "0 'n' := fn n 1 + 'n' := end ^& 'inc' := fn n end ^& 'get' := inc inc get",
+        &["2"],
+    ),
+    case(
+        "collect a closure sharing itself",
+        "fn 0 'g' := fn &g end ^& 'g' := end 'make' := make make collect-garbage",
+        &["2"],
+    ),
+    case(
+        "collect keeps reachable cycles",
+        "0 'g' := fn &g end ^& 'g' := collect-garbage &g typeof swap drop",
+        &["0", "function"],
+    ),
+    case(
+        "collect keeps cycles on the stack",
+        "fn 0 'g' := fn &g end ^& 'g' := &g end 'make' := make collect-garbage 'freed' := call typeof swap drop freed",
+        &["function", "0"],
+    ),
+    case(
+        "captures by value stay separate",
+        "0 'n' := fn n 1 + 'n' := n end ^ 'inc' := inc inc n",
+        &["1", "1", "0"],
+    ),
+    case(
+        "generator",
+        "fn 0 'c' := fn c 1 + 'c' := c end ^& end 'counter' := counter 'a' := a a counter 'b' := b a",
+        &["1", "2", "1", "3"],
+    ),
+    case(
+        "elif taken",
+        "2 'x' := 1 x = if 'one' elif 2 x = then 'two' else 'other' end",
+        &["two"],
+    ),
+    case(
+        "if before elif taken",
+        "1 'x' := 1 x = if 'one' elif 2 x = then 'two' end",
+        &["one"],
+    ),
+    case(
+        "else after elif taken",
+        "3 'x' := 1 x = if 'one' elif 2 x = then 'two' elif 3 x = then 'three' else 'other' end",
+        &["three"],
+    ),
+    case("no elif taken", "1 2 < if 'a' elif 1 2 < then 'b' end", &[]),
+    failure("elif outside of if", "elif"),
+    failure("then outside of elif", "then"),
+    failure("elif without then", "1 2 < if elif 1 2 < end"),
+    case(
+        "case branch taken",
+        "2 case of 1 then 'one' of 2 then 'two' else 'other' end",
+        &["two"],
+    ),
+    case(
+        "case default taken",
+        "3 case of 1 then 'one' of 2 then 'two' else 'other' end",
+        &["other"],
+    ),
+    case("case without match", "3 case of 1 then 'one' end", &[]),
+    case(
+        "case on strings and nil",
+        "nil case of 'a' then 1 of nil then 2 end 'a' case of 'a' then 3 end",
+        &["2", "3"],
+    ),
+    case("case compares ints to floats", "1.0 case of 1 then 'one' end", &["one"]),
+    failure("of outside of case", "1 of"),
+    failure("case label not a literal", "1 case of 1 1 + then 'two' end"),
+    failure("case label without then", "1 case of 1 'one' end"),
+    failure("code between case and of", "1 case 2 of 1 then 'one' end"),
+    case("for-each", "[ 1 2 3 ] fn $0 2 * end for-each", &["2", "4", "6"]),
+    case("for-each on an empty list", "[] fn 'never' throw end for-each", &[]),
+    case("for-each with a builtin", "0 [ 1 2 3 ] $+ for-each", &["6"]),
+    case(
+        "for-each with a shared closure",
+        "0 'n' := [ 1 2 3 ] fn n + 'n' := end ^& for-each n",
+        &["6"],
+    ),
+    failure("for-each without a function", "[ 1 ] 2 for-each"),
+    case("range", "0 4 range", &["[0, 1, 2, 3]"]),
+    case("empty range", "3 3 range 5 3 range", &["[]", "[]"]),
+    case("range with a step", "1 10 3 range-step", &["[1, 4, 7]"]),
+    case("counting down", "3 0 -1 range-step", &["[3, 2, 1]"]),
+    case("float range", "0 1 0.25 range-step", &["[0.0, 0.25, 0.5, 0.75]"]),
+    case(
+        "range near the int limit",
+        "9223372036854775806 9223372036854775807 range len",
+        &["1"],
+    ),
+    case("range for counting loops", "0 1 4 range $+ for-each", &["6"]),
+    failure("range with a zero step", "0 5 0 range-step"),
+    failure("range of a string", "0 'a' range"),
+    failure("range to infinity", "0 0 1 / range"),
+    case("map", "[ 1 2 3 ] fn $0 $0 * end map", &["[1, 4, 9]"]),
+    case("map with a bound builtin", "[ 1 2 ] 10 1 $+ bind map", &["[11, 12]"]),
+    case("filter", "0 6 range fn 2 $0 mod 0 = end filter", &["[0, 2, 4]"]),
+    case("reduce", "[ 1 2 3 4 ] 0 $+ reduce", &["10"]),
+    case(
+        "reduce passes the value so far first",
+        "[ 'b' 'c' ] [ 'a' ] fn $0 $1 push end reduce",
+        &["[a, b, c]"],
+    ),
+    case("reduce of an empty list", "[] 'start' $+ reduce", &["start"]),
+    case("map leaves the stack alone", "1 [ 2 ] fn $0 end map", &["1", "[2]"]),
+    failure("filter needs a bool", "[ 1 ] fn $0 end filter"),
+    failure("map needs a result", "[ 1 ] fn $0 drop end map"),
+    case("parse-number", "'42' parse-number ' -2.5 ' parse-number", &["42", "-2.5"]),
+    case("parse-number keeps ints", "'7' parse-number 'int' !", &[]),
+    failure("parse-number of junk", "'12abc' parse-number"),
+    failure("parse-number of a number", "12 parse-number"),
+    case(
+        "catching a failed parse",
+        "try 'x' parse-number catch error-message end",
+        &["Not a number: \"x\""],
+    ),
+    case(
+        "to-number",
+        "3 to-number 1.5 to-number '8' to-number 1 1 = to-number",
+        &["3", "1.5", "8", "1"],
+    ),
+    failure("to-number of nil", "nil to-number"),
+    case(
+        "falsy values",
+        "1 2 = to-bool nil to-bool 0 to-bool 0.0 to-bool '' to-bool [] to-bool",
+        &["false", "false", "false", "false", "false", "false"],
+    ),
+    case(
+        "truthy values",
+        "1 to-bool -0.5 to-bool 'false' to-bool [ 0 ] to-bool fn end to-bool",
+        &["true", "true", "true", "true", "true"],
+    ),
+    case("typeof", "1 typeof", &["1", "int"]),
+    case(
+        "typeof of every type",
+        "nil typeof 2.5 typeof 'a' typeof [] typeof fn end typeof",
+        &["nil", "nil", "2.5", "number", "a", "string", "[]", "list", "<function>", "function"],
+    ),
+    case("typeof of an error", "try 'e' throw end typeof", &["<error: e>", "error"]),
+    case(
+        "branching on typeof",
+        "'a' typeof case of 'string' then 'text' else 'other' end",
+        &["a", "text"],
+    ),
+    failure("typeof on an empty stack", "typeof"),
+    case(
+        "rand is in [0, 1)",
+        "rand 'r' := 0 r >= 1 r < and rand 'number' !",
+        &["true"],
+    ),
+    case(
+        "rand-int is in [low, high)",
+        "0 100 range fn drop -2 3 rand-int 'x' := -2 x >= 3 x < and end map fn $0 end filter len",
+        &["100"],
+    ),
+    case(
+        "rand-int over every int",
+        "1 -9223372036854775807 - 9223372036854775807 rand-int 'int' !",
+        &[],
+    ),
+    failure("rand-int of an empty range", "5 5 rand-int"),
+    failure("rand-int of floats", "0 1.5 rand-int"),
+    case("to-string of a float", "1.0 to-string", &["1.0"]),
+    case(
+        "printed floats read back as floats",
+        "3.0 to-string parse-number 'number' !",
+        &[],
+    ),
+    case("to-string is a string", "2.5 to-string 'string' !", &[]),
+    case(
+        "to-string of a list",
+        "[ 1 'a' nil ] to-string",
+        &["[1, 'a', nil]"],
+    ),
+    case(
+        "to-string of a function",
+        "fn end to-string",
+        &["<function>"],
+    ),
+    case(
+        "built strings equal literals",
+        "12 to-string '12' = 12 to-string 'string' ! 2 to-string 1 to-string <",
+        &["true", "true"],
+    ),
+    case(
+        "built strings as names and case labels",
+        "5 'n' to-string := n 2 to-string case of '2' then 'two' else 'other' end",
+        &["5", "two"],
+    ),
+    case("built strings in lists", "[ 1 to-string ] to-string", &["['1']"]),
+    case("char-at", "'abc' 1 char-at", &["b"]),
+    case("char-at counts characters", "'héllo' 2 char-at", &["l"]),
+    failure("char-at out of range", "'abc' 3 char-at"),
+    case("chars", "'aé' chars", &["[a, é]"]),
+    case("ord and chr", "'A' ord 233 chr", &["65", "é"]),
+    failure("ord of several characters", "'ab' ord"),
+    failure("chr of a surrogate", "55296 chr"),
+    case(
+        "bytes",
+        "[ 0 255 16 ] bytes [ 0 255 16 ] bytes bytes-list",
+        &["<bytes 00ff10>", "[0, 255, 16]"],
+    ),
+    failure("bytes out of range", "[ 256 ] bytes"),
+    case(
+        "bytes and strings",
+        "'hé' to-bytes 'hé' to-bytes bytes-len 'hé' to-bytes bytes-to-string",
+        &["<bytes 68c3a9>", "3", "hé"],
+    ),
+    failure("invalid utf-8", "[ 255 ] bytes bytes-to-string"),
+    case(
+        "bytes and hex",
+        "'00FFa0' hex-to-bytes 'ab' to-bytes bytes-to-hex",
+        &["<bytes 00ffa0>", "6162"],
+    ),
+    failure("odd hex", "'abc' hex-to-bytes"),
+    failure("invalid hex digit", "'+f' hex-to-bytes"),
+    case(
+        "bytes slice and concat",
+        "'hello' to-bytes 1 3 bytes-slice 'a' to-bytes 'b' to-bytes bytes-concat",
+        &["<bytes 656c>", "<bytes 6162>"],
+    ),
+    failure("bytes slice out of range", "'ab' to-bytes 1 3 bytes-slice"),
+    case("bytes equality", "'a' to-bytes [ 97 ] bytes =", &["true"]),
+    case("unicode names", "2 'π' := π 'naïve' := naïve", &["2"]),
+    case("unicode whitespace separates tokens", "1\u{a0}2\u{2003}+", &["3"]),
+    case("unicode in string literals", "'日本語' chars len", &["3"]),
+];
+
+#[derive(Debug, Clone)]
+pub struct SelfTestFailure {
+    pub name: &'static str,
+    pub source: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SelfTestReport {
+    pub passed: usize,
+    pub failures: Vec<SelfTestFailure>,
+}
+
+impl SelfTestReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+fn render(value: &Value) -> String {
+    match value {
+        Value::Nil => "nil".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(x) => x.to_string(),
+        Value::Number(x) => format_number(*x),
+        Value::String(s) => s.to_string(),
+        Value::OwnedString(s) => s.to_string(),
+        Value::List(list) => {
+            let items: Vec<_> = list.iter().map(render).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Bytes(_) => value.to_string(),
+        Value::Error(message) => format!("<error: {message}>"),
+        Value::Shared(_) => render(&value.current()),
+        Value::Channel(_) => "<channel>".into(),
+        Value::Function(f) => match f.kind {
+            CallableKind::Builtin(_) => "<builtin>".into(),
+            CallableKind::Function(_) => "<function>".into(),
+        },
+    }
+}
+
+fn run_case(interpreter: &mut Interpreter, case: &Case) -> Result<(), SelfTestFailure> {
+    let result = interpreter.repl_step(case.source);
+    let stack = interpreter.take_stack();
+
+    let (expected, actual) = match (&case.expected, result) {
+        (Expected::Stack(expected), Ok(())) => {
+            let actual: Vec<_> = stack.iter().map(render).collect();
+            if actual == *expected {
+                return Ok(());
+            }
+            (format!("{expected:?}"), format!("{actual:?}"))
+        }
+        (Expected::Failure, Err(_)) => return Ok(()),
+        (Expected::Stack(expected), Err(e)) => (format!("{expected:?}"), format!("error: {e}")),
+        (Expected::Failure, Ok(())) => ("an error".into(), "success".into()),
+    };
+
+    Err(SelfTestFailure {
+        name: case.name,
+        source: case.source,
+        expected,
+        actual,
+    })
+}
+
+pub(crate) fn run_selftest(mut new_interpreter: impl FnMut() -> Interpreter) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    for case in CASES {
+        match run_case(&mut new_interpreter(), case) {
+            Ok(()) => report.passed += 1,
+            Err(failure) => report.failures.push(failure),
+        }
+    }
+    report
+}
@@ -0,0 +1,486 @@
+use crate::{
+    callable::{BuiltinFuntion, Callable, CallableKind, FunctionDescriptor, Signature},
+    execute::ExecuteOptions,
+    machine_state::MachineState,
+    operation::{Operation, OperationKind},
+    scope::Scope,
+    value::lock,
+    FlyString, Value,
+};
+
+use std::{cell::RefCell, collections::HashMap, sync::Arc, thread::LocalKey};
+
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// Written in front of every serialized program, bumped whenever the encoding changes
+const HEADER: &[u8; 4] = b"SSL\x09";
+/// Written in front of every machine snapshot, versioned along with [`HEADER`]
+const SNAPSHOT_HEADER: &[u8; 4] = b"SSM\x09";
+
+thread_local! {
+    /// While a machine is saved, the name of every builtin of its options by address
+    static BUILTIN_NAMES: RefCell<HashMap<usize, FlyString>> = RefCell::default();
+    /// While a machine is restored, the builtins names are linked back to
+    static BUILTINS: RefCell<HashMap<FlyString, Value>> = RefCell::default();
+    /// While a program or machine is saved, the number of every constant pool written so far by address
+    static WRITTEN_POOLS: RefCell<Option<HashMap<usize, usize>>> = RefCell::default();
+    /// While a program or machine is loaded, the constant pools read so far
+    static READ_POOLS: RefCell<Option<Vec<Arc<[Value]>>>> = RefCell::default();
+}
+
+#[derive(Error, Debug)]
+pub enum SerializeError {
+    #[error("Not a compiled program, or compiled by an incompatible version")]
+    InvalidHeader,
+    #[error("Not a machine snapshot, or saved by an incompatible version")]
+    InvalidSnapshotHeader,
+    #[error("Constant {0} is out of range for the program's pool")]
+    InvalidConstant(usize),
+    #[error("Failed to encode program: {0}")]
+    Encoding(#[from] bincode::Error),
+}
+
+impl FunctionDescriptor {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SerializeError> {
+        let mut bytes = HEADER.to_vec();
+        bincode::serialize_into(&mut bytes, &None::<Vec<u8>>)?;
+        bytes.extend(self.encode()?);
+        Ok(bytes)
+    }
+
+    /// Like [`Self::to_bytes`], storing the signature `sign` makes over the encoded program next to the header.
+    /// [`Self::from_bytes`] hands it back through [`Self::signature`]
+    pub fn to_signed_bytes(
+        &self,
+        sign: impl FnOnce(&[u8]) -> Vec<u8>,
+    ) -> Result<Vec<u8>, SerializeError> {
+        let body = self.encode()?;
+        let signature = sign(&body);
+        let mut bytes = HEADER.to_vec();
+        bincode::serialize_into(&mut bytes, &Some(signature))?;
+        bytes.extend(body);
+        Ok(bytes)
+    }
+
+    /// The program without header and signature, which is what gets signed
+    fn encode(&self) -> Result<Vec<u8>, SerializeError> {
+        // Not `bincode::serialize`, whose first pass to measure the size would already mark every pool as written
+        let mut bytes = vec![];
+        with_context(&WRITTEN_POOLS, Some(HashMap::new()), || {
+            bincode::serialize_into(&mut bytes, self)
+        })?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerializeError> {
+        let mut body = bytes
+            .strip_prefix(HEADER)
+            .ok_or(SerializeError::InvalidHeader)?;
+        let signature: Option<Vec<u8>> = bincode::deserialize_from(&mut body)?;
+        let mut f: Self = with_context(&READ_POOLS, Some(vec![]), || bincode::deserialize(body))?;
+        check_constants(&f.operations, f.constants.len())?;
+        f.signature = signature.map(|signature| {
+            Arc::new(Signature {
+                signed: body.into(),
+                signature: signature.into(),
+            })
+        });
+        Ok(f)
+    }
+}
+
+/// The data of a machine: where a program is in its code isn't part of it
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    stack: Vec<Value>,
+    scopes: Vec<Scope>,
+}
+
+impl ExecuteOptions {
+    /// Save the stack and scopes of a machine created with these options, e.g. to checkpoint a long computation
+    /// between runs or keep a REPL session across restarts. Builtins of the options are left out of the global
+    /// scope and saved by name wherever else they are used. Other builtins, like the closures `compose` makes,
+    /// and channels can't be saved
+    pub fn snapshot(&self, state: &MachineState) -> Result<Vec<u8>, SerializeError> {
+        let builtins = self.builtins();
+        let names = builtins
+            .iter()
+            .filter_map(|(name, value)| match value {
+                Value::Function(Callable {
+                    kind: CallableKind::Builtin(f),
+                    ..
+                }) => Some((builtin_address(f), name.clone())),
+                _ => None,
+            })
+            .collect();
+        let mut scopes: Vec<_> = state.scopes().cloned().collect();
+        if let Some(global) = scopes.first_mut() {
+            global.retain_names(|name, value| builtins.get(name) != Some(value));
+        }
+        let snapshot = Snapshot {
+            stack: state.stack().cloned().collect(),
+            scopes,
+        };
+        let mut bytes = SNAPSHOT_HEADER.to_vec();
+        with_context(&BUILTIN_NAMES, names, || {
+            with_context(&WRITTEN_POOLS, Some(HashMap::new()), || {
+                bincode::serialize_into(&mut bytes, &snapshot)
+            })
+        })?;
+        Ok(bytes)
+    }
+
+    /// A machine with the stack and scopes saved by [`Self::snapshot`], linking builtins by name to those of
+    /// these options. Variables that were shared between closures are separate copies afterwards
+    pub fn restore(&self, bytes: &[u8]) -> Result<MachineState, SerializeError> {
+        let body = bytes
+            .strip_prefix(SNAPSHOT_HEADER)
+            .ok_or(SerializeError::InvalidSnapshotHeader)?;
+        let builtins = self.builtins();
+        let mut state = self.new_state();
+        let Snapshot { stack, mut scopes } = with_context(&BUILTINS, builtins.clone(), || {
+            with_context(&READ_POOLS, Some(vec![]), || {
+                state.interner().enter(|| bincode::deserialize(body))
+            })
+        })?;
+        for scope in &scopes {
+            let values = scope
+                .names()
+                .chain(scope.captured_names())
+                .map(|(_, value)| value);
+            values.chain(scope.args()).try_for_each(check_value)?;
+        }
+        stack.iter().try_for_each(check_value)?;
+
+        for scope in &scopes {
+            let values = scope
+                .names()
+                .chain(scope.captured_names())
+                .map(|(_, value)| value);
+            values
+                .chain(scope.args())
+                .for_each(|value| track_cells(&mut state, value));
+        }
+        stack
+            .iter()
+            .for_each(|value| track_cells(&mut state, value));
+        match scopes.first_mut() {
+            Some(global) => {
+                for (name, value) in builtins {
+                    if global.get(&name).is_none() {
+                        global.set(name, value);
+                    }
+                }
+            }
+            None => scopes.push(state.global_scope().clone()),
+        }
+        state.set_contents(stack, scopes);
+        Ok(state)
+    }
+}
+
+fn builtin_address(f: &BuiltinFuntion) -> usize {
+    Arc::as_ptr(f) as *const () as usize
+}
+
+/// Puts back the context that was set before, however (de)serializing ends, even by a panic
+struct ContextGuard<C: 'static> {
+    key: &'static LocalKey<RefCell<C>>,
+    previous: Option<C>,
+}
+
+impl<C: 'static> Drop for ContextGuard<C> {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            self.key.set(previous);
+        }
+    }
+}
+
+/// Run `f` with `context` set for (de)serializing builtins and constant pools
+fn with_context<C: 'static, T>(
+    key: &'static LocalKey<RefCell<C>>,
+    context: C,
+    f: impl FnOnce() -> T,
+) -> T {
+    let _guard = ContextGuard {
+        key,
+        previous: Some(key.replace(context)),
+    };
+    f()
+}
+
+/// Register the variables shared by closures anywhere in `value` with `state`, so its garbage collection sees them
+fn track_cells(state: &mut MachineState, value: &Value) {
+    match value {
+        Value::Shared(cell) => {
+            state.track_cell(cell);
+            track_cells(state, &lock(cell).clone());
+        }
+        Value::Function(callable) => {
+            if let CallableKind::Function(f) = &callable.kind {
+                f.captured_names
+                    .values()
+                    .for_each(|value| track_cells(state, value));
+            }
+            callable
+                .bound_arguments
+                .iter()
+                .for_each(|value| track_cells(state, value));
+        }
+        Value::List(items) => items.iter().for_each(|value| track_cells(state, value)),
+        _ => {}
+    }
+}
+
+/// Make sure decoded functions anywhere in a value cannot index past their constant pools
+fn check_value(value: &Value) -> Result<(), SerializeError> {
+    match value {
+        Value::Function(callable) => {
+            if let CallableKind::Function(f) = &callable.kind {
+                check_constants(&f.operations, f.constants.len())?;
+                f.captured_names.values().try_for_each(check_value)?;
+            }
+            callable.bound_arguments.iter().try_for_each(check_value)
+        }
+        Value::List(items) => items.iter().try_for_each(check_value),
+        Value::Shared(cell) => check_value(&lock(cell)),
+        _ => Ok(()),
+    }
+}
+
+/// Make sure a decoded program cannot index past its constant pool
+fn check_constants(operations: &[Operation], len: usize) -> Result<(), SerializeError> {
+    use OperationKind as O;
+
+    for op in operations {
+        match &op.kind {
+            O::PushConst(index) if *index >= len => {
+                return Err(SerializeError::InvalidConstant(*index))
+            }
+            O::Push(Value::Function(Callable {
+                kind: CallableKind::Function(f),
+                ..
+            })) => check_constants(&f.operations, len)?,
+            _ => {
+                for block in op.blocks() {
+                    check_constants(block, len)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Serialize for FlyString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FlyString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(s.into())
+    }
+}
+
+/// Functions written in SSL are stored whole. Builtins are native code, so they can only be stored by name
+/// while a machine is saved, and are looked up by that name when it's restored
+#[derive(Serialize, Deserialize)]
+enum StoredCallable {
+    Function(Arc<FunctionDescriptor>),
+    Builtin(FlyString),
+}
+
+impl Serialize for CallableKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let stored = match self {
+            CallableKind::Function(f) => StoredCallable::Function(Arc::clone(f)),
+            CallableKind::Builtin(f) => {
+                let name =
+                    BUILTIN_NAMES.with_borrow(|names| names.get(&builtin_address(f)).cloned());
+                StoredCallable::Builtin(name.ok_or_else(|| {
+                    ser::Error::custom(
+                        "builtins cannot be serialized, except by name in a snapshot",
+                    )
+                })?)
+            }
+        };
+        stored.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CallableKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match StoredCallable::deserialize(deserializer)? {
+            StoredCallable::Function(f) => Ok(CallableKind::Function(f)),
+            StoredCallable::Builtin(name) => {
+                match BUILTINS.with_borrow(|builtins| builtins.get(&name).cloned()) {
+                    Some(Value::Function(Callable {
+                        kind: kind @ CallableKind::Builtin(_),
+                        ..
+                    })) => Ok(kind),
+                    _ => Err(de::Error::custom(format!(
+                        "there is no builtin named {name}"
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+/// A constant pool is written out with the first function using it. Functions nested in that one, or made from
+/// the same program, refer back to it by number, so loading them shares it again
+#[derive(Serialize, Deserialize)]
+enum StoredPool<T> {
+    New(T),
+    Seen(usize),
+}
+
+/// How [`FunctionDescriptor::constants`] is (de)serialized, see [`StoredPool`]
+pub(crate) mod pool {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        constants: &Arc<[Value]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let address = Arc::as_ptr(constants) as *const () as usize;
+        let seen = WRITTEN_POOLS.with_borrow_mut(|pools| {
+            let pools = pools.as_mut()?;
+            let next = pools.len();
+            let index = *pools.entry(address).or_insert(next);
+            (index < next).then_some(index)
+        });
+        match seen {
+            Some(index) => StoredPool::<&[Value]>::Seen(index).serialize(serializer),
+            None => StoredPool::New(&**constants).serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<[Value]>, D::Error> {
+        match StoredPool::<Vec<Value>>::deserialize(deserializer)? {
+            StoredPool::New(values) => {
+                let constants: Arc<[Value]> = values.into();
+                READ_POOLS.with_borrow_mut(|pools| {
+                    if let Some(pools) = pools {
+                        pools.push(Arc::clone(&constants));
+                    }
+                });
+                Ok(constants)
+            }
+            StoredPool::Seen(index) => READ_POOLS
+                .with_borrow(|pools| pools.as_ref()?.get(index).cloned())
+                .ok_or_else(|| de::Error::custom(format!("constant pool {index} wasn't read yet"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        execute::{execute_in, ExecuteError},
+        parser::parse,
+    };
+
+    fn run(state: &mut MachineState, source: &str) {
+        execute_in(state, &parse(source.chars()).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn program_round_trip() {
+        let f = parse("fn fn 'a' 2.5 end end 'f' := [ 1 'a' ] f call".chars()).unwrap();
+        let decoded = FunctionDescriptor::from_bytes(&f.to_bytes().unwrap()).unwrap();
+        let options = ExecuteOptions::default();
+        let mut expected = options.new_state();
+        execute_in(&mut expected, &f).unwrap();
+        let mut actual = options.new_state();
+        execute_in(&mut actual, &decoded).unwrap();
+        assert!(expected.stack().eq(actual.stack()));
+    }
+
+    #[test]
+    fn pool_is_written_once_and_shared_again() {
+        let f = parse("'a long literal' fn 'a long literal' fn 1.5 end end".chars()).unwrap();
+        let bytes = f.to_bytes().unwrap();
+        let count = bytes
+            .windows(b"a long literal".len())
+            .filter(|w| w == b"a long literal")
+            .count();
+        assert_eq!(count, 1);
+
+        let decoded = FunctionDescriptor::from_bytes(&bytes).unwrap();
+        let Some(OperationKind::Push(Value::Function(Callable {
+            kind: CallableKind::Function(nested),
+            ..
+        }))) = decoded.operations().get(1).map(|op| &op.kind)
+        else {
+            panic!("the second operation pushes a function");
+        };
+        assert!(Arc::ptr_eq(&nested.constants, &decoded.constants));
+    }
+
+    #[test]
+    fn verifier_sees_the_signature_before_anything_runs() {
+        use crate::Interpreter;
+
+        let f = parse("'ran'".chars()).unwrap();
+        let sign = |body: &[u8]| body.iter().rev().copied().collect::<Vec<_>>();
+        let signed = FunctionDescriptor::from_bytes(&f.to_signed_bytes(sign).unwrap()).unwrap();
+        let unsigned = FunctionDescriptor::from_bytes(&f.to_bytes().unwrap()).unwrap();
+        assert!(unsigned.signature().is_none());
+
+        let options =
+            ExecuteOptions::default().with_verifier(move |program| match program.signature() {
+                Some(s) if s.bytes() == sign(s.signed_bytes()) => Ok(()),
+                _ => Err("not signed".into()),
+            });
+        let mut interpreter = Interpreter::builder().options(options).build();
+        assert!(matches!(
+            interpreter.run(&unsigned, vec![]),
+            Err(ExecuteError::Rejected(_))
+        ));
+        assert_eq!(interpreter.state().stack().len(), 0);
+        interpreter.run(&signed, vec![]).unwrap();
+        assert!(interpreter.state().stack().eq([&Value::from("ran")]));
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        assert!(matches!(
+            FunctionDescriptor::from_bytes(b"nope"),
+            Err(SerializeError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn restore_continues_where_snapshot_left_off() {
+        let options = ExecuteOptions::default();
+        let mut state = options.new_state();
+        run(&mut state, "fn $0 2 * end 'double' := 5 'x' := [ 1 2 ]");
+        let mut restored = options.restore(&options.snapshot(&state).unwrap()).unwrap();
+        for state in [&mut state, &mut restored] {
+            run(state, "x double 3 +");
+        }
+        assert!(state.stack().eq(restored.stack()));
+    }
+
+    #[test]
+    fn restored_shared_variables_are_collected() {
+        let options = ExecuteOptions::default();
+        let mut state = options.new_state();
+        run(
+            &mut state,
+            "fn 0 'n' := fn n drop 'n' := end ^& end 'make' := make 'set' :=",
+        );
+        let mut restored = options.restore(&options.snapshot(&state).unwrap()).unwrap();
+        // Storing the closure in the variable it shares makes a cycle only the collector can free
+        run(&mut restored, "&set set nil 'set' :=");
+        assert_eq!(restored.collect_garbage(), 1);
+    }
+}
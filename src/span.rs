@@ -0,0 +1,26 @@
+use std::{fmt, ops::Range};
+
+/// A position in the source text, both counted from 1, and the bytes of the token found there
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    /// Bytes from the start of the source to the token
+    pub offset: usize,
+    /// Length of the token in bytes. Operations with blocks only cover their keyword
+    pub len: usize,
+}
+
+impl Span {
+    /// The bytes of the source the token was read from
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
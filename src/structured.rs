@@ -0,0 +1,114 @@
+use crate::Value;
+
+use std::{borrow::Borrow, fmt, sync::Arc};
+
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// A value as plain data, for exchanging arguments and results with the host through any self-describing serde
+/// format. Nil, bools, numbers, strings and lists map onto their natural counterparts, so a `serde_json::Value`
+/// converts with `serde_json::from_value::<Structured<Value>>` and back with `serde_json::to_value(Structured(&v))`.
+/// Maps become lists of `[key value]` pairs, and bytes are written as the format writes bytes, which for JSON
+/// is a list of numbers. Functions and errors have no such form and fail to serialize
+#[derive(Debug, Clone, PartialEq)]
+pub struct Structured<T>(pub T);
+
+impl<V: Borrow<Value>> Serialize for Structured<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.borrow() {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(x) => serializer.serialize_i64(*x),
+            Value::Number(x) => serializer.serialize_f64(*x),
+            Value::String(s) => serializer.serialize_str(s.as_str()),
+            Value::OwnedString(s) => serializer.serialize_str(s),
+            Value::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            Value::List(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items.iter() {
+                    seq.serialize_element(&Structured(item))?;
+                }
+                seq.end()
+            }
+            Value::Shared(_) => Structured(self.0.borrow().current()).serialize(serializer),
+            v @ (Value::Function(_) | Value::Error(_) | Value::Channel(_)) => Err(
+                ser::Error::custom(format_args!("a {} is not data", v.type_name())),
+            ),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Structured<Value> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_any(StructuredVisitor)
+            .map(Structured)
+    }
+}
+
+struct StructuredVisitor;
+
+impl<'de> Visitor<'de> for StructuredVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("nil, a bool, a number, a string, a list or a map")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, x: i64) -> Result<Value, E> {
+        Ok(Value::Int(x))
+    }
+
+    /// Integers too large for an int lose precision rather than failing
+    fn visit_u64<E: de::Error>(self, x: u64) -> Result<Value, E> {
+        Ok(i64::try_from(x).map_or(Value::Number(x as f64), Value::Int))
+    }
+
+    fn visit_f64<E: de::Error>(self, x: f64) -> Result<Value, E> {
+        Ok(Value::Number(x))
+    }
+
+    /// Strings from the host are data, so they aren't interned
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::OwnedString(Arc::from(s)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Value, E> {
+        Ok(Value::Bytes(bytes.into()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(Structured(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::List(items.into()))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut pairs = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((Structured(key), Structured(value))) = map.next_entry()? {
+            pairs.push(Value::List(vec![key, value].into()));
+        }
+        Ok(Value::List(pairs.into()))
+    }
+}
@@ -0,0 +1,70 @@
+use crate::channel::Channel;
+
+use std::{
+    fmt,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// How long dropping [`Tasks`] waits for the cancelled threads to end
+const DROP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A thread started by `spawn`, known by the channel its result arrives on
+struct Task {
+    result: Channel,
+    thread: JoinHandle<()>,
+}
+
+/// The threads a machine started with `spawn` that weren't joined yet. Dropping them cancels the threads
+/// and waits a moment for them to end. Threads stuck in a host call that doesn't check for cancellation,
+/// like `read-line` or `exec`, are left running after that, rather than hanging the host
+#[derive(Default)]
+pub(crate) struct Tasks(Vec<Task>);
+
+impl Tasks {
+    pub(crate) fn add(&mut self, result: Channel, thread: JoinHandle<()>) {
+        // Threads whose result was only received are forgotten once they ended, so they don't pile up
+        self.0.retain(|task| !task.thread.is_finished());
+        self.0.push(Task { result, thread });
+    }
+
+    /// Wait for the thread whose result arrives on `result` to end, if it is one of these
+    pub(crate) fn join(&mut self, result: &Channel) {
+        if let Some(i) = self.0.iter().position(|task| task.result == *result) {
+            // A panic in the thread was already reported, and its result was never sent
+            let _ = self.0.swap_remove(i).thread.join();
+        }
+    }
+
+    /// Wait for every thread to end, however long that takes
+    pub(crate) fn join_all(&mut self) {
+        for task in self.0.drain(..) {
+            let _ = task.thread.join();
+        }
+    }
+
+    fn cancel_all(&self) {
+        self.0.iter().for_each(|task| task.result.cancel_task());
+    }
+}
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        self.cancel_all();
+        let deadline = Instant::now() + DROP_TIMEOUT;
+        while Instant::now() < deadline && self.0.iter().any(|task| !task.thread.is_finished()) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        for task in self.0.drain(..) {
+            if task.thread.is_finished() {
+                let _ = task.thread.join();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Tasks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Tasks({})", self.0.len())
+    }
+}
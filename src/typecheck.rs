@@ -0,0 +1,551 @@
+use crate::{
+    callable::{Callable, CallableKind, FunctionDescriptor},
+    operation::{Operation, OperationKind},
+    parser::ParseError,
+    FlyString, Span, Value,
+};
+
+use std::collections::HashMap;
+
+/// Every type a value can have, as `typeof` and `!` name them
+const TYPE_NAMES: &[&str] = &[
+    "nil", "bool", "int", "number", "function", "string", "list", "bytes", "error", "channel",
+];
+
+/// How often a loop body is followed again to learn what its iterations can leave behind before giving up
+const LOOP_PASSES: usize = 8;
+
+/// What a builtin needs one of its operands to be, named like its type mismatch errors name it
+#[derive(Debug, Clone, Copy)]
+enum Param {
+    Any,
+    Number,
+    Bool,
+    Int,
+    List,
+    Function,
+}
+
+impl Param {
+    fn name(self) -> &'static str {
+        match self {
+            Param::Any => "anything",
+            Param::Number => "Number",
+            Param::Bool => "Bool",
+            Param::Int => "Int",
+            Param::List => "List",
+            Param::Function => "Function",
+        }
+    }
+
+    fn accepts(self, ty: &str) -> bool {
+        match self {
+            Param::Any => true,
+            Param::Number => ty == "int" || ty == "number",
+            param => param.exact() == Some(ty),
+        }
+    }
+
+    /// The one type the operand must have, if there is only one
+    fn exact(self) -> Option<&'static str> {
+        match self {
+            Param::Bool => Some("bool"),
+            Param::Int => Some("int"),
+            Param::List => Some("list"),
+            Param::Function => Some("function"),
+            Param::Any | Param::Number => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Returns {
+    Type(&'static str),
+    /// An int if all operands are, a number if any is
+    Numeric,
+    Unknown,
+}
+
+use Param as P;
+
+/// The operands, top first, and result of builtins that take a fixed set of values and leave one.
+/// Comparisons, stack shuffles and `!` are followed separately
+const SIGNATURES: &[(&str, &[Param], Returns)] = &[
+    ("+", &[P::Number, P::Number], Returns::Numeric),
+    ("-", &[P::Number, P::Number], Returns::Numeric),
+    ("*", &[P::Number, P::Number], Returns::Numeric),
+    ("/", &[P::Number, P::Number], Returns::Type("number")),
+    ("min", &[P::Number, P::Number], Returns::Numeric),
+    ("max", &[P::Number, P::Number], Returns::Numeric),
+    ("mod", &[P::Number, P::Number], Returns::Unknown),
+    ("pow", &[P::Number, P::Number], Returns::Unknown),
+    ("abs", &[P::Number], Returns::Numeric),
+    ("sqrt", &[P::Number], Returns::Type("number")),
+    ("floor", &[P::Number], Returns::Unknown),
+    ("ceil", &[P::Number], Returns::Unknown),
+    ("to-int", &[P::Number], Returns::Type("int")),
+    ("to-float", &[P::Number], Returns::Type("number")),
+    ("band", &[P::Number, P::Number], Returns::Type("int")),
+    ("bor", &[P::Number, P::Number], Returns::Type("int")),
+    ("bxor", &[P::Number, P::Number], Returns::Type("int")),
+    ("bnot", &[P::Number], Returns::Type("int")),
+    ("shl", &[P::Number, P::Number], Returns::Type("int")),
+    ("shr", &[P::Number, P::Number], Returns::Type("int")),
+    ("rand", &[], Returns::Type("number")),
+    ("rand-int", &[P::Int, P::Int], Returns::Type("int")),
+    ("=", &[P::Any, P::Any], Returns::Type("bool")),
+    ("!=", &[P::Any, P::Any], Returns::Type("bool")),
+    ("and", &[P::Bool, P::Bool], Returns::Type("bool")),
+    ("or", &[P::Bool, P::Bool], Returns::Type("bool")),
+    ("not", &[P::Bool], Returns::Type("bool")),
+    ("is-nil", &[P::Any], Returns::Type("bool")),
+    ("depth", &[], Returns::Type("int")),
+    ("to-string", &[P::Any], Returns::Type("string")),
+    ("to-number", &[P::Any], Returns::Unknown),
+    ("to-bool", &[P::Any], Returns::Type("bool")),
+    ("^", &[P::Function], Returns::Type("function")),
+    ("^&", &[P::Function], Returns::Type("function")),
+    ("push", &[P::Any, P::List], Returns::Type("list")),
+    ("get", &[P::Number, P::List], Returns::Unknown),
+    ("set", &[P::Any, P::Number, P::List], Returns::Type("list")),
+    ("len", &[P::List], Returns::Type("int")),
+    ("collect-garbage", &[], Returns::Type("int")),
+];
+
+/// Follow the types of literals and of values checked with `!` through `program` to find operations that
+/// are certain to fail with a type mismatch once they run, like `'a' 1 +`. Values of unknown type are
+/// given the benefit of the doubt, and mismatches inside a `try` are left alone since they may be intended.
+/// Builtins are assumed to be the standard ones; calling anything else forgets what is known of the stack
+pub fn type_check(program: &FunctionDescriptor) -> Vec<ParseError> {
+    let mut checker = Checker::default();
+    checker.function(program);
+    checker
+        .errors
+        .sort_by_key(|e| e.span().map(|span| span.offset));
+    checker.errors
+}
+
+/// The type of a value, if it is known
+type Type = Option<&'static str>;
+
+/// Where a value came from, so that learning its type also tells the type of its source
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Origin {
+    Arg(usize),
+    Var(FlyString),
+    /// A string literal, which may name a variable or a type
+    Text(FlyString),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Slot {
+    ty: Type,
+    origin: Option<Origin>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct State {
+    /// The values on top of the stack. Whatever is below them is of unknown type
+    stack: Vec<Slot>,
+    /// The variables assigned so far, with the type of their value if it is known
+    vars: HashMap<FlyString, Type>,
+    args: HashMap<usize, &'static str>,
+    /// Control never gets here, e.g. after `ret`
+    diverged: bool,
+}
+
+impl State {
+    fn push(&mut self, ty: Type, origin: Option<Origin>) {
+        self.stack.push(Slot { ty, origin });
+    }
+
+    fn pop(&mut self) -> Slot {
+        self.stack.pop().unwrap_or(Slot {
+            ty: None,
+            origin: None,
+        })
+    }
+
+    /// Called code may have done anything to the stack and the variables
+    fn forget(&mut self) {
+        self.stack.clear();
+        self.vars.values_mut().for_each(|ty| *ty = None);
+    }
+
+    /// Now that the value from `origin` is known to be a `ty`, so are its copies
+    fn learn(&mut self, origin: Option<Origin>, ty: &'static str) {
+        match &origin {
+            Some(Origin::Arg(index)) => {
+                self.args.insert(*index, ty);
+            }
+            Some(Origin::Var(name)) => match self.vars.get_mut(name) {
+                Some(var) => *var = Some(ty),
+                None => return,
+            },
+            _ => return,
+        }
+        for slot in &mut self.stack {
+            if slot.origin == origin {
+                slot.ty = Some(ty);
+            }
+        }
+    }
+
+    /// What is known after either this or `other` ran
+    fn merge(&mut self, other: &State) {
+        if other.diverged {
+            return;
+        }
+        if self.diverged {
+            *self = other.clone();
+            return;
+        }
+
+        if self.stack.len() == other.stack.len() {
+            for (slot, other) in self.stack.iter_mut().zip(&other.stack) {
+                if slot.ty != other.ty {
+                    slot.ty = None;
+                }
+                if slot.origin != other.origin {
+                    slot.origin = None;
+                }
+            }
+        } else {
+            self.stack.clear();
+        }
+        for (name, ty) in &mut self.vars {
+            if other.vars.get(name) != Some(&*ty) {
+                *ty = None;
+            }
+        }
+        for name in other.vars.keys() {
+            self.vars.entry(name.clone()).or_insert(None);
+        }
+        self.args
+            .retain(|index, ty| other.args.get(index) == Some(ty));
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    errors: Vec<ParseError>,
+    /// Mismatches aren't reported while this is above zero, inside `try` and while following loops again
+    quiet: usize,
+}
+
+impl Checker {
+    fn function(&mut self, f: &FunctionDescriptor) {
+        self.block(f.operations(), f, &mut State::default());
+    }
+
+    fn block(&mut self, ops: &[Operation], f: &FunctionDescriptor, state: &mut State) {
+        for op in ops {
+            if state.diverged {
+                break;
+            }
+            self.operation(op, f, state);
+        }
+    }
+
+    fn operation(&mut self, op: &Operation, f: &FunctionDescriptor, state: &mut State) {
+        use OperationKind as O;
+
+        // Blocks run in scopes of their own, so what they assign is gone after them
+        let outer = op.blocks().next().is_some().then(|| state.vars.clone());
+        match &op.kind {
+            O::Push(value) => {
+                if let Value::Function(Callable {
+                    kind: CallableKind::Function(inner),
+                    ..
+                }) = value
+                {
+                    self.function(inner);
+                }
+                state.push(Some(value.type_name()), None);
+            }
+            O::PushConst(index) => {
+                let value = &f.constants()[*index];
+                let origin = match value {
+                    Value::String(text) => Some(Origin::Text(text.clone())),
+                    _ => None,
+                };
+                state.push(Some(value.type_name()), origin);
+            }
+            O::PushRaw(name) => {
+                let ty = state.vars.get(name).copied().flatten();
+                state.push(ty, Some(Origin::Var(name.clone())));
+            }
+            O::PushArg(index) => {
+                let ty = state.args.get(index).copied();
+                state.push(ty, Some(Origin::Arg(*index)));
+            }
+            O::PushId(name) => match state.vars.get(name) {
+                Some(Some(ty)) if *ty != "function" => {
+                    state.push(Some(ty), Some(Origin::Var(name.clone())))
+                }
+                // Naming a function calls it
+                Some(_) => state.forget(),
+                None => self.call(name, op.span, state),
+            },
+            O::Call(name, _) => self.call(name, op.span, state),
+            O::If(then, otherwise) => {
+                let condition = state.pop();
+                self.expect(condition, Param::Bool, "if", op.span, state);
+                let mut other = state.clone();
+                self.block(then, f, state);
+                self.block(otherwise, f, &mut other);
+                state.merge(&other);
+            }
+            O::Case(branches, default) => {
+                state.pop();
+                let start = state.clone();
+                self.block(default, f, state);
+                for (_, body) in branches {
+                    let mut branch = start.clone();
+                    self.block(body, f, &mut branch);
+                    state.merge(&branch);
+                }
+            }
+            O::Try(body, handler) => {
+                // A failed body leaves nothing behind but the error
+                let mut failed = state.clone();
+                self.quiet += 1;
+                self.block(body, f, state);
+                self.quiet -= 1;
+                failed.push(Some("error"), None);
+                self.block(handler, f, &mut failed);
+                state.merge(&failed);
+            }
+            O::While(body) => self.repeat(body, f, op.span, state, false),
+            O::Repeat(body) => self.repeat(body, f, op.span, state, true),
+            O::List(body) => {
+                let below = std::mem::take(&mut state.stack);
+                self.block(body, f, state);
+                state.stack = below;
+                state.push(Some("list"), None);
+            }
+            O::Recurse => state.forget(),
+            O::Return => state.diverged = true,
+        }
+        if let Some(vars) = outer {
+            state.vars = vars;
+        }
+    }
+
+    /// Follow a `while` loop, or with `counted` a `repeat` loop, which takes its count once rather than a
+    /// condition before every iteration. The first iteration starts from what is known before the loop, later
+    /// ones from whatever the iterations before them may have left, so only the first reports mismatches
+    fn repeat(
+        &mut self,
+        body: &[Operation],
+        f: &FunctionDescriptor,
+        span: Span,
+        state: &mut State,
+        counted: bool,
+    ) {
+        let (keyword, param) = match counted {
+            true => ("repeat", Param::Int),
+            false => ("while", Param::Bool),
+        };
+        let operand = state.pop();
+        self.expect(operand, param, keyword, span, state);
+
+        let outer = self.quiet;
+        let mut entry = state.clone();
+        let mut settled = false;
+        for _ in 0..LOOP_PASSES {
+            let mut end = entry.clone();
+            self.block(body, f, &mut end);
+            if !end.diverged && !counted {
+                let condition = end.pop();
+                self.expect(condition, Param::Bool, "while", span, &mut end);
+            }
+            self.quiet = outer + 1;
+
+            let before = entry.clone();
+            entry.merge(&end);
+            if entry == before {
+                settled = true;
+                break;
+            }
+        }
+        self.quiet = outer;
+        if !settled {
+            entry.forget();
+        }
+        *state = entry;
+    }
+
+    fn call(&mut self, name: &FlyString, span: Span, state: &mut State) {
+        let name = name.as_str();
+        match name {
+            "dup" => {
+                let a = state.pop();
+                state.stack.extend([a.clone(), a]);
+            }
+            "drop" | "." => {
+                state.pop();
+            }
+            "swap" => {
+                let b = state.pop();
+                let a = state.pop();
+                state.stack.extend([b, a]);
+            }
+            "over" => {
+                let b = state.pop();
+                let a = state.pop();
+                state.stack.extend([a.clone(), b, a]);
+            }
+            "rot" => {
+                let c = state.pop();
+                let b = state.pop();
+                let a = state.pop();
+                state.stack.extend([b, c, a]);
+            }
+            "clear-stack" => state.stack.clear(),
+            "typeof" => {
+                let a = state.pop();
+                state.stack.push(a);
+                state.push(Some("string"), None);
+            }
+            "throw" | "exit" => {
+                state.pop();
+                state.diverged = true;
+            }
+            ":=" | ":=global" => {
+                let name = state.pop();
+                let value = state.pop();
+                match name.origin {
+                    Some(Origin::Text(name)) => {
+                        state.vars.insert(name, value.ty);
+                    }
+                    _ => state.forget(),
+                }
+            }
+            "!" => self.annotation(span, state),
+            "<" | ">" | "<=" | ">=" => self.comparison(name, span, state),
+            _ => match SIGNATURES.iter().find(|(builtin, ..)| *builtin == name) {
+                Some((_, params, returns)) => {
+                    let mut types = vec![];
+                    for param in *params {
+                        let operand = state.pop();
+                        types.push(operand.ty);
+                        self.expect(operand, *param, name, span, state);
+                    }
+                    let ty = match returns {
+                        Returns::Type(ty) => Some(*ty),
+                        Returns::Numeric => numeric_result(&types),
+                        Returns::Unknown => None,
+                    };
+                    state.push(ty, None);
+                }
+                None => state.forget(),
+            },
+        }
+    }
+
+    /// `value 'type' !` fails unless the value has the type, and afterwards the value is known to have it
+    fn annotation(&mut self, span: Span, state: &mut State) {
+        let annotation = state.pop();
+        let value = state.pop();
+        let Some(Origin::Text(expected)) = annotation.origin else {
+            return;
+        };
+        match value.ty {
+            Some(actual) if actual != expected.as_str() => {
+                self.mismatch("!", expected, actual, span)
+            }
+            Some(_) => {}
+            None => {
+                if let Some(ty) = TYPE_NAMES.iter().find(|ty| **ty == expected.as_str()) {
+                    state.learn(value.origin, ty);
+                }
+            }
+        }
+    }
+
+    /// Strings compare with strings, anything else only with numbers
+    fn comparison(&mut self, name: &str, span: Span, state: &mut State) {
+        let operands = [state.pop(), state.pop()];
+        let compares_text = operands
+            .iter()
+            .all(|operand| operand.ty.is_none_or(|ty| ty == "string"));
+        if !compares_text {
+            for operand in operands {
+                self.expect(operand, Param::Number, name, span, state);
+            }
+        }
+        state.push(Some("bool"), None);
+    }
+
+    /// Report `operand` if it can't be a `param`, otherwise remember that it must be one from now on
+    fn expect(&mut self, operand: Slot, param: Param, name: &str, span: Span, state: &mut State) {
+        match operand.ty {
+            Some(ty) if !param.accepts(ty) => self.mismatch(name, param.name().into(), ty, span),
+            Some(_) => {}
+            None => {
+                if let Some(ty) = param.exact() {
+                    state.learn(operand.origin, ty);
+                }
+            }
+        }
+    }
+
+    fn mismatch(&mut self, name: &str, expected: FlyString, actual: &'static str, span: Span) {
+        if self.quiet == 0 {
+            self.errors.push(
+                ParseError::TypeMismatch {
+                    name: name.into(),
+                    expected,
+                    actual,
+                }
+                .at(span),
+            );
+        }
+    }
+}
+
+fn numeric_result(types: &[Type]) -> Type {
+    if types.iter().all(|ty| *ty == Some("int")) {
+        Some("int")
+    } else if types.iter().all(|ty| matches!(ty, Some("int" | "number"))) {
+        Some("number")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn errors(source: &str) -> Vec<ParseError> {
+        type_check(&parse(source.chars()).unwrap())
+    }
+
+    #[test]
+    fn finds_mismatched_literals() {
+        let found = errors("1 2 +\n'a' 1 +");
+        assert_eq!(found.len(), 1);
+        let span = found[0].span().unwrap();
+        assert_eq!((span.line, span.column), (2, 7));
+        assert!(matches!(
+            found[0].kind(),
+            ParseError::TypeMismatch { name, actual: "string", .. } if name.as_str() == "+"
+        ));
+    }
+
+    #[test]
+    fn follows_variables_and_functions() {
+        assert_eq!(errors("1 'v' := 'a' v +").len(), 1);
+        assert_eq!(errors("fn 'a' $0 + end 'f' := 1 f").len(), 1);
+    }
+
+    #[test]
+    fn gives_unknown_values_and_try_the_benefit_of_the_doubt() {
+        assert!(errors("try 'a' 1 + end").is_empty());
+        assert!(errors("fn $0 1 + end 'f' := 'a' f").is_empty());
+        assert!(errors("'a' to-number 1 +").is_empty());
+    }
+}
@@ -1,28 +1,316 @@
-use crate::{callable::*, FlyString};
+use crate::{
+    callable::*, channel::Channel, execute::ExecuteError, machine_state::MachineState, FlyString,
+};
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
+    Nil,
     Bool(bool),
+    Int(i64),
     Number(f64),
     Function(Callable),
     String(FlyString),
+    /// Text built while running, like the result of `to-string` or a line read from input. Unlike
+    /// [`Value::String`] it isn't interned, so it is freed once the program drops it
+    OwnedString(Arc<str>),
+    List(Arc<Vec<Value>>),
+    /// Binary data, like the contents of a file that isn't text
+    Bytes(Arc<[u8]>),
+    /// A caught runtime error, carrying its message. Not interned, like [`Value::OwnedString`]
+    Error(Arc<str>),
+    /// A variable captured by reference with `^&`. Only found in scopes: reading the variable gives what it holds
+    Shared(Arc<Mutex<Value>>),
+    /// Made by `chan` and `spawn`, for talking to programs on other threads
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Channel(Channel),
 }
 
 impl Value {
-    pub fn builtin(f: BuiltinFuntion) -> Self {
+    pub fn builtin(
+        f: impl Fn(&mut MachineState) -> Result<(), ExecuteError> + Send + Sync + 'static,
+    ) -> Self {
+        let f: BuiltinFuntion = Arc::new(f);
         f.into()
     }
 
+    /// A builtin taking `arity` values, which fails with [`ExecuteError::ArityMismatch`] before running when the
+    /// stack holds fewer, rather than partway through
+    pub fn builtin_with_arity(
+        arity: usize,
+        f: impl Fn(&mut MachineState) -> Result<(), ExecuteError> + Send + Sync + 'static,
+    ) -> Self {
+        Self::builtin(move |state| {
+            let got = state.stack_len();
+            if got < arity {
+                return Err(ExecuteError::ArityMismatch {
+                    expected: arity,
+                    got,
+                });
+            }
+            f(state)
+        })
+    }
+
     pub fn type_name(&self) -> &'static str {
         match self {
+            Value::Nil => "nil",
             Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
             Value::Number(_) => "number",
             Value::Function(_) => "function",
-            Value::String(_) => "string",
+            Value::String(_) | Value::OwnedString(_) => "string",
+            Value::List(_) => "list",
+            Value::Bytes(_) => "bytes",
+            Value::Error(_) => "error",
+            Value::Channel(_) => "channel",
+            Value::Shared(cell) => lock(cell).type_name(),
+        }
+    }
+
+    /// The value itself, or what a shared variable currently holds
+    pub fn current(&self) -> Value {
+        match self {
+            Value::Shared(cell) => lock(cell).clone(),
+            value => value.clone(),
+        }
+    }
+
+    /// The text of a string of either kind
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s.as_str()),
+            Value::OwnedString(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn lock(cell: &Mutex<Value>) -> std::sync::MutexGuard<'_, Value> {
+    cell.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Two lowercase hex digits per byte
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Shortest text that parses back to exactly `x`, using exponent notation for very large or small magnitudes.
+/// Whole numbers keep a `.0`, so they read back as floats rather than ints. Finite numbers are valid JSON;
+/// NaN and infinities print as `NaN`, `inf` and `-inf`
+pub fn format_number(x: f64) -> String {
+    format!("{x:?}")
+}
+
+/// How `.` and `to-string` show values: strings unquoted except inside lists, and functions
+/// with what they captured or have bound
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => f.write_str("nil"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Int(x) => write!(f, "{x}"),
+            Value::Number(x) => f.write_str(&format_number(*x)),
+            Value::String(s) => write!(f, "{s}"),
+            Value::OwnedString(s) => write!(f, "{s}"),
+            Value::Function(callable) => write!(f, "{callable}"),
+            Value::List(list) => {
+                f.write_str("[")?;
+                for (i, value) in list.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    match value.as_str() {
+                        Some(s) => write!(f, "'{s}'")?,
+                        None => write!(f, "{value}")?,
+                    }
+                }
+                f.write_str("]")
+            }
+            Value::Bytes(bytes) => write!(f, "<bytes {}>", to_hex(bytes)),
+            Value::Error(message) => write!(f, "<error: {message}>"),
+            Value::Shared(cell) => write!(f, "{}", lock(cell)),
+            Value::Channel(_) => f.write_str("<channel>"),
+        }
+    }
+}
+
+/// Ints and floats compare by numeric value, strings by their text whether interned or not
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        use Value as V;
+        match (self, other) {
+            (V::Nil, V::Nil) => true,
+            (V::Bool(a), V::Bool(b)) => a == b,
+            (V::Int(a), V::Int(b)) => a == b,
+            (V::Number(a), V::Number(b)) => a == b,
+            (V::Int(a), V::Number(b)) | (V::Number(b), V::Int(a)) => *a as f64 == *b,
+            (V::Function(a), V::Function(b)) => a == b,
+            (V::String(a), V::String(b)) => a == b,
+            (V::String(_) | V::OwnedString(_), V::String(_) | V::OwnedString(_)) => {
+                self.as_str() == other.as_str()
+            }
+            (V::List(a), V::List(b)) => a == b,
+            (V::Bytes(a), V::Bytes(b)) => a == b,
+            (V::Error(a), V::Error(b)) => a == b,
+            (V::Channel(a), V::Channel(b)) => a == b,
+            (V::Shared(a), V::Shared(b)) if Arc::ptr_eq(a, b) => true,
+            // The lock is released before comparing, since `b` may hold the same cell
+            (V::Shared(a), b) | (b, V::Shared(a)) => {
+                let inner = lock(a).clone();
+                inner == *b
+            }
+            _ => false,
         }
     }
 }
 
+/// Numbers order by value and strings by their text, like `<` and friends do. Other values are only ordered
+/// against values equal to them
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        use Value as V;
+        match (self, other) {
+            (V::Int(a), V::Int(b)) => a.partial_cmp(b),
+            (V::Int(a), V::Number(b)) => (*a as f64).partial_cmp(b),
+            (V::Number(a), V::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (V::Number(a), V::Number(b)) => a.partial_cmp(b),
+            // Locks are released before comparing, since the other side may hold the same cell
+            (V::Shared(a), b) => {
+                let inner = lock(a).clone();
+                inner.partial_cmp(b)
+            }
+            (a, V::Shared(b)) => {
+                let inner = lock(b).clone();
+                a.partial_cmp(&inner)
+            }
+            (a, b) => match (a.as_str(), b.as_str()) {
+                (Some(a), Some(b)) => a.partial_cmp(b),
+                _ => (a == b).then_some(Ordering::Equal),
+            },
+        }
+    }
+}
+
+/// A [`Value`] usable as a key in hash maps and sets. Keys are equal when the values are, except for numbers:
+/// all NaNs are the same key, and an int and a float are only the same key if the float holds exactly that int,
+/// so that keys of big ints don't collapse into the floats they round to
+#[derive(Debug, Clone)]
+pub struct ValueKey(pub Value);
+
+/// The form numbers are compared and hashed in as keys. Floats holding an integer are that integer
+#[derive(PartialEq, Eq, Hash)]
+enum NumberKey {
+    Int(i64),
+    Float(u64),
+}
+
+impl NumberKey {
+    fn of(x: f64) -> Self {
+        // 2^63 is exactly representable, so everything in this range converts to an i64 without rounding
+        const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+        if x.is_nan() {
+            Self::Float(f64::NAN.to_bits())
+        } else if x.fract() == 0.0 && (-LIMIT..LIMIT).contains(&x) {
+            Self::Int(x as i64)
+        } else {
+            Self::Float(x.to_bits())
+        }
+    }
+}
+
+fn keys_equal(a: &Value, b: &Value) -> bool {
+    use Value as V;
+    match (a, b) {
+        (V::Int(a), V::Int(b)) => a == b,
+        (V::Number(a), V::Number(b)) => NumberKey::of(*a) == NumberKey::of(*b),
+        (V::Int(a), V::Number(b)) | (V::Number(b), V::Int(a)) => {
+            NumberKey::Int(*a) == NumberKey::of(*b)
+        }
+        (V::List(a), V::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| keys_equal(a, b))
+        }
+        (V::Function(a), V::Function(b)) => {
+            a.kind == b.kind
+                && a.bound_arguments.len() == b.bound_arguments.len()
+                && a.bound_arguments
+                    .iter()
+                    .zip(b.bound_arguments.iter())
+                    .all(|(a, b)| keys_equal(a, b))
+        }
+        (V::Shared(a), V::Shared(b)) if Arc::ptr_eq(a, b) => true,
+        (V::Shared(a), b) | (b, V::Shared(a)) => {
+            let inner = lock(a).clone();
+            keys_equal(&inner, b)
+        }
+        (a, b) => a == b,
+    }
+}
+
+impl PartialEq for ValueKey {
+    fn eq(&self, other: &Self) -> bool {
+        keys_equal(&self.0, &other.0)
+    }
+}
+
+impl Eq for ValueKey {}
+
+impl Hash for ValueKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+            // Values of different types can be equal, so the type only goes into the hash where it can't be
+            match value {
+                Value::Nil => 0u8.hash(state),
+                Value::Bool(b) => b.hash(state),
+                Value::Int(x) => NumberKey::Int(*x).hash(state),
+                Value::Number(x) => NumberKey::of(*x).hash(state),
+                Value::String(_) | Value::OwnedString(_) => value.as_str().hash(state),
+                Value::List(list) => {
+                    list.len().hash(state);
+                    list.iter().for_each(|item| hash_value(item, state));
+                }
+                Value::Bytes(bytes) => bytes.hash(state),
+                Value::Error(message) => message.hash(state),
+                Value::Function(f) => {
+                    match &f.kind {
+                        CallableKind::Function(f) => Arc::as_ptr(f).cast::<u8>().hash(state),
+                        CallableKind::Builtin(f) => Arc::as_ptr(f).cast::<u8>().hash(state),
+                    }
+                    f.bound_arguments
+                        .iter()
+                        .for_each(|arg| hash_value(arg, state));
+                }
+                Value::Shared(cell) => {
+                    let inner = lock(cell).clone();
+                    hash_value(&inner, state)
+                }
+                Value::Channel(channel) => Arc::as_ptr(&channel.0).cast::<u8>().hash(state),
+            }
+        }
+
+        hash_value(&self.0, state)
+    }
+}
+
+impl From<Value> for ValueKey {
+    fn from(value: Value) -> Self {
+        Self(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
 impl From<f64> for Value {
     fn from(value: f64) -> Self {
         Self::Number(value)
@@ -31,7 +319,7 @@ impl From<f64> for Value {
 
 impl From<String> for Value {
     fn from(value: String) -> Self {
-        Self::String(value.into())
+        Self::OwnedString(value.into())
     }
 }
 
@@ -41,6 +329,18 @@ impl From<&str> for Value {
     }
 }
 
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Bytes(value.into())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Self::List(value.into())
+    }
+}
+
 impl From<BuiltinFuntion> for Value {
     fn from(value: BuiltinFuntion) -> Self {
         Self::Function(value.into())
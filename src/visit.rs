@@ -0,0 +1,47 @@
+use crate::{
+    callable::{Callable, CallableKind, FunctionDescriptor},
+    operation::{Operation, OperationKind},
+    Value,
+};
+
+/// Walks a parsed program, e.g. for linters and analyzers.
+/// The default methods visit everything nested in what they are given; override one and call the
+/// matching `walk_*` function to keep descending
+pub trait OperationVisitor {
+    fn visit_function(&mut self, f: &FunctionDescriptor) {
+        walk_function(self, f);
+    }
+
+    /// `f` is the function `op` belongs to, which holds the constants it may refer to
+    fn visit_operation(&mut self, op: &Operation, f: &FunctionDescriptor) {
+        walk_operation(self, op, f);
+    }
+}
+
+/// Visit every operation at the top of `f`
+pub fn walk_function<V: OperationVisitor + ?Sized>(visitor: &mut V, f: &FunctionDescriptor) {
+    for op in f.operations() {
+        visitor.visit_operation(op, f);
+    }
+}
+
+/// Visit the function literal or blocks nested in `op`
+pub fn walk_operation<V: OperationVisitor + ?Sized>(
+    visitor: &mut V,
+    op: &Operation,
+    f: &FunctionDescriptor,
+) {
+    match op.kind() {
+        OperationKind::Push(Value::Function(Callable {
+            kind: CallableKind::Function(inner),
+            ..
+        })) => visitor.visit_function(inner),
+        _ => {
+            for block in op.blocks() {
+                for op in block {
+                    visitor.visit_operation(op, f);
+                }
+            }
+        }
+    }
+}
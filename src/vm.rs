@@ -0,0 +1,679 @@
+use crate::{
+    callable::{Callable, CallableKind, FunctionDescriptor},
+    execute::{enter_function, pack_rest, resolve, ExecuteError, ExecuteOptions},
+    machine_state::{MachineState, Resumable},
+    operation::{Operation, OperationKind},
+    pop_as,
+    scope::Scope,
+    span::Span,
+    Value,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+enum BlockKind {
+    TopLevel,
+    Function,
+    If,
+    While,
+    /// A `repeat` body with `left` more iterations to run after the current one
+    Repeat {
+        left: i64,
+    },
+    /// A list literal collecting everything pushed above `depth`
+    List {
+        depth: usize,
+    },
+    /// A `try` body, remembering what to restore if it fails
+    Try {
+        scope_depth: usize,
+        stack_len: usize,
+    },
+    Catch,
+}
+
+/// A block of code that is being run
+#[derive(Debug, Clone)]
+struct Block {
+    kind: BlockKind,
+    /// How to find the block from the top of its function: the index of each enclosing operation and which of its blocks
+    path: Vec<(usize, usize)>,
+    next: usize,
+    /// The operation that opened the block, blamed for errors at its end
+    span: Span,
+}
+
+/// A call that is being run
+#[derive(Debug, Clone)]
+struct Frame {
+    code: Arc<FunctionDescriptor>,
+    /// `None` for the top-level program
+    function: Option<Callable>,
+    blocks: Vec<Block>,
+}
+
+impl Frame {
+    fn new(code: Arc<FunctionDescriptor>, function: Option<Callable>, kind: BlockKind) -> Self {
+        Self {
+            code,
+            function,
+            blocks: vec![Block {
+                kind,
+                path: vec![],
+                next: 0,
+                span: Span::default(),
+            }],
+        }
+    }
+}
+
+/// The rest of a program from the point `call/cc` was called at
+#[derive(Debug)]
+pub(crate) struct Continuation {
+    frames: Vec<Frame>,
+    machine: Resumable,
+}
+
+/// What a builtin asks the [`Vm`] that called it to do once it returns
+#[derive(Debug)]
+pub(crate) enum Request {
+    /// Call the function with the continuation of the builtin's call
+    CallWithContinuation(Callable),
+    /// Carry on from the continuation, with the value on the stack
+    Resume(Arc<Continuation>, Value),
+}
+
+/// Where [`Vm::run`] pauses, right before the operation runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The operation at this index of the top-level program, as numbered by `disassemble`
+    Operation(usize),
+    /// The first operation of a source line, whenever execution reaches it from another line
+    Line(usize),
+}
+
+/// Why [`Vm::run`] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Finished,
+    Breakpoint(Breakpoint),
+    /// The program ran `yield`. What it yielded is on top of the stack
+    Yielded,
+}
+
+/// Runs a program one operation at a time, for debuggers and visualizers.
+/// Builtins run as a single step. Like [`crate::execute::execute_in`], a function called in tail position
+/// replaces the frame of its caller, and errors leaving a function carry the trace of calls they happened in
+#[derive(Debug)]
+pub struct Vm {
+    state: MachineState,
+    frames: Vec<Frame>,
+    breakpoints: Vec<Breakpoint>,
+    /// The line of the operation that ran last, so line breakpoints only fire on entering a line
+    last_line: Option<usize>,
+}
+
+impl Vm {
+    pub fn new(program: FunctionDescriptor, args: Vec<Value>) -> Self {
+        Self::with_options(program, args, &ExecuteOptions::default())
+    }
+
+    pub fn with_options(
+        program: FunctionDescriptor,
+        args: Vec<Value>,
+        options: &ExecuteOptions,
+    ) -> Self {
+        let mut state = MachineState::default();
+        options.apply(&mut state);
+        state.push_scope(Scope::global(pack_rest(&program, args), options.builtins()));
+        Self {
+            state,
+            frames: vec![Frame::new(program.into(), None, BlockKind::TopLevel)],
+            breakpoints: vec![],
+            last_line: None,
+        }
+    }
+
+    /// Call `f` on `state`, which needs a global scope, taking its arguments from the stack
+    pub(crate) fn calling(state: MachineState, f: Callable) -> Result<Self, ExecuteError> {
+        let mut vm = Self {
+            state,
+            frames: vec![],
+            breakpoints: vec![],
+            last_line: None,
+        };
+        vm.call(f)?;
+        Ok(vm)
+    }
+
+    pub fn state(&self) -> &MachineState {
+        &self.state
+    }
+
+    pub(crate) fn state_mut(&mut self) -> &mut MachineState {
+        &mut self.state
+    }
+
+    pub fn into_state(self) -> MachineState {
+        self.state
+    }
+
+    /// The visible scopes, innermost first
+    pub fn scopes(&self) -> impl Iterator<Item = &Scope> {
+        self.state.scopes().rev()
+    }
+
+    /// The number of calls in progress, including the top-level program
+    pub fn call_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The operation the next [`Self::step`] will run, if it isn't the end of a block
+    pub fn next_operation(&self) -> Option<&Operation> {
+        let frame = self.frames.last()?;
+        let block = frame.blocks.last()?;
+        block_code(&frame.code.operations, &block.path).get(block.next)
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Returns whether the breakpoint was set
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| *b != breakpoint);
+        self.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Run exactly one operation, returning it, or `None` once the program has finished.
+    /// Leaving blocks and functions happens as part of the step that follows them. Stepping doesn't stop at `yield`
+    pub fn step(&mut self) -> Result<Option<Operation>, ExecuteError> {
+        let interner = self.state.interner().clone();
+        interner.enter(|| self.step_interned())
+    }
+
+    /// [`Self::step`], with strings already going to the machine's table
+    fn step_interned(&mut self) -> Result<Option<Operation>, ExecuteError> {
+        self.state.take_yield();
+        self.settle()?;
+        let Some(frame) = self.frames.last_mut() else {
+            return Ok(None);
+        };
+        let block = frame.blocks.last_mut().expect("Frames have a body");
+        let code = Arc::clone(&frame.code);
+        let op = &block_code(&code.operations, &block.path)[block.next];
+        block.next += 1;
+        let index = block.next - 1;
+        self.last_line = Some(op.span.line);
+
+        let result = self
+            .state
+            .begin_operation(op)
+            .and_then(|()| self.state.check_stack_size())
+            .and_then(|()| self.execute(op, index, &code));
+        if let Err(e) = result {
+            self.catch(e.at(op.span))?;
+        }
+        Ok(Some(op.clone()))
+    }
+
+    /// Step until the program finishes, yields, or is about to run an operation with a breakpoint.
+    /// Running again while paused returns right away, use [`Self::continue_run`] to get past it
+    pub fn run(&mut self) -> Result<Stop, ExecuteError> {
+        loop {
+            self.settle()?;
+            if let Some(breakpoint) = self.breakpoint_hit() {
+                return Ok(Stop::Breakpoint(breakpoint));
+            }
+            if let Some(stop) = self.step_to_stop()? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    /// Run the operation paused at, then carry on like [`Self::run`]
+    pub fn continue_run(&mut self) -> Result<Stop, ExecuteError> {
+        if let Some(stop) = self.step_to_stop()? {
+            return Ok(stop);
+        }
+        self.run()
+    }
+
+    /// Step once, returning why to stop if the program finished or yielded
+    fn step_to_stop(&mut self) -> Result<Option<Stop>, ExecuteError> {
+        if self.step()?.is_none() {
+            return Ok(Some(Stop::Finished));
+        }
+        Ok(self.state.take_yield().then_some(Stop::Yielded))
+    }
+
+    /// Leave every block that has run to its end, so the next operation is at hand
+    fn settle(&mut self) -> Result<(), ExecuteError> {
+        while let Some(frame) = self.frames.last() {
+            let block = frame.blocks.last().expect("Frames have a body");
+            if block.next < block_code(&frame.code.operations, &block.path).len() {
+                break;
+            }
+            let span = block.span;
+            if let Err(e) = self.end_block() {
+                self.catch(e.at(span))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The breakpoint set on the next operation, if any
+    fn breakpoint_hit(&self) -> Option<Breakpoint> {
+        let op = self.next_operation()?;
+        let block = self.frames.last()?.blocks.last()?;
+        let top_level = self.frames.len() == 1 && block.path.is_empty();
+        self.breakpoints
+            .iter()
+            .copied()
+            .find(|breakpoint| match *breakpoint {
+                Breakpoint::Operation(index) => top_level && index == block.next,
+                Breakpoint::Line(line) => op.span.line == line && self.last_line != Some(line),
+            })
+    }
+
+    fn execute(
+        &mut self,
+        op: &Operation,
+        index: usize,
+        code: &FunctionDescriptor,
+    ) -> Result<(), ExecuteError> {
+        use OperationKind as O;
+
+        let state = &mut self.state;
+        match &op.kind {
+            O::Push(v) => state.push(v.clone()),
+            O::PushConst(index) => state.push(code.constants[*index].clone()),
+            O::PushId(id) => match resolve(state, id)? {
+                Value::Function(f) => self.call(f)?,
+                v => state.push(v),
+            },
+            O::PushRaw(id) => state.push(resolve(state, id)?),
+            O::PushArg(index) => state.push(state.get_arg(*index)?.clone()),
+            O::Call(_, f) => self.call(f.clone())?,
+            O::If(..) => {
+                let body = if pop_as!(state, Bool) { 0 } else { 1 };
+                state.push_scope(Scope::conditional());
+                self.open_block(BlockKind::If, index, body, op.span);
+            }
+            O::Case(branches, _) => {
+                let subject = state.pop()?;
+                let body = branches
+                    .iter()
+                    .position(|(label, _)| *label == subject)
+                    .unwrap_or(branches.len());
+                state.push_scope(Scope::conditional());
+                self.open_block(BlockKind::If, index, body, op.span);
+            }
+            O::While(_) => {
+                state.push_scope(Scope::conditional());
+                if pop_as!(state, Bool) {
+                    self.open_block(BlockKind::While, index, 0, op.span);
+                } else {
+                    state.pop_scope();
+                }
+            }
+            O::Repeat(_) => {
+                let count = pop_as!(state, Int);
+                if count > 0 {
+                    state.push_scope(Scope::conditional());
+                    self.open_block(BlockKind::Repeat { left: count - 1 }, index, 0, op.span);
+                }
+            }
+            O::List(_) => {
+                let depth = state.stack_len();
+                state.push_scope(Scope::conditional());
+                self.open_block(BlockKind::List { depth }, index, 0, op.span);
+            }
+            O::Try(..) => {
+                let (scope_depth, stack_len) = (state.scope_depth(), state.stack_len());
+                state.push_scope(Scope::conditional());
+                let kind = BlockKind::Try {
+                    scope_depth,
+                    stack_len,
+                };
+                self.open_block(kind, index, 0, op.span);
+            }
+            O::Recurse => {
+                let frame = self.frames.last().expect("Is running");
+                let function = frame
+                    .function
+                    .clone()
+                    .ok_or(ExecuteError::RecurseOutsideFunction)?;
+                self.call(function)?
+            }
+            O::Return => self.return_from_frame(),
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, callable: Callable) -> Result<(), ExecuteError> {
+        match &callable.kind {
+            CallableKind::Builtin(f) => {
+                self.state.record_builtin(f);
+                self.state
+                    .run_yieldable(|state| callable.call_builtin(state, f))?;
+                match self.state.take_request() {
+                    Some(Request::CallWithContinuation(f)) => {
+                        let continuation = self.continuation();
+                        self.state.push_new(continuation);
+                        self.call(f)
+                    }
+                    Some(Request::Resume(continuation, value)) => {
+                        self.frames.clone_from(&continuation.frames);
+                        self.state.restore_resumable(&continuation.machine);
+                        self.state.push(value);
+                        Ok(())
+                    }
+                    None => Ok(()),
+                }
+            }
+            CallableKind::Function(f) => {
+                let f = Arc::clone(f);
+                let tail = self.in_tail_position();
+                if tail {
+                    // The caller's call site is kept for the callee, as nothing of the caller is left to return to
+                    self.close_frame();
+                }
+                if let Err(e) = enter_function(&mut self.state, &callable, &f) {
+                    if tail {
+                        self.state.leave_call();
+                    }
+                    return Err(e);
+                }
+                if !tail {
+                    self.state.enter_call();
+                }
+                self.state.enter_body(f.span);
+                self.frames
+                    .push(Frame::new(f, Some(callable), BlockKind::Function));
+                Ok(())
+            }
+        }
+    }
+
+    /// The rest of the program after the operation being stepped, as a function that carries on there with the
+    /// value it is called with. Calling it more than once runs the rest again each time
+    fn continuation(&self) -> Value {
+        let continuation = Arc::new(Continuation {
+            frames: self.frames.clone(),
+            machine: self.state.save_resumable(),
+        });
+        Value::builtin(move |state| {
+            let value = state.pop()?;
+            state.request(Request::Resume(Arc::clone(&continuation), value))
+        })
+    }
+
+    /// Whether nothing of the current function runs after the operation being stepped, so a call it makes
+    /// can take over its frame. The same calls are tail calls as in compiled code
+    fn in_tail_position(&self) -> bool {
+        let Some(frame) = self.frames.last() else {
+            return false;
+        };
+        if frame.function.is_none()
+            || frame
+                .blocks
+                .iter()
+                .any(|block| matches!(block.kind, BlockKind::Try { .. }))
+        {
+            return false;
+        }
+        let block = frame.blocks.last().expect("Frames have a body");
+        let code = block_code(&frame.code.operations, &block.path);
+        if code
+            .get(block.next)
+            .is_some_and(|op| matches!(op.kind, OperationKind::Return))
+        {
+            return true;
+        }
+        frame.blocks.iter().all(|block| {
+            matches!(
+                block.kind,
+                BlockKind::Function | BlockKind::If | BlockKind::Catch
+            ) && block.next == block_code(&frame.code.operations, &block.path).len()
+        })
+    }
+
+    fn open_block(&mut self, kind: BlockKind, op: usize, body: usize, span: Span) {
+        let frame = self.frames.last_mut().expect("Is running");
+        let mut path = frame
+            .blocks
+            .last()
+            .expect("Frames have a body")
+            .path
+            .clone();
+        path.push((op, body));
+        frame.blocks.push(Block {
+            kind,
+            path,
+            next: 0,
+            span,
+        });
+    }
+
+    fn end_block(&mut self) -> Result<(), ExecuteError> {
+        let frame = self.frames.last_mut().expect("Is running");
+        let block = frame.blocks.last_mut().expect("Frames have a body");
+        match block.kind {
+            BlockKind::TopLevel | BlockKind::Function => self.return_from_frame(),
+            BlockKind::If | BlockKind::Try { .. } | BlockKind::Catch => {
+                frame.blocks.pop();
+                self.state.pop_scope();
+            }
+            BlockKind::While => {
+                let state = &mut self.state;
+                if pop_as!(state, Bool) {
+                    block.next = 0;
+                } else {
+                    frame.blocks.pop();
+                    self.state.pop_scope();
+                }
+            }
+            BlockKind::Repeat { left } => {
+                if left > 0 {
+                    block.kind = BlockKind::Repeat { left: left - 1 };
+                    block.next = 0;
+                } else {
+                    frame.blocks.pop();
+                    self.state.pop_scope();
+                }
+            }
+            BlockKind::List { depth } => {
+                frame.blocks.pop();
+                self.state.pop_scope();
+                let items = self.state.split_stack(depth);
+                self.state.push_new(items.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Continue in the handler of the innermost `try` around the failure, or give up if there is none
+    fn catch(&mut self, error: ExecuteError) -> Result<(), ExecuteError> {
+        if !error.is_catchable() {
+            return Err(self.traced(error));
+        }
+        let Some((frame_index, block_index)) =
+            self.frames.iter().enumerate().rev().find_map(|(i, frame)| {
+                let j = frame
+                    .blocks
+                    .iter()
+                    .rposition(|block| matches!(block.kind, BlockKind::Try { .. }))?;
+                Some((i, j))
+            })
+        else {
+            return Err(self.traced(error));
+        };
+
+        while self.frames.len() > frame_index + 1 {
+            self.return_from_frame();
+        }
+        let frame = &mut self.frames[frame_index];
+        let Block {
+            kind:
+                BlockKind::Try {
+                    scope_depth,
+                    stack_len,
+                },
+            mut path,
+            span,
+            ..
+        } = frame.blocks.drain(block_index..).next().expect("Was found")
+        else {
+            unreachable!("Only try blocks are looked for");
+        };
+
+        self.state.restore(scope_depth, stack_len);
+        self.state
+            .push_new(Value::Error(error.kind().to_string().into()));
+        self.state.push_scope(Scope::conditional());
+        let (op, _) = path.pop().expect("Try blocks are nested in an operation");
+        path.push((op, 1));
+        frame.blocks.push(Block {
+            kind: BlockKind::Catch,
+            path,
+            next: 0,
+            span,
+        });
+        Ok(())
+    }
+
+    /// Attach the calls in progress to an error leaving the program, if it happened inside a function
+    fn traced(&self, error: ExecuteError) -> ExecuteError {
+        if self.frames.iter().any(|frame| frame.function.is_some()) {
+            error.traced(&self.state)
+        } else {
+            error
+        }
+    }
+
+    /// Leave the current call, closing every scope it opened
+    fn return_from_frame(&mut self) {
+        if self.close_frame() {
+            self.state.leave_call();
+        }
+    }
+
+    /// Drop the current frame and its scopes, returning whether it was a function call
+    fn close_frame(&mut self) -> bool {
+        let frame = self.frames.pop().expect("Is running");
+        let called = frame.function.is_some();
+        if called {
+            self.state.leave_body();
+        }
+        for block in frame.blocks {
+            if !matches!(block.kind, BlockKind::TopLevel) {
+                self.state.pop_scope();
+            }
+        }
+        called
+    }
+}
+
+fn block_code<'a>(operations: &'a [Operation], path: &[(usize, usize)]) -> &'a [Operation] {
+    path.iter().fold(operations, |operations, &(op, body)| {
+        operations[op]
+            .blocks()
+            .nth(body)
+            .expect("Paths point at blocks")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn vm(source: &str) -> Vm {
+        Vm::new(parse(source.chars()).unwrap(), vec![])
+    }
+
+    #[test]
+    fn errors_in_functions_carry_their_call_sites() {
+        let error = vm("fn 'boom' throw end 'inner' :=\nfn inner 1 end 'outer' :=\nouter")
+            .run()
+            .unwrap_err();
+        let sites: Vec<_> = error
+            .trace()
+            .unwrap()
+            .call_sites()
+            .iter()
+            .map(|s| s.line)
+            .collect();
+        assert_eq!(sites, [2, 3]);
+        assert!(vm("'boom' throw").run().unwrap_err().trace().is_none());
+    }
+
+    #[test]
+    fn tail_calls_reuse_the_frame() {
+        let mut vm = vm("fn 0 $0 = if 'done' else 1 $0 - count end end 'count' := 3 count");
+        let mut depth = 0;
+        while vm.step().unwrap().is_some() {
+            depth = depth.max(vm.call_depth());
+        }
+        assert_eq!(depth, 2);
+        assert!(vm.state().stack().eq([&Value::from("done")]));
+    }
+
+    #[test]
+    fn calls_before_the_end_of_a_try_keep_their_frame() {
+        let mut vm = vm("fn 0 $0 = if 'done' else try 1 $0 - count end end end 'count' := 3 count");
+        let mut depth = 0;
+        while vm.step().unwrap().is_some() {
+            depth = depth.max(vm.call_depth());
+        }
+        assert_eq!(depth, 5);
+    }
+
+    #[test]
+    fn line_breakpoints_stop_at_the_start_of_the_line() {
+        let mut vm = vm("fn\n$0 2 *\nend 'double' :=\n1 double\n3 double");
+        vm.add_breakpoint(Breakpoint::Line(2));
+        let mut stop = vm.run().unwrap();
+        for _ in 0..2 {
+            assert_eq!(stop, Stop::Breakpoint(Breakpoint::Line(2)));
+            let span = vm.next_operation().unwrap().span;
+            assert_eq!((span.line, span.column), (2, 1));
+            assert_eq!(vm.call_depth(), 2);
+            stop = vm.continue_run().unwrap();
+        }
+        assert_eq!(stop, Stop::Finished);
+        assert!(vm.state().stack().eq([&Value::Int(2), &Value::Int(6)]));
+    }
+
+    #[test]
+    fn operation_breakpoints_stop_before_the_operation() {
+        let mut vm = vm("1 2 + 4 *");
+        vm.add_breakpoint(Breakpoint::Operation(3));
+        assert_eq!(
+            vm.run().unwrap(),
+            Stop::Breakpoint(Breakpoint::Operation(3))
+        );
+        assert!(vm.state().stack().eq([&Value::Int(3)]));
+        assert_eq!(
+            vm.run().unwrap(),
+            Stop::Breakpoint(Breakpoint::Operation(3))
+        );
+        assert!(vm.remove_breakpoint(Breakpoint::Operation(3)));
+        assert!(vm.breakpoints().is_empty());
+        assert_eq!(vm.continue_run().unwrap(), Stop::Finished);
+        assert!(vm.state().stack().eq([&Value::Int(12)]));
+    }
+}